@@ -1,8 +1,15 @@
 #![allow(dead_code)]
 
+use checkpoint;
 use murmur;
+use std::cmp;
+use std::f64;
+use std::io;
+use std::sync::atomic;
 
 const ARRAY_SIZE: usize = 8192;
+/// Largest LargeBloomFilter accepted from a checkpoint (32 GB).
+const MAX_BITS: u64 = 1<<38;
 
 /// Data structure that elements can be added to, such that when shown a new element,
 /// it can decide if it has been added. Here, added means its hash stays saved.
@@ -72,15 +79,79 @@ impl BloomFilter {
     }
 }
 
-const LARGE_ARRAY_SIZE: usize = 536870912;
+/// Reads the dimensions (number of bits and hash functions) and seed of a
+/// bloom filter written by `write_to`, checking that they are sensible.
+fn read_dimensions<R: io::Read>(r: &mut R) -> io::Result<(u64, u32, u32)> {
+    let seed=checkpoint::read_u32(r)?;
+    let num_hashes=checkpoint::read_u32(r)?;
+    let num_bits=checkpoint::read_u64(r)?;
+    if num_bits==0 || num_bits%64!=0 || num_bits>MAX_BITS || num_hashes==0 || num_hashes>64 {
+        return Err(checkpoint::invalid_data("bloom filter dimensions are invalid"));
+    }
+    Ok((num_bits, num_hashes, seed))
+}
+
+/// Returns the number of bits and hash functions a bloom filter needs to hold
+/// `expected_items` elements with a false positive probability of `fpp`.
+///
+/// # Arguments
+///
+/// * `expected_items` - Number of elements expected to be added.
+/// * `fpp` - Desired false positive probability, between 0 and 1.
+pub fn optimal_dimensions(expected_items: usize, fpp: f64) -> (u64, u32) {
+    let n=cmp::max(expected_items, 1) as f64;
+    let p=fpp.max(1e-12).min(0.5);
+    let ln2=f64::consts::LN_2;
+
+    let num_bits=(-n*p.ln()/(ln2*ln2)).ceil().max(64.0) as u64;
+    let num_hashes=((num_bits as f64/n)*ln2).round().max(1.0).min(64.0) as u32;
+    (num_bits, num_hashes)
+}
+
+/// Estimates the number of distinct elements added to a bloom filter from the
+/// number of bits set (Swamidass & Baldi).
+fn estimate_len(bits_set: u64, num_bits: u64, num_hashes: u32) -> usize {
+    if bits_set>=num_bits {
+        return usize::max_value();
+    }
+    let m=num_bits as f64;
+    (-(m/num_hashes as f64)*(1.0-bits_set as f64/m).ln()).round() as usize
+}
+
+/// Estimates the probability of a bloom filter returning a false positive from
+/// the fraction of bits set.
+fn estimate_fpp(bits_set: u64, num_bits: u64, num_hashes: u32) -> f64 {
+    (bits_set as f64/num_bits as f64).powi(num_hashes as i32)
+}
+
+impl BloomFilter {
+    /// Returns the estimated number of distinct elements added so far.
+    pub fn approx_len(&self) -> usize {
+        estimate_len(self.bits_set(), (ARRAY_SIZE*8) as u64, 2*self.seeds.len() as u32)
+    }
+
+    /// Returns the estimated probability that `contains` wrongly returns true
+    /// for an element that was never added.
+    pub fn estimated_fpp(&self) -> f64 {
+        estimate_fpp(self.bits_set(), (ARRAY_SIZE*8) as u64, 2*self.seeds.len() as u32)
+    }
+
+    fn bits_set(&self) -> u64 {
+        self.bitarray.iter().map(|b| b.count_ones() as u64).sum()
+    }
+}
 
 /// Data structure that elements can be added to, such that when shown a new element,
 /// it can decide if it has been added. Here, added means its hash stays saved.
-/// This is a larger version of BloomFilter, which occupies 512 MB of memory.
+/// This is a larger version of BloomFilter, sized to hold a given number of
+/// elements with a given false positive probability. All probes of an element
+/// are derived from a single 128-bit murmur hash by double hashing.
 #[derive(Clone)]
 pub struct LargeBloomFilter{
-    bitarray: Box<[u8;LARGE_ARRAY_SIZE]>, // use box of array as soon as box syntax is here (lazy adming is lazy, again)
-    seeds: Vec<u32>,
+    bitarray: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+    seed: u32,
 }
 
 impl LargeBloomFilter {
@@ -88,27 +159,52 @@ impl LargeBloomFilter {
     ///
     /// # Arguments
     ///
-    /// * `seeds` - seeds to be used for hashing. More seeds yield less collissions.
-    pub fn new(seeds: Vec<u32>) -> LargeBloomFilter {
+    /// * `expected_items` - Number of elements expected to be added.
+    /// * `fpp` - Desired false positive probability once `expected_items` elements were added.
+    /// * `seed` - Seed to be used for hashing.
+    pub fn new(expected_items: usize, fpp: f64, seed: u32) -> LargeBloomFilter {
+        let (num_bits, num_hashes)=optimal_dimensions(expected_items, fpp);
+        LargeBloomFilter::with_dimensions(num_bits, num_hashes, seed)
+    }
+
+    /// Creates and returns a new LargeBloomFilter structure with `num_bits`
+    /// bits (rounded up to a multiple of 64) and `num_hashes` probes per element.
+    ///
+    /// # Arguments
+    ///
+    /// * `num_bits` - Number of bits of the filter.
+    /// * `num_hashes` - Number of bits set for each element.
+    /// * `seed` - Seed to be used for hashing.
+    pub fn with_dimensions(num_bits: u64, num_hashes: u32, seed: u32) -> LargeBloomFilter {
+        let num_words=cmp::max((num_bits+63)/64, 1);
         LargeBloomFilter{
-            bitarray: box [0u8;LARGE_ARRAY_SIZE],
-            seeds: seeds,
+            bitarray: vec![0u64;num_words as usize],
+            num_bits: num_words*64,
+            num_hashes: cmp::max(num_hashes, 1),
+            seed: seed,
         }
     }
 
+    /// Returns the number of bits of the filter.
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    /// Returns the number of bits set for each element.
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
     /// Adds an element to the LargeBloomFilter structure.
     ///
     /// # Arguments
     ///
     /// * `item` - byte slice representation of element to be hashed.
     pub fn add(&mut self, item: &[u8]){
-        let fourwise_hashes=self.seeds.iter().map(|&x| murmur::murmur_hash3_x64_128(item, x)).map(|(x1,x2)| (x1>>32, x1&0xffffffff, x2>>32, x2&0xffffffff));
-
-        for (s1,s2,s3,s4) in fourwise_hashes{
-            self.bitarray[(s1>>3) as usize]|=1<<((s1&7) as u8);
-            self.bitarray[(s2>>3) as usize]|=1<<((s2&7) as u8);
-            self.bitarray[(s3>>3) as usize]|=1<<((s3&7) as u8);
-            self.bitarray[(s4>>3) as usize]|=1<<((s4&7) as u8);
+        let (h1, h2)=murmur::murmur_hash3_x64_128(item, self.seed);
+        for i in 0..self.num_hashes as u64{
+            let position=h1.wrapping_add(i.wrapping_mul(h2))%self.num_bits;
+            self.bitarray[(position>>6) as usize]|=1<<(position&63);
         }
     }
 
@@ -118,14 +214,11 @@ impl LargeBloomFilter {
     ///
     /// * `item` - byte slice representation of element to be hashed.
     pub fn contains(&self, item: &[u8]) -> bool{
-        let mut fourwise_hashes=self.seeds.iter().map(|&x| murmur::murmur_hash3_x64_128(item, x)).map(|(x1,x2)| (x1>>32, x1&0xffffffff, x2>>32, x2&0xffffffff));
-
-        fourwise_hashes.all(|(s1,s2,s3,s4)| 
-            self.bitarray[(s1>>3) as usize]&((1<<(s1&7)) as u8)!=0 &&
-            self.bitarray[(s2>>3) as usize]&((1<<(s2&7)) as u8)!=0 &&
-            self.bitarray[(s3>>3) as usize]&((1<<(s3&7)) as u8)!=0 &&
-            self.bitarray[(s4>>3) as usize]&((1<<(s4&7)) as u8)!=0
-            )
+        let (h1, h2)=murmur::murmur_hash3_x64_128(item, self.seed);
+        (0..self.num_hashes as u64).all(|i| {
+            let position=h1.wrapping_add(i.wrapping_mul(h2))%self.num_bits;
+            self.bitarray[(position>>6) as usize]&(1<<(position&63))!=0
+        })
     }
 
     /// Checks whether an element was added to the LargeBloomFilter structure. If it
@@ -135,25 +228,227 @@ impl LargeBloomFilter {
     ///
     /// * `item` - byte slice representation of element to be hashed.
     pub fn contains_add(&mut self, item: &[u8]) -> bool{
-        let fourwise_hashes=self.seeds.iter().map(|&x| murmur::murmur_hash3_x64_128(item, x)).map(|(x1,x2)| (x1>>32, x1&0xffffffff, x2>>32, x2&0xffffffff));
+        let (h1, h2)=murmur::murmur_hash3_x64_128(item, self.seed);
         let mut contains=true;
 
-        for (s1,s2,s3,s4) in fourwise_hashes{
-            if  (self.bitarray[(s1>>3) as usize]&((1<<(s1&7)) as u8)) |
-                (self.bitarray[(s2>>3) as usize]&((1<<(s2&7)) as u8)) |
-                (self.bitarray[(s3>>3) as usize]&((1<<(s3&7)) as u8)) |
-                (self.bitarray[(s4>>3) as usize]&((1<<(s4&7)) as u8))==0{
+        for i in 0..self.num_hashes as u64{
+            let position=h1.wrapping_add(i.wrapping_mul(h2))%self.num_bits;
+            let word=&mut self.bitarray[(position>>6) as usize];
+            let mask=1<<(position&63);
+            if *word&mask==0{
+                contains=false;
+                *word|=mask;
+            }
+        }
+
+        contains
+    }
+
+    /// Returns the estimated number of distinct elements added so far, from the
+    /// number of bits set.
+    pub fn approx_len(&self) -> usize {
+        estimate_len(self.bits_set(), self.num_bits, self.num_hashes)
+    }
+
+    /// Returns the estimated probability that `contains` wrongly returns true
+    /// for an element that was never added, from the number of bits set.
+    pub fn estimated_fpp(&self) -> f64 {
+        estimate_fpp(self.bits_set(), self.num_bits, self.num_hashes)
+    }
+
+    /// Returns the fraction of bits set.
+    pub fn fill_ratio(&self) -> f64 {
+        self.bits_set() as f64/self.num_bits as f64
+    }
+
+    fn bits_set(&self) -> u64 {
+        self.bitarray.iter().map(|w| w.count_ones() as u64).sum()
+    }
+
+    /// Writes the LargeBloomFilter structure (dimensions, seed and bit array) to `w`.
+    ///
+    /// # Arguments
+    ///
+    /// * `w` - Writer to write to.
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()>{
+        checkpoint::write_u32(w, self.seed)?;
+        checkpoint::write_u32(w, self.num_hashes)?;
+        checkpoint::write_u64(w, self.num_bits)?;
+        for &word in self.bitarray.iter(){
+            checkpoint::write_u64(w, word)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a LargeBloomFilter structure written by `write_to` from `r`.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - Reader to read from.
+    pub fn read_from<R: io::Read>(r: &mut R) -> io::Result<LargeBloomFilter>{
+        let (num_bits, num_hashes, seed)=read_dimensions(r)?;
+        let mut bloom_filter=LargeBloomFilter::with_dimensions(num_bits, num_hashes, seed);
+        for word in bloom_filter.bitarray.iter_mut(){
+            *word=checkpoint::read_u64(r)?;
+        }
+        Ok(bloom_filter)
+    }
+}
+
+/// Version of LargeBloomFilter that can be shared between threads without a
+/// lock. Its bits are kept in atomic words that are only ever or-ed into, so
+/// an element that was added is never reported missing, no matter how calls
+/// from different threads interleave.
+pub struct ConcurrentBloomFilter{
+    bitarray: Vec<atomic::AtomicU64>,
+    num_bits: u64,
+    num_hashes: u32,
+    seed: u32,
+}
+
+impl ConcurrentBloomFilter {
+    /// Creates and returns a new ConcurrentBloomFilter structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `expected_items` - Number of elements expected to be added.
+    /// * `fpp` - Desired false positive probability once `expected_items` elements were added.
+    /// * `seed` - Seed to be used for hashing.
+    pub fn new(expected_items: usize, fpp: f64, seed: u32) -> ConcurrentBloomFilter {
+        let (num_bits, num_hashes)=optimal_dimensions(expected_items, fpp);
+        ConcurrentBloomFilter::with_dimensions(num_bits, num_hashes, seed)
+    }
+
+    /// Creates and returns a new ConcurrentBloomFilter structure with `num_bits`
+    /// bits (rounded up to a multiple of 64) and `num_hashes` probes per element.
+    ///
+    /// # Arguments
+    ///
+    /// * `num_bits` - Number of bits of the filter.
+    /// * `num_hashes` - Number of bits set for each element.
+    /// * `seed` - Seed to be used for hashing.
+    pub fn with_dimensions(num_bits: u64, num_hashes: u32, seed: u32) -> ConcurrentBloomFilter {
+        let num_words=cmp::max((num_bits+63)/64, 1);
+        ConcurrentBloomFilter{
+            bitarray: (0..num_words).map(|_| atomic::AtomicU64::new(0)).collect(),
+            num_bits: num_words*64,
+            num_hashes: cmp::max(num_hashes, 1),
+            seed: seed,
+        }
+    }
+
+    /// Returns the number of bits of the filter.
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
 
+    /// Returns the number of bits set for each element.
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
+    /// Adds an element to the ConcurrentBloomFilter structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `item` - byte slice representation of element to be hashed.
+    pub fn add(&self, item: &[u8]){
+        let (h1, h2)=murmur::murmur_hash3_x64_128(item, self.seed);
+        for i in 0..self.num_hashes as u64{
+            let position=h1.wrapping_add(i.wrapping_mul(h2))%self.num_bits;
+            self.bitarray[(position>>6) as usize].fetch_or(1<<(position&63), atomic::Ordering::Relaxed);
+        }
+    }
+
+    /// Checks whether an element was added to the ConcurrentBloomFilter structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `item` - byte slice representation of element to be hashed.
+    pub fn contains(&self, item: &[u8]) -> bool{
+        let (h1, h2)=murmur::murmur_hash3_x64_128(item, self.seed);
+        (0..self.num_hashes as u64).all(|i| {
+            let position=h1.wrapping_add(i.wrapping_mul(h2))%self.num_bits;
+            self.bitarray[(position>>6) as usize].load(atomic::Ordering::Relaxed)&(1<<(position&63))!=0
+        })
+    }
+
+    /// Checks whether an element was added to the ConcurrentBloomFilter structure. If it
+    /// was not, it gets added. The result of the check gets returned. Each bit
+    /// is checked and set in one atomic operation, so the element is reported
+    /// as contained only if all its bits were set before this call. Two threads
+    /// adding the same new element at the same time may both be told it was not
+    /// contained, but every call after either of them returned is told it is.
+    ///
+    /// # Arguments
+    ///
+    /// * `item` - byte slice representation of element to be hashed.
+    pub fn contains_add(&self, item: &[u8]) -> bool{
+        let (h1, h2)=murmur::murmur_hash3_x64_128(item, self.seed);
+        let mut contains=true;
+
+        for i in 0..self.num_hashes as u64{
+            let position=h1.wrapping_add(i.wrapping_mul(h2))%self.num_bits;
+            let mask=1<<(position&63);
+            if self.bitarray[(position>>6) as usize].fetch_or(mask, atomic::Ordering::Relaxed)&mask==0{
                 contains=false;
-                self.bitarray[(s1>>3) as usize]|=1<<((s1&7) as u8);
-                self.bitarray[(s2>>3) as usize]|=1<<((s2&7) as u8);
-                self.bitarray[(s3>>3) as usize]|=1<<((s3&7) as u8);
-                self.bitarray[(s4>>3) as usize]|=1<<((s4&7) as u8);
             }
         }
 
         contains
     }
+
+    /// Returns the estimated number of distinct elements added so far, from the
+    /// number of bits set.
+    pub fn approx_len(&self) -> usize {
+        estimate_len(self.bits_set(), self.num_bits, self.num_hashes)
+    }
+
+    /// Returns the estimated probability that `contains` wrongly returns true
+    /// for an element that was never added, from the number of bits set.
+    pub fn estimated_fpp(&self) -> f64 {
+        estimate_fpp(self.bits_set(), self.num_bits, self.num_hashes)
+    }
+
+    /// Returns the fraction of bits set.
+    pub fn fill_ratio(&self) -> f64 {
+        self.bits_set() as f64/self.num_bits as f64
+    }
+
+    fn bits_set(&self) -> u64 {
+        self.bitarray.iter().map(|w| w.load(atomic::Ordering::Relaxed).count_ones() as u64).sum()
+    }
+
+    /// Writes the ConcurrentBloomFilter structure (dimensions, seed and bit array)
+    /// to `w`, in the same format as LargeBloomFilter. Elements added while
+    /// writing may or may not be included.
+    ///
+    /// # Arguments
+    ///
+    /// * `w` - Writer to write to.
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()>{
+        checkpoint::write_u32(w, self.seed)?;
+        checkpoint::write_u32(w, self.num_hashes)?;
+        checkpoint::write_u64(w, self.num_bits)?;
+        for word in self.bitarray.iter(){
+            checkpoint::write_u64(w, word.load(atomic::Ordering::Relaxed))?;
+        }
+        Ok(())
+    }
+
+    /// Reads a ConcurrentBloomFilter structure written by `write_to` from `r`.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - Reader to read from.
+    pub fn read_from<R: io::Read>(r: &mut R) -> io::Result<ConcurrentBloomFilter>{
+        let (num_bits, num_hashes, seed)=read_dimensions(r)?;
+        let bloom_filter=ConcurrentBloomFilter::with_dimensions(num_bits, num_hashes, seed);
+        for word in bloom_filter.bitarray.iter(){
+            word.store(checkpoint::read_u64(r)?, atomic::Ordering::Relaxed);
+        }
+        Ok(bloom_filter)
+    }
 }
 
 
@@ -161,6 +456,8 @@ impl LargeBloomFilter {
 mod tests {
     use super::*;
     use std::str;
+    use std::sync;
+    use std::thread;
 
     #[test]
     fn test_bloom_filter() {
@@ -194,7 +491,7 @@ mod tests {
 
     #[test]
     fn test_large_bloom_filter() {
-        let mut bloom_filter=LargeBloomFilter::new(vec![0xa4a759a4, 0xe5f20661]);
+        let mut bloom_filter=LargeBloomFilter::new(1000, 0.001, 0xa4a759a4);
 
         let to_add=[b"1" as &[u8], b"hello" as &[u8], b"NaN" as &[u8], b"" as &[u8], b"ohle" as &[u8], b"some rather long string just because. And just in case, lets make it even longer :D" as &[u8]];
         let not_to_add=[b"0" as &[u8], b"bye" as &[u8], b"ehlo" as &[u8], b"lello" as &[u8], b"_" as &[u8], b"another rather long string just because. And just in case, lets make it even longer :D" as &[u8]];
@@ -221,4 +518,79 @@ mod tests {
             assert!(bloom_filter.contains_add(s)==true, format!("LargeBloomFilter says a word that was added is not contained (contains_add). ({:?})", str::from_utf8(s)));
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_large_bloom_filter_sizing() {
+        let (num_bits, num_hashes)=optimal_dimensions(10000, 0.01);
+        assert_eq!(num_hashes, 7);
+        assert!(num_bits>95000 && num_bits<96000, "unexpected number of bits {}", num_bits);
+
+        let mut bloom_filter=LargeBloomFilter::new(10000, 0.01, 0xe5f20661);
+        assert_eq!(bloom_filter.num_bits()%64, 0);
+        assert_eq!(bloom_filter.approx_len(), 0);
+        assert_eq!(bloom_filter.estimated_fpp(), 0.0);
+
+        for i in 0..10000{
+            bloom_filter.add(format!("http://a.com/{}", i).as_bytes());
+        }
+
+        let approx_len=bloom_filter.approx_len();
+        assert!(approx_len>9500 && approx_len<10500, "unexpected approximate length {}", approx_len);
+        let estimated_fpp=bloom_filter.estimated_fpp();
+        assert!(estimated_fpp>0.005 && estimated_fpp<0.02, "unexpected estimated false positive probability {}", estimated_fpp);
+
+        let false_positives=(0..10000).filter(|i| bloom_filter.contains(format!("http://b.com/{}", i).as_bytes())).count();
+        assert!(false_positives<200, "too many false positives {}", false_positives);
+    }
+
+    #[test]
+    fn test_concurrent_bloom_filter() {
+        let bloom_filter=sync::Arc::new(ConcurrentBloomFilter::new(100000, 0.001, 0x85684b56));
+        let num_threads=8;
+        let items_per_thread=10000;
+
+        // Every thread adds its own items and some shared by all, checking right after each call that they are contained.
+        let handles=(0..num_threads).map(|t| {
+            let bloom_filter=bloom_filter.clone();
+            thread::spawn(move || {
+                let mut newly_added=0;
+                for i in 0..items_per_thread{
+                    let own=format!("http://{}.com/{}", t, i);
+                    let shared=format!("http://shared.com/{}", i);
+                    if t%2==0{
+                        bloom_filter.add(own.as_bytes());
+                    } else if !bloom_filter.contains_add(own.as_bytes()){
+                        newly_added+=1;
+                    }
+                    let _=bloom_filter.contains_add(shared.as_bytes());
+                    assert!(bloom_filter.contains(own.as_bytes()), "ConcurrentBloomFilter says a word that was added is not contained. ({})", own);
+                    assert!(bloom_filter.contains(shared.as_bytes()), "ConcurrentBloomFilter says a word that was added is not contained. ({})", shared);
+                }
+                newly_added
+            })
+        }).collect::<Vec<_>>();
+
+        let newly_added: usize=handles.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert!(newly_added>(num_threads/2)*items_per_thread*99/100);
+
+        // Once all threads are done, every item is contained and reported as such by contains_add.
+        for t in 0..num_threads{
+            for i in 0..items_per_thread{
+                assert!(bloom_filter.contains_add(format!("http://{}.com/{}", t, i).as_bytes()));
+                assert!(bloom_filter.contains_add(format!("http://shared.com/{}", i).as_bytes()));
+            }
+        }
+
+        let approx_len=bloom_filter.approx_len();
+        let len=(num_threads+1)*items_per_thread;
+        assert!(approx_len>len*95/100 && approx_len<len*105/100, "unexpected approximate length {}", approx_len);
+
+        // It can be written and read back.
+        let mut buf=Vec::new();
+        bloom_filter.write_to(&mut buf).unwrap();
+        let read_bloom_filter=ConcurrentBloomFilter::read_from(&mut io::Cursor::new(buf)).unwrap();
+        assert_eq!(read_bloom_filter.num_bits(), bloom_filter.num_bits());
+        assert!(read_bloom_filter.contains(b"http://0.com/0"));
+        assert!(!read_bloom_filter.contains(b"http://0.com/-1"));
+    }
+}
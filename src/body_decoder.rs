@@ -0,0 +1,325 @@
+#![allow(dead_code)]
+
+use brotli_decompressor;
+use flate2;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+/// Value of the Accept-Encoding header sent with every request.
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+/// Size of the buffer the brotli decompressor writes through.
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// Content encoding of a response body, as given by its Content-Encoding header.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ContentEncoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+}
+
+impl ContentEncoding {
+    /// Returns the content encoding named by the value of a Content-Encoding
+    /// header (`None` if there is no such header), or an error if it is not
+    /// supported (including several stacked encodings).
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - Value of the Content-Encoding header.
+    pub fn from_header(value: Option<&str>) -> Result<ContentEncoding, String> {
+        let value=match value {
+            Some(value) => value.trim().to_lowercase(),
+            None => return Ok(ContentEncoding::Identity),
+        };
+
+        match value.as_str() {
+            "" | "identity" => Ok(ContentEncoding::Identity),
+            "gzip" | "x-gzip" => Ok(ContentEncoding::Gzip),
+            "deflate" => Ok(ContentEncoding::Deflate),
+            "br" => Ok(ContentEncoding::Brotli),
+            _ => Err(format!("unsupported content encoding {:?}", value)),
+        }
+    }
+}
+
+/// Error given when a decoded body exceeds its size limit.
+#[derive(Debug)]
+pub struct BodyTooLarge;
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "decoded body exceeds its size limit")
+    }
+}
+
+impl error::Error for BodyTooLarge {
+    fn description(&self) -> &str {
+        "decoded body exceeds its size limit"
+    }
+}
+
+/// Returns whether `e` was caused by a body exceeding its size limit.
+pub fn is_too_large(e: &io::Error) -> bool {
+    e.get_ref().map_or(false, |inner| inner.is::<BodyTooLarge>())
+}
+
+/// Buffer that holds decoded bytes up to a limit. Past it, it either fails or
+/// (if truncating) silently drops the rest.
+struct LimitedBuffer {
+    buf: Vec<u8>,
+    limit: usize,
+    truncate: bool,
+    exceeded: bool,
+}
+
+impl Write for LimitedBuffer {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let room=self.limit-self.buf.len();
+        if data.len()>room {
+            self.exceeded=true;
+            if !self.truncate {
+                return Err(io::Error::new(io::ErrorKind::Other, BodyTooLarge));
+            }
+            self.buf.extend_from_slice(&data[..room]);
+            return Ok(data.len());
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Decoder {
+    Identity(LimitedBuffer),
+    Gzip(flate2::write::GzDecoder<LimitedBuffer>),
+    /// Deflate bodies whose first bytes have not been seen yet, which tell
+    /// whether they are zlib wrapped (as they should be) or raw deflate.
+    DeflateUndecided(LimitedBuffer, Vec<u8>),
+    Zlib(flate2::write::ZlibDecoder<LimitedBuffer>),
+    RawDeflate(flate2::write::DeflateDecoder<LimitedBuffer>),
+    Brotli(brotli_decompressor::DecompressorWriter<LimitedBuffer>),
+}
+
+/// Decodes a response body chunk by chunk as it arrives, so that at no point
+/// more than `limit` decoded bytes are held and decompression bombs are cut off.
+pub struct BodyDecoder {
+    decoder: Decoder,
+}
+
+impl BodyDecoder {
+    /// Creates and returns a new BodyDecoder structure that fails once the
+    /// decoded body exceeds `limit` bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `encoding` - Content encoding of the body.
+    /// * `limit` - Maximum size of the decoded body.
+    pub fn new(encoding: ContentEncoding, limit: usize) -> BodyDecoder {
+        BodyDecoder::with_buffer(encoding, LimitedBuffer{buf: Vec::new(), limit: limit, truncate: false, exceeded: false})
+    }
+
+    /// Creates and returns a new BodyDecoder structure that keeps the first
+    /// `limit` decoded bytes and ignores the rest of the body.
+    ///
+    /// # Arguments
+    ///
+    /// * `encoding` - Content encoding of the body.
+    /// * `limit` - Maximum size of the decoded body.
+    pub fn truncating(encoding: ContentEncoding, limit: usize) -> BodyDecoder {
+        BodyDecoder::with_buffer(encoding, LimitedBuffer{buf: Vec::new(), limit: limit, truncate: true, exceeded: false})
+    }
+
+    fn with_buffer(encoding: ContentEncoding, buffer: LimitedBuffer) -> BodyDecoder {
+        let decoder=match encoding {
+            ContentEncoding::Identity => Decoder::Identity(buffer),
+            ContentEncoding::Gzip => Decoder::Gzip(flate2::write::GzDecoder::new(buffer)),
+            ContentEncoding::Deflate => Decoder::DeflateUndecided(buffer, Vec::new()),
+            ContentEncoding::Brotli => Decoder::Brotli(brotli_decompressor::DecompressorWriter::new(buffer, BROTLI_BUFFER_SIZE)),
+        };
+        BodyDecoder{decoder: decoder}
+    }
+
+    fn buffer(&self) -> &LimitedBuffer {
+        match self.decoder {
+            Decoder::Identity(ref buffer) => buffer,
+            Decoder::Gzip(ref decoder) => decoder.get_ref(),
+            Decoder::DeflateUndecided(ref buffer, _) => buffer,
+            Decoder::Zlib(ref decoder) => decoder.get_ref(),
+            Decoder::RawDeflate(ref decoder) => decoder.get_ref(),
+            Decoder::Brotli(ref decoder) => decoder.get_ref(),
+        }
+    }
+
+    fn buffer_mut(&mut self) -> &mut LimitedBuffer {
+        match self.decoder {
+            Decoder::Identity(ref mut buffer) => buffer,
+            Decoder::Gzip(ref mut decoder) => decoder.get_mut(),
+            Decoder::DeflateUndecided(ref mut buffer, _) => buffer,
+            Decoder::Zlib(ref mut decoder) => decoder.get_mut(),
+            Decoder::RawDeflate(ref mut decoder) => decoder.get_mut(),
+            Decoder::Brotli(ref mut decoder) => decoder.get_mut(),
+        }
+    }
+
+    /// Returns the number of decoded bytes so far.
+    pub fn len(&self) -> usize {
+        self.buffer().buf.len()
+    }
+
+    /// Returns whether the decoded body exceeded the limit.
+    pub fn exceeded(&self) -> bool {
+        self.buffer().exceeded
+    }
+
+    /// Decodes the next chunk of the body. Fails if the chunk cannot be decoded
+    /// or (unless truncating) if the decoded body grows beyond the limit, in
+    /// which case the error satisfies `is_too_large`.
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - Next bytes of the body, as they were received.
+    pub fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        // Once a truncating decoder is full, the rest of the body is not even decompressed.
+        if self.exceeded() {
+            return Ok(());
+        }
+
+        let decided=match self.decoder {
+            Decoder::DeflateUndecided(ref mut buffer, ref mut start) => {
+                start.extend_from_slice(chunk);
+                if start.len()<2 {
+                    return Ok(());
+                }
+                // A zlib header is a compression method of 8 and a multiple of 31.
+                let is_zlib=start[0]&0x0f==8 && ((start[0] as u16)<<8 | start[1] as u16)%31==0;
+                let buffer=::std::mem::replace(buffer, LimitedBuffer{buf: Vec::new(), limit: 0, truncate: false, exceeded: false});
+                let decoder=if is_zlib {
+                    Decoder::Zlib(flate2::write::ZlibDecoder::new(buffer))
+                } else {
+                    Decoder::RawDeflate(flate2::write::DeflateDecoder::new(buffer))
+                };
+                Some((decoder, ::std::mem::replace(start, Vec::new())))
+            },
+            _ => None,
+        };
+        if let Some((decoder, start))=decided {
+            self.decoder=decoder;
+            return self.write(&start);
+        }
+
+        let result=match self.decoder {
+            Decoder::Identity(ref mut buffer) => buffer.write_all(chunk),
+            Decoder::Gzip(ref mut decoder) => decoder.write_all(chunk),
+            Decoder::DeflateUndecided(_, _) => Ok(()),
+            Decoder::Zlib(ref mut decoder) => decoder.write_all(chunk),
+            Decoder::RawDeflate(ref mut decoder) => decoder.write_all(chunk),
+            Decoder::Brotli(ref mut decoder) => decoder.write_all(chunk),
+        };
+
+        // Decoders may wrap the error of the buffer into their own.
+        match result {
+            Err(_) if self.exceeded() && !self.buffer().truncate => Err(io::Error::new(io::ErrorKind::Other, BodyTooLarge)),
+            Err(_) if self.exceeded() => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Finishes decoding the body and returns it. Fails if the body ended in
+    /// the middle of a compressed stream (unless it was truncated).
+    pub fn finish(mut self) -> io::Result<Vec<u8>> {
+        if !self.exceeded() {
+            let result=match self.decoder {
+                Decoder::Identity(_) => Ok(()),
+                Decoder::Gzip(ref mut decoder) => decoder.try_finish(),
+                Decoder::DeflateUndecided(_, ref start) if start.is_empty() => Ok(()),
+                Decoder::DeflateUndecided(_, _) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "deflate body ended early")),
+                Decoder::Zlib(ref mut decoder) => decoder.try_finish(),
+                Decoder::RawDeflate(ref mut decoder) => decoder.try_finish(),
+                Decoder::Brotli(ref mut decoder) => decoder.close(),
+            };
+            match result {
+                Err(_) if self.exceeded() && !self.buffer().truncate => return Err(io::Error::new(io::ErrorKind::Other, BodyTooLarge)),
+                Err(_) if self.exceeded() => {},
+                Err(e) => return Err(e),
+                Ok(_) => {},
+            }
+        }
+
+        Ok(::std::mem::replace(&mut self.buffer_mut().buf, Vec::new()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_in_chunks(encoding: ContentEncoding, body: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+        let mut decoder=BodyDecoder::new(encoding, limit);
+        for chunk in body.chunks(7) {
+            decoder.write(chunk)?;
+        }
+        decoder.finish()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder=flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_content_encodings() {
+        let text=b"<html><body><a href=\"/a\">a</a> <a href=\"/b\">b</a></body></html>".repeat(20);
+
+        assert_eq!(decode_in_chunks(ContentEncoding::Identity, &text, 1<<20).unwrap(), text);
+        assert_eq!(decode_in_chunks(ContentEncoding::Gzip, &gzip(&text), 1<<20).unwrap(), text);
+
+        let mut encoder=flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&text).unwrap();
+        assert_eq!(decode_in_chunks(ContentEncoding::Deflate, &encoder.finish().unwrap(), 1<<20).unwrap(), text);
+
+        let mut encoder=flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&text).unwrap();
+        assert_eq!(decode_in_chunks(ContentEncoding::Deflate, &encoder.finish().unwrap(), 1<<20).unwrap(), text);
+
+        // "hello" compressed with brotli.
+        let brotli=[0x0b, 0x02, 0x80, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x03];
+        assert_eq!(decode_in_chunks(ContentEncoding::Brotli, &brotli, 1<<20).unwrap(), b"hello".to_vec());
+
+        // Truncated and corrupted bodies are rejected.
+        let compressed=gzip(&text);
+        assert!(decode_in_chunks(ContentEncoding::Gzip, &compressed[..compressed.len()/2], 1<<20).is_err());
+        assert!(decode_in_chunks(ContentEncoding::Gzip, &text, 1<<20).is_err());
+
+        assert_eq!(ContentEncoding::from_header(None), Ok(ContentEncoding::Identity));
+        assert_eq!(ContentEncoding::from_header(Some(" GZip ")), Ok(ContentEncoding::Gzip));
+        assert!(ContentEncoding::from_header(Some("gzip, br")).is_err());
+    }
+
+    #[test]
+    fn test_limit() {
+        // A megabyte of zeros compresses to about a kilobyte.
+        let bomb=gzip(&vec![0u8; 1<<20]);
+        assert!(bomb.len()<4096);
+
+        let e=decode_in_chunks(ContentEncoding::Gzip, &bomb, 64*1024).unwrap_err();
+        assert!(is_too_large(&e));
+        assert!(decode_in_chunks(ContentEncoding::Gzip, &bomb, 1<<20).is_ok());
+        assert!(is_too_large(&decode_in_chunks(ContentEncoding::Identity, &[1u8; 100], 99).unwrap_err()));
+
+        let mut decoder=BodyDecoder::truncating(ContentEncoding::Gzip, 1000);
+        for chunk in bomb.chunks(100) {
+            decoder.write(chunk).unwrap();
+        }
+        assert!(decoder.exceeded());
+        assert_eq!(decoder.finish().unwrap(), vec![0u8; 1000]);
+    }
+}
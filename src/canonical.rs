@@ -0,0 +1,230 @@
+#![allow(dead_code)]
+
+use config;
+use url;
+use std::mem;
+use std::sync;
+
+/// Name (or, if `prefix`, start of the names) of the parameters to be stripped from urls.
+#[derive(Debug, PartialEq, Clone)]
+struct ParamPattern {
+    name: String,
+    prefix: bool,
+}
+
+impl ParamPattern {
+    /// Returns the ParamPattern of `pattern`, a trailing `*` of which matches any end of name.
+    fn new(pattern: &str) -> ParamPattern {
+        let pattern=pattern.to_lowercase();
+        if pattern.ends_with('*') {
+            ParamPattern{name: pattern.trim_right_matches('*').to_string(), prefix: true}
+        } else {
+            ParamPattern{name: pattern, prefix: false}
+        }
+    }
+
+    /// Returns whether the parameter named `name` matches the pattern, ignoring case.
+    fn matches(&self, name: &str) -> bool {
+        if self.prefix {
+            name.len()>=self.name.len() && name.is_char_boundary(self.name.len()) && name[..self.name.len()].eq_ignore_ascii_case(self.name.as_str())
+        } else {
+            name.eq_ignore_ascii_case(self.name.as_str())
+        }
+    }
+}
+
+/// Returns whether `byte` may appear in urls without being percent-encoded, so
+/// that encoding it changes nothing but the spelling of the url.
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte==b'-' || byte==b'.' || byte==b'_' || byte==b'~'
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|value| value as u8)
+}
+
+/// Returns `s` with its percent-encoded unreserved characters decoded, and the
+/// hex digits of the other escapes in upper case, e.g. `%7euser%2fx` becomes `~user%2Fx`.
+/// Reserved characters stay encoded, as decoding them could change what the url means.
+pub fn normalize_percent_encoding(s: &str) -> String {
+    let bytes=s.as_bytes();
+    let mut normalized=String::with_capacity(s.len());
+    let mut i=0;
+    while i<bytes.len() {
+        if bytes[i]==b'%' && i+2<bytes.len() {
+            if let (Some(high), Some(low))=(hex_value(bytes[i+1]), hex_value(bytes[i+2])) {
+                let byte=high*16+low;
+                if is_unreserved(byte) {
+                    normalized.push(byte as char);
+                } else {
+                    normalized.push('%');
+                    normalized.push(bytes[i+1].to_ascii_uppercase() as char);
+                    normalized.push(bytes[i+2].to_ascii_uppercase() as char);
+                }
+                i+=3;
+                continue;
+            }
+        }
+        // Urls are serialized as ascii, but any other text is kept as it is.
+        let len=s[i..].chars().next().map_or(1, |c| c.len_utf8());
+        normalized.push_str(&s[i..i+len]);
+        i+=len;
+    }
+    normalized
+}
+
+/// Rewrites urls into a canonical form, so that the spellings of an url all
+/// make the same bytes for the bloom filter and the frontier. Urls are
+/// canonicalized where they enter the crawl (seeds, links found by processors
+/// and redirect targets), and once more as they leave the frontier, so that
+/// urls restored from checkpoints of older versions are too.
+///
+/// Parsing already lower cases schemes and hosts, drops default ports and
+/// resolves dot segments. On top of that, fragments are stripped, percent-encoding
+/// is normalized, parameters matching the configured patterns (e.g. tracking and
+/// session ids) are removed from queries and path segments, empty queries are
+/// dropped and, if configured, query parameters are sorted by name.
+pub struct Canonicalizer {
+    sort_query: bool,
+    strip_params: Vec<ParamPattern>,
+    urls_canonicalized: sync::Arc<sync::atomic::AtomicUsize>,
+}
+
+impl Canonicalizer {
+    /// Creates and returns a new Canonicalizer structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Settings of the crawl (whether to sort queries and which parameters to strip).
+    /// * `start` - Returns the value a counter (given its name) starts from, e.g. as saved by a checkpoint.
+    pub fn new<F: Fn(&str) -> usize>(config: &config::CrawlConfig, start: F) -> Canonicalizer {
+        Canonicalizer{
+            sort_query: config.canonical_sort_query,
+            strip_params: config.canonical_strip_params.iter().filter(|pattern| !pattern.is_empty()).map(|pattern| ParamPattern::new(pattern)).collect(),
+            urls_canonicalized: sync::Arc::new(sync::atomic::AtomicUsize::new(start("urls_canonicalized"))),
+        }
+    }
+
+    /// Returns whether the parameter `param` (e.g. `utm_source=x`) is to be stripped.
+    fn is_stripped(&self, param: &str) -> bool {
+        let name=param.split('=').next().unwrap_or("");
+        self.strip_params.iter().any(|pattern| pattern.matches(name))
+    }
+
+    /// Rewrites `url` into its canonical form.
+    pub fn canonicalize_url(&self, url: &mut url::Url) {
+        url.set_fragment(None);
+
+        // Normalize every segment of the path, dropping its stripped parameters (e.g. `;jsessionid=...`).
+        if !url.cannot_be_a_base() {
+            let path=url.path().split('/').map(|segment| {
+                let mut params=segment.split(';');
+                let mut normalized=normalize_percent_encoding(params.next().unwrap_or(""));
+                for param in params.filter(|param| !self.is_stripped(param)) {
+                    normalized.push(';');
+                    normalized.push_str(normalize_percent_encoding(param).as_str());
+                }
+                normalized
+            }).collect::<Vec<_>>().join("/");
+            if path!=url.path() {
+                url.set_path(path.as_str());
+            }
+        }
+
+        // Normalize the query, dropping its empty and stripped parameters and the query itself if none are left.
+        let query=url.query().map(|query| {
+            let mut params:Vec<String>=query.split('&')
+            .filter(|param| !param.is_empty() && !self.is_stripped(param))
+            .map(normalize_percent_encoding)
+            .collect();
+            if self.sort_query {
+                // The sort is stable, so the values of a repeated parameter keep their order.
+                params.sort_by(|a, b| a.split('=').next().cmp(&b.split('=').next()));
+            }
+            params.join("&")
+        });
+        match query {
+            Some(ref query) if query.is_empty() => url.set_query(None),
+            Some(ref query) if Some(query.as_str())!=url.query() => url.set_query(Some(query.as_str())),
+            _ => {},
+        }
+    }
+
+    /// Returns the canonical form of `url`, counting the urls it differs from.
+    /// Urls that cannot be parsed are returned as they are, for the scope to reject.
+    pub fn canonicalize(&self, url: String) -> String {
+        let mut parsed=match url::Url::parse(url.as_str()) {
+            Ok(parsed) => parsed,
+            Err(_) => return url,
+        };
+        self.canonicalize_url(&mut parsed);
+        if parsed.as_str()==url.as_str() {
+            return url;
+        }
+        self.urls_canonicalized.fetch_add(1, sync::atomic::Ordering::Relaxed);
+        parsed.into_string()
+    }
+
+    /// Canonicalizes every url of `urls` in place.
+    pub fn canonicalize_all(&self, urls: &mut [String]) {
+        for url in urls.iter_mut() {
+            let original=mem::replace(url, String::new());
+            *url=self.canonicalize(original);
+        }
+    }
+
+    /// Returns the counter of the urls rewritten along with its name, to be saved in checkpoints.
+    pub fn named(&self) -> Vec<(&'static str, sync::Arc<sync::atomic::AtomicUsize>)> {
+        vec![("urls_canonicalized", self.urls_canonicalized.clone())]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_canonicalizer(sort_query: bool) -> Canonicalizer {
+        let mut config=config::CrawlConfig::default();
+        config.canonical_sort_query=sort_query;
+        Canonicalizer::new(&config, |_| 0)
+    }
+
+    #[test]
+    fn test_normalize_percent_encoding() {
+        assert_eq!(normalize_percent_encoding("/%7euser/%2fx%3F"), "/~user/%2Fx%3F");
+        assert_eq!(normalize_percent_encoding("%41%2d%5F%7E"), "A-_~");
+        assert_eq!(normalize_percent_encoding("100%"), "100%");
+        assert_eq!(normalize_percent_encoding("%zz%4"), "%zz%4");
+        assert_eq!(normalize_percent_encoding("é%e9"), "é%E9");
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let canonicalizer=new_canonicalizer(false);
+        for url in &["http://A.com/x", "http://a.com:80/x", "http://a.com/x#frag", "http://a.com/./x?", "HTTP://a.COM/y/../x?&"] {
+            assert_eq!(canonicalizer.canonicalize(url.to_string()), "http://a.com/x");
+        }
+        assert_eq!(canonicalizer.named()[0].1.load(sync::atomic::Ordering::Relaxed), 5);
+        assert_eq!(canonicalizer.canonicalize("http://a.com/x".to_string()), "http://a.com/x");
+        assert_eq!(canonicalizer.named()[0].1.load(sync::atomic::Ordering::Relaxed), 5);
+
+        assert_eq!(canonicalizer.canonicalize("https://a.com:443/%7ebob/a%2fb".to_string()), "https://a.com/~bob/a%2Fb");
+        assert_eq!(canonicalizer.canonicalize("http://a.com:8080/%2e/x".to_string()), "http://a.com:8080/x");
+        assert_eq!(canonicalizer.canonicalize("http://a.com/x?utm_source=m&b=2&UTM_Medium=e&a=1&fbclid=z".to_string()), "http://a.com/x?b=2&a=1");
+        assert_eq!(canonicalizer.canonicalize("http://a.com/x;jsessionid=AB12;v=1?PHPSESSID=c".to_string()), "http://a.com/x;v=1");
+        assert_eq!(canonicalizer.canonicalize("mailto:a@a.com".to_string()), "mailto:a@a.com");
+        assert_eq!(canonicalizer.canonicalize("not an url".to_string()), "not an url");
+
+        let canonicalizer=new_canonicalizer(true);
+        assert_eq!(canonicalizer.canonicalize("http://a.com/x?b=2&a=3&a=1&c".to_string()), "http://a.com/x?a=3&a=1&b=2&c");
+    }
+
+    #[test]
+    fn test_param_pattern() {
+        assert!(ParamPattern::new("utm_*").matches("UTM_source"));
+        assert!(!ParamPattern::new("utm_*").matches("utm"));
+        assert!(ParamPattern::new("SID").matches("sid"));
+        assert!(!ParamPattern::new("sid").matches("side"));
+    }
+}
@@ -0,0 +1,166 @@
+#![allow(dead_code)]
+
+use encoding_rs;
+use html_links;
+use std::borrow::Cow;
+use std::cmp;
+use std::str;
+
+/// Number of bytes at the beginning of an html document searched for a `<meta charset>`.
+const HTML_PRESCAN_SIZE: usize = 1024;
+
+/// Kind of document to be decoded, which determines where it may declare its encoding.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum DocumentKind {
+    Html,
+    Css,
+}
+
+/// Text of a document transcoded to utf8 (borrowed from the document if it was utf8 already).
+pub struct Decoded<'a> {
+    pub text: Cow<'a, str>,
+    /// Encoding the document was decoded from.
+    pub encoding: &'static encoding_rs::Encoding,
+    /// Whether the document contained byte sequences invalid in `encoding` (replaced by U+FFFD).
+    pub had_errors: bool,
+    /// Whether the document was not valid utf8 but could be decoded without errors.
+    pub rescued: bool,
+}
+
+/// Decodes `body` into utf8 text. Its encoding is taken from the charset of the
+/// Content-Type header if there is one, else from its byte order mark, else from
+/// its declaration within the document (`<meta charset>` for html, `@charset`
+/// for css). Undeclared documents are taken to be utf8 if they are valid utf8
+/// and windows-1252 otherwise.
+///
+/// # Arguments
+///
+/// * `body` - Bytes of the document.
+/// * `header_charset` - Charset parameter of the Content-Type header, if any.
+/// * `kind` - Whether the document is html or css.
+pub fn decode<'a>(body: &'a [u8], header_charset: Option<&str>, kind: DocumentKind) -> Decoded<'a> {
+    let declared=header_charset.and_then(|label| encoding_rs::Encoding::for_label(label.trim().as_bytes())).map(|encoding| {
+        // A byte order mark of the same encoding is still removed.
+        let bom_len=match encoding_rs::Encoding::for_bom(body) {
+            Some((bom_encoding, bom_len)) if bom_encoding==encoding => bom_len,
+            _ => 0,
+        };
+        (encoding, bom_len)
+    })
+    .or_else(|| encoding_rs::Encoding::for_bom(body))
+    .or_else(|| in_document_encoding(body, kind).map(|encoding| (encoding, 0)));
+
+    let is_utf8=str::from_utf8(body).is_ok();
+    let (encoding, bom_len)=match declared {
+        Some(declared) => declared,
+        None if is_utf8 => (encoding_rs::UTF_8, 0),
+        None => (encoding_rs::WINDOWS_1252, 0),
+    };
+
+    // Valid utf8 (and ascii compatible text that is ascii only) is borrowed, without copying.
+    let (mut text, had_errors)=encoding.decode_without_bom_handling(&body[bom_len..]);
+    if kind==DocumentKind::Css && encoding!=encoding_rs::UTF_8 {
+        text=Cow::Owned(replace_css_charset_rule(text.into_owned()));
+    }
+
+    Decoded{text: text, encoding: encoding, had_errors: had_errors, rescued: !is_utf8 && !had_errors}
+}
+
+/// Returns the encoding a document declares within itself, if any.
+fn in_document_encoding(body: &[u8], kind: DocumentKind) -> Option<&'static encoding_rs::Encoding> {
+    let label=match kind {
+        DocumentKind::Html => {
+            let head=String::from_utf8_lossy(&body[..cmp::min(body.len(), HTML_PRESCAN_SIZE)]);
+            html_links::meta_charset(&head)
+        },
+        DocumentKind::Css => css_charset_rule(body).map(|(label, _)| String::from_utf8_lossy(label).into_owned()),
+    };
+
+    label.and_then(|label| encoding_rs::Encoding::for_label(label.trim().as_bytes())).map(|encoding| {
+        // A document cannot declare itself utf16 from within, as it could not have been read that far.
+        if encoding==encoding_rs::UTF_16LE || encoding==encoding_rs::UTF_16BE {
+            encoding_rs::UTF_8
+        } else {
+            encoding
+        }
+    })
+}
+
+/// Returns the label and the length of a `@charset "label";` rule at the very beginning of css code.
+fn css_charset_rule(body: &[u8]) -> Option<(&[u8], usize)> {
+    const PREFIX: &[u8] = b"@charset \"";
+    if !body.starts_with(PREFIX) {
+        return None;
+    }
+    let rest=&body[PREFIX.len()..cmp::min(body.len(), PREFIX.len()+64)];
+    rest.windows(2).position(|w| w==b"\";").map(|i| (&rest[..i], PREFIX.len()+i+2))
+}
+
+/// Replaces the `@charset` rule of transcoded css code, which no longer holds, with a utf8 one.
+fn replace_css_charset_rule(text: String) -> String {
+    let rule_len=css_charset_rule(text.as_bytes()).map(|(_, rule_len)| rule_len);
+    match rule_len {
+        Some(rule_len) => format!("@charset \"utf-8\";{}", &text[rule_len..]),
+        None => text,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_precedence() {
+        // "café" in windows-1252.
+        let latin=b"<p>caf\xe9</p>";
+        let decoded=decode(latin, None, DocumentKind::Html);
+        assert_eq!(decoded.text, "<p>café</p>");
+        assert_eq!(decoded.encoding, encoding_rs::WINDOWS_1252);
+        assert!(decoded.rescued);
+
+        // The header wins over the byte order mark and the in-document declaration.
+        let body=b"\xef\xbb\xbf<meta charset=\"shift_jis\"><p>caf\xc3\xa9</p>";
+        let decoded=decode(body, Some("ISO-8859-1"), DocumentKind::Html);
+        assert_eq!(decoded.encoding, encoding_rs::WINDOWS_1252);
+        assert!(decoded.text.ends_with("cafÃ©</p>"));
+
+        // The byte order mark wins over the in-document declaration, and is removed.
+        let decoded=decode(body, None, DocumentKind::Html);
+        assert_eq!(decoded.encoding, encoding_rs::UTF_8);
+        assert_eq!(decoded.text, "<meta charset=\"shift_jis\"><p>café</p>");
+        assert!(!decoded.rescued);
+
+        // Unknown header charsets are ignored.
+        let decoded=decode(b"<p>caf\xc3\xa9</p>", Some("no-such-charset"), DocumentKind::Html);
+        assert_eq!(decoded.text, "<p>café</p>");
+        match decoded.text {
+            Cow::Borrowed(_) => {},
+            Cow::Owned(_) => panic!("valid utf8 was copied"),
+        }
+    }
+
+    #[test]
+    fn test_in_document() {
+        // "日本" in shift_jis.
+        let body=b"<html><head><META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=Shift_JIS\"></head><p>\x93\xfa\x96\x7b</p>";
+        let decoded=decode(body, None, DocumentKind::Html);
+        assert_eq!(decoded.encoding, encoding_rs::SHIFT_JIS);
+        assert!(decoded.text.ends_with("<p>日本</p>"));
+        assert!(decoded.rescued);
+
+        let decoded=decode(b"<meta charset=utf-16><p>caf\xc3\xa9</p>", None, DocumentKind::Html);
+        assert_eq!(decoded.encoding, encoding_rs::UTF_8);
+
+        let body=b"@charset \"iso-8859-2\";\na::after { content: \"\xb1\"; }";
+        let decoded=decode(body, None, DocumentKind::Css);
+        assert_eq!(decoded.encoding, encoding_rs::ISO_8859_2);
+        assert_eq!(decoded.text, "@charset \"utf-8\";\na::after { content: \"ą\"; }");
+        assert!(decoded.rescued);
+
+        // Bytes invalid in the declared encoding are replaced, and the page does not count as rescued.
+        let decoded=decode(b"<p>\xff\xfe\xfd</p>", Some("utf-8"), DocumentKind::Html);
+        assert!(decoded.had_errors);
+        assert!(!decoded.rescued);
+    }
+}
@@ -0,0 +1,274 @@
+use bloom_filter;
+use frontier;
+use outstanding;
+use retry;
+use url_meta;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path;
+use std::sync;
+
+const MAGIC: &[u8;8] = b"RCRAWLCK";
+const VERSION: u32 = 7;
+const END_MARKER: &[u8;4] = b"END!";
+
+/// Writes `n` in little endian.
+pub fn write_u32<W: Write>(w: &mut W, n: u32) -> io::Result<()> {
+    w.write_all(&[n as u8, (n>>8) as u8, (n>>16) as u8, (n>>24) as u8])
+}
+
+/// Writes `n` in little endian.
+pub fn write_u64<W: Write>(w: &mut W, n: u64) -> io::Result<()> {
+    write_u32(w, n as u32)?;
+    write_u32(w, (n>>32) as u32)
+}
+
+/// Writes the length of `bytes` followed by `bytes`.
+pub fn write_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    write_u64(w, bytes.len() as u64)?;
+    w.write_all(bytes)
+}
+
+/// Reads a little endian u32.
+pub fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf=[0u8;4];
+    r.read_exact(&mut buf)?;
+    Ok((buf[0] as u32) | (buf[1] as u32)<<8 | (buf[2] as u32)<<16 | (buf[3] as u32)<<24)
+}
+
+/// Reads a little endian u64.
+pub fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let low=read_u32(r)? as u64;
+    let high=read_u32(r)? as u64;
+    Ok(low | high<<32)
+}
+
+/// Reads bytes written by `write_bytes`, refusing lengths above `max_len`.
+pub fn read_bytes<R: Read>(r: &mut R, max_len: usize) -> io::Result<Vec<u8>> {
+    let len=read_u64(r)?;
+    if len>max_len as u64 {
+        return Err(invalid_data("length out of bounds"));
+    }
+    let mut bytes=vec![0u8;len as usize];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Returns an `InvalidData` io error with message `msg`.
+pub fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// State of a crawl as loaded from a checkpoint file.
+pub struct Checkpoint {
+    pub bloom_filter: bloom_filter::ConcurrentBloomFilter,
+    pub frontier: frontier::Frontier,
+    /// Urls that were neither in the frontier nor fetched yet, along with whether they were sent (and thus are in the bloom filter).
+    pub pending: Vec<(String, url_meta::UrlMeta, bool)>,
+    /// Bloom filter keeping track of the css code already saved.
+    pub css_bloom_filter: bloom_filter::ConcurrentBloomFilter,
+    pub counters: Vec<(String, u64)>,
+    /// Seeds the crawl was started from.
+    pub seeds: Vec<String>,
+}
+
+impl Checkpoint {
+    /// Returns the value of the counter named `name`, or 0 if it was not saved.
+    pub fn counter(&self, name: &str) -> usize {
+        self.counters.iter().find(|&&(ref n, _)| n==name).map_or(0, |&(_, value)| value as usize)
+    }
+}
+
+/// Writes the state of a crawl to `path`. The file is first written to a
+/// temporary file next to it, which is then renamed, so that a crash while
+/// writing leaves the previous checkpoint intact. The frontier is copied
+/// while its lock is held, before the bloom filter is written, so that every url
+/// sent before the copy is contained within the saved bloom filter. The urls
+/// taken from it since, and those waiting to be retried, are copied after it,
+/// so that urls on their way from one to the other are not lost.
+///
+/// # Arguments
+///
+/// * `path` - Path of the checkpoint file.
+/// * `bloom_filter` - ConcurrentBloomFilter keeping track of already sent urls.
+/// * `css_bloom_filter` - ConcurrentBloomFilter keeping track of the css code already saved.
+/// * `frontier` - Frontier containing the urls to be crawled.
+/// * `outstanding` - Urls taken from the frontier that were not fetched yet.
+/// * `retry_queue` - RetryQueue holding urls until they are due to be tried again.
+/// * `counters` - Named counters to be saved along.
+/// * `seeds` - Seeds the crawl was started from, to be told apart from those of another crawl.
+pub fn write_checkpoint<P: AsRef<path::Path>>(path: P, bloom_filter: &bloom_filter::ConcurrentBloomFilter, css_bloom_filter: &bloom_filter::ConcurrentBloomFilter, frontier: &sync::Mutex<frontier::Frontier>, outstanding: &outstanding::Outstanding, retry_queue: &sync::Mutex<retry::RetryQueue>, counters: &[(&str, usize)], seeds: &[String]) -> io::Result<()> {
+    let path=path.as_ref();
+    let mut temp_path=path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path=path::PathBuf::from(temp_path);
+
+    {
+        let mut w=io::BufWriter::new(fs::File::create(&temp_path)?);
+        w.write_all(MAGIC)?;
+        write_u32(&mut w, VERSION)?;
+
+        // Counters.
+        write_u32(&mut w, counters.len() as u32)?;
+        for &(name, value) in counters.iter() {
+            write_bytes(&mut w, name.as_bytes())?;
+            write_u64(&mut w, value as u64)?;
+        }
+
+        // Seeds.
+        write_u32(&mut w, seeds.len() as u32)?;
+        for seed in seeds.iter() {
+            write_bytes(&mut w, seed.as_bytes())?;
+        }
+
+        // Frontier, copied so that its lock is not held while writing.
+        let mut frontier_bytes=Vec::new();
+        match frontier.lock() {
+            Ok(mut mutex_guard) => mutex_guard.write_to(&mut frontier_bytes)?,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "frontier lock is poisoned")),
+        }
+        w.write_all(&frontier_bytes)?;
+        drop(frontier_bytes);
+
+        // Urls outstanding and waiting to be retried.
+        let mut pending=outstanding.urls();
+        match retry_queue.lock() {
+            Ok(mutex_guard) => pending.extend(mutex_guard.urls()),
+            Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "retry queue lock is poisoned")),
+        }
+        outstanding::write_urls(&mut w, &pending)?;
+
+        // Bloom filters.
+        bloom_filter.write_to(&mut w)?;
+        css_bloom_filter.write_to(&mut w)?;
+
+        w.write_all(END_MARKER)?;
+        let f=w.into_inner().map_err(|e| e.into_error())?;
+        f.sync_all()?;
+    }
+
+    fs::rename(&temp_path, path)
+}
+
+/// Reads the state of a crawl from the checkpoint file at `path`.
+///
+/// # Arguments
+///
+/// * `path` - Path of the checkpoint file.
+/// * `reservoir_size` - Size of the frontier to be restored.
+pub fn read_checkpoint<P: AsRef<path::Path>>(path: P, reservoir_size: usize) -> io::Result<Checkpoint> {
+    let mut r=io::BufReader::new(fs::File::open(path)?);
+
+    let mut magic=[0u8;8];
+    r.read_exact(&mut magic)?;
+    if &magic!=MAGIC {
+        return Err(invalid_data("not a checkpoint file"));
+    }
+    let version=read_u32(&mut r)?;
+    if version!=VERSION {
+        return Err(invalid_data(format!("unsupported checkpoint version {}", version).as_str()));
+    }
+
+    let counters_len=read_u32(&mut r)?;
+    let mut counters=Vec::new();
+    for _ in 0..counters_len {
+        let name=String::from_utf8(read_bytes(&mut r, 1024)?).map_err(|_| invalid_data("counter name is not utf8"))?;
+        let value=read_u64(&mut r)?;
+        counters.push((name, value));
+    }
+
+    let seeds_len=read_u32(&mut r)?;
+    let mut seeds=Vec::new();
+    for _ in 0..seeds_len {
+        seeds.push(String::from_utf8(read_bytes(&mut r, url_meta::MAX_URL_LEN)?).map_err(|_| invalid_data("seed is not utf8"))?);
+    }
+
+    let frontier=frontier::Frontier::read_from(&mut r, reservoir_size)?;
+    let pending=outstanding::read_urls(&mut r)?;
+    let bloom_filter=bloom_filter::ConcurrentBloomFilter::read_from(&mut r)?;
+    let css_bloom_filter=bloom_filter::ConcurrentBloomFilter::read_from(&mut r)?;
+
+    let mut end_marker=[0u8;4];
+    r.read_exact(&mut end_marker)?;
+    if &end_marker!=END_MARKER {
+        return Err(invalid_data("checkpoint file is corrupted"));
+    }
+
+    Ok(Checkpoint{bloom_filter: bloom_filter, frontier: frontier, pending: pending, css_bloom_filter: css_bloom_filter, counters: counters, seeds: seeds})
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand;
+    use test_util;
+    use std::time;
+
+    #[test]
+    fn test_integers() {
+        let mut buf=Vec::new();
+        write_u32(&mut buf, 0xdeadbeef).unwrap();
+        write_u64(&mut buf, 0x0123456789abcdef).unwrap();
+        write_bytes(&mut buf, b"hello").unwrap();
+
+        let mut r=io::Cursor::new(buf);
+        assert_eq!(read_u32(&mut r).unwrap(), 0xdeadbeef);
+        assert_eq!(read_u64(&mut r).unwrap(), 0x0123456789abcdef);
+        assert_eq!(read_bytes(&mut r, 5).unwrap(), b"hello".to_vec());
+        assert!(read_u32(&mut r).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let dir=test_util::TempDir::new("checkpoint");
+        let path=dir.join("checkpoint.bin");
+
+        let bloom_filter=bloom_filter::ConcurrentBloomFilter::new(1000, 0.001, 0xa4a759a4);
+        bloom_filter.add(b"http://a.com/");
+        let css_bloom_filter=bloom_filter::ConcurrentBloomFilter::new(100, 0.001, 0x41be6a18);
+        css_bloom_filter.add(b"a{color:red}");
+        let frontier=sync::Mutex::new(frontier::Frontier::new(frontier::FrontierKind::Reservoir, vec!["http://b.com/".into(), "http://c.com/".into()], 16, rand::StdRng::new().unwrap()));
+        let meta=url_meta::UrlMeta::seed().child(sync::Arc::from("http://b.com/"), 1500);
+        frontier.lock().unwrap().add_urls(vec![("http://b.com/x".into(), meta.clone()), ("http://b.com/y".into(), meta.clone())]);
+
+        let outstanding=outstanding::Outstanding::new();
+        outstanding.taken("http://d.com/".to_string(), &meta, true);
+        let retry_queue=sync::Mutex::new(retry::RetryQueue::new(retry::RetryPolicy{max_attempts: 3, base_delay: time::Duration::from_secs(1), max_delay: time::Duration::from_secs(1)}, 16));
+        retry_queue.lock().unwrap().postpone("http://e.com/".to_string(), meta.clone(), time::Instant::now(), false);
+
+        write_checkpoint(&path, &bloom_filter, &css_bloom_filter, &frontier, &outstanding, &retry_queue, &[("urls_gotten", 42), ("css_written", 7)], &["http://b.com/".to_string()]).unwrap();
+        let mut checkpoint=read_checkpoint(&path, 16).unwrap();
+
+        // Urls outstanding or waiting to be retried are saved apart, with whether they were sent.
+        assert_eq!(checkpoint.pending, vec![("http://d.com/".to_string(), meta.clone(), true), ("http://e.com/".to_string(), meta.clone(), false)]);
+
+        assert_eq!(checkpoint.counter("urls_gotten"), 42);
+        assert_eq!(checkpoint.counter("css_written"), 7);
+        assert_eq!(checkpoint.counter("no_such_counter"), 0);
+        assert_eq!(checkpoint.seeds, vec!["http://b.com/".to_string()]);
+        assert!(checkpoint.bloom_filter.contains(b"http://a.com/"));
+        assert!(!checkpoint.bloom_filter.contains(b"http://b.com/"));
+        assert!(checkpoint.css_bloom_filter.contains(b"a{color:red}") && !checkpoint.css_bloom_filter.contains(b"http://a.com/"));
+
+        // The reservoir continues with the same random sequence as the one that was saved.
+        let mut frontier=frontier.into_inner().unwrap();
+        assert_eq!(checkpoint.frontier.len(), 4);
+        let mut parents=Vec::new();
+        for _ in 0..4 {
+            let (url, meta)=checkpoint.frontier.get_url().unwrap();
+            assert_eq!(Some((url.clone(), meta.clone())), frontier.get_url());
+            parents.extend(meta.parent);
+        }
+
+        // Urls found in the same page share their parent again.
+        assert_eq!(parents.len(), 2);
+        assert!(sync::Arc::ptr_eq(&parents[0], &parents[1]));
+
+        // Truncated files are rejected.
+        let len=fs::metadata(&path).unwrap().len();
+        fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(len-1).unwrap();
+        assert!(read_checkpoint(&path, 16).is_err());
+    }
+}
@@ -0,0 +1,530 @@
+use frontier;
+use regex;
+use serde_json;
+use toml;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::net;
+use std::path;
+
+pub const USAGE: &str = "Usage: rustcrawl [--config FILE] [--resume] [--seed URL]... [--OPTION VALUE]...
+
+Options are read from FILE first (toml, or json if FILE ends in .json) and
+then overridden by the ones given on the command line. Every field of the
+config file can be given as a flag by replacing underscores with dashes,
+e.g. `--get-timeout-millis 5000`. List fields take one value per flag, and
+giving one (or --seed) at least once replaces the list of the config file.
+--resume continues the crawl saved in the checkpoint file instead of
+starting from the seeds. --verbose tells on stderr how the threads of the
+crawl are doing as they start and stop.";
+
+/// Reasons the command line does not make a CrawlConfig.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ArgsError {
+    /// `--help` was given, so the usage is to be printed instead of crawling.
+    Help,
+    /// The arguments (or the config file they point to) are invalid.
+    Invalid(String),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArgsError::Help => write!(f, "{}", USAGE),
+            ArgsError::Invalid(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for ArgsError {
+    fn from(message: String) -> ArgsError {
+        ArgsError::Invalid(message)
+    }
+}
+
+/// Flags that take no value.
+const SWITCHES: &[&str] = &["resume", "verbose"];
+
+/// Flags that may be given several times, each adding a value to a list.
+const LISTS: &[&str] = &[
+    "seed",
+    "scope-allowed-schemes",
+    "scope-allowed-ports",
+    "scope-allowed-hosts",
+    "scope-denied-hosts",
+    "scope-allowed-path-prefixes",
+    "scope-denied-path-prefixes",
+    "scope-include-patterns",
+    "scope-exclude-patterns",
+    "canonical-strip-params",
+];
+
+/// Settings that shape a crawl. Every field has a default (see `Default`), so a
+/// config file only needs to contain the fields that should differ from it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrawlConfig {
+    /// Urls the reservoir contains when the crawl starts.
+    pub seeds: Vec<String>,
+    /// Capacity of the channel between `url_enqueuer` and the IO loop.
+    pub channel_buffer_size: usize,
+    /// Number of get requests the IO loop has in flight at the same time.
+    pub future_stream_buffer_size: usize,
+    /// Time the reporter waits between reports.
+    pub sleep_millis_between_reports: u64,
+    /// Time a get request (including its body) may take before it is dropped.
+    pub get_timeout_millis: u64,
+    /// Time the requests in flight are given to finish once the crawl is asked to stop.
+    pub shutdown_timeout_millis: u64,
+    /// Size an html response body may reach (once decompressed) before its download is aborted.
+    pub max_html_bytes: usize,
+    /// Size a css response body may reach (once decompressed) before its download is aborted.
+    pub max_css_bytes: usize,
+    /// Number of redirects followed (within the same origin) before a request is given up.
+    pub max_redirects: usize,
+    /// Number of times a fetch that failed in a way that may go away (timeouts, connection and dns errors, 5xx and 429 responses) is tried at most.
+    pub retry_max_attempts: u32,
+    /// Delay before the first retry of a failed fetch, doubled for each following one (and jittered).
+    pub retry_base_delay_millis: u64,
+    /// Upper bound for the delay before a retry. Fetches whose Retry-After asks for longer are given up.
+    pub retry_max_delay_millis: u64,
+    /// Number of failed fetches that may wait to be retried at the same time.
+    pub max_retry_queue_size: usize,
+    /// Whether the certificates of https servers are verified (if not, any certificate is accepted).
+    pub tls_verify_certificates: bool,
+    /// Pem file with certificates trusted in addition to the usual ones (empty for none).
+    pub tls_ca_file: String,
+    /// File the reporter appends its reports to.
+    pub report_filename: String,
+    /// File the reporter appends its reports to as lines of json (empty for none).
+    pub report_json_filename: String,
+    /// Address (e.g. `127.0.0.1:9898`) metrics are served at under `/metrics`, in the prometheus text format (empty for none).
+    pub metrics_address: String,
+    /// File every fetch is logged to as a line of json (empty for no log).
+    pub fetch_log_filename: String,
+    /// Size the fetch log may reach before it is rotated (renamed to `fetch_log_filename`.1, the older ones shifting up).
+    pub fetch_log_max_bytes: u64,
+    /// Number of rotated fetch logs kept besides the current one.
+    pub fetch_log_max_files: usize,
+    /// Number of fetch log records waiting to be written before further ones are dropped.
+    pub fetch_log_buffer_size: usize,
+    /// Directory `css_processor` writes the gathered css files into.
+    pub css_directory: String,
+    /// Time `url_enqueuer` sleeps when the reservoir is empty.
+    pub sleep_millis_on_empty_reservoir: u64,
+    /// Time `url_enqueuer` sleeps when the channel to the IO loop is full.
+    pub sleep_millis_on_full_channel: u64,
+    /// Number of urls `url_enqueuer` takes out of the reservoir at once.
+    pub max_urls_per_iter: usize,
+    /// Number of urls `html_processor` takes out of a single html document.
+    pub max_urls_per_site: usize,
+    /// Number of urls sharing a host `html_processor` takes out of a single html document.
+    pub max_host_sharing_urls_per_site: usize,
+    /// Number of links followed from a seed beyond which urls are dropped (0 for no limit).
+    pub max_depth: usize,
+    /// Number of urls the reservoir holds before it starts replacing random ones.
+    pub reservoir_size: usize,
+    /// Kind of frontier the urls waiting to be crawled are kept in: `reservoir` (retrieved and replaced at random) or `priority` (retrieved highest scored first, lowest scored dropped when full).
+    pub frontier: String,
+    /// Number of distinct urls the bloom filter is sized for.
+    pub bloom_expected_urls: usize,
+    /// False positive probability of the bloom filter once `bloom_expected_urls` urls were seen.
+    pub bloom_false_positive_rate: f64,
+    /// User agent sent with every request and matched against robots.txt groups.
+    pub user_agent: String,
+    /// Time the rules of a fetched robots.txt are used before fetching it again.
+    pub robots_ttl_secs: u64,
+    /// Time the urls of a host whose robots.txt is unreachable wait before it is fetched again.
+    pub robots_error_ttl_secs: u64,
+    /// Number of hosts whose robots.txt rules are kept at the same time.
+    pub robots_cache_size: usize,
+    /// Number of urls `url_enqueuer` holds back while the robots.txt of their hosts is fetched, the oldest going back to the frontier beyond it.
+    pub max_urls_awaiting_robots: usize,
+    /// Minimum time between two requests to the same host.
+    pub min_host_delay_millis: u64,
+    /// Upper bound for the Crawl-delay a robots.txt may ask for.
+    pub max_crawl_delay_millis: u64,
+    /// Number of requests to the same host that may be in flight at the same time.
+    pub max_host_connections: usize,
+    /// Number of requests to the same ip address that may be in flight at the same time (0 for no limit).
+    pub max_ip_connections: usize,
+    /// Number of urls waiting for their host to allow a request before `url_enqueuer` stops taking urls out of the reservoir.
+    pub max_scheduled_urls: usize,
+    /// Time `url_enqueuer` sleeps when no host allows a request.
+    pub scheduler_tick_millis: u64,
+    /// File the state of the crawl is saved to, periodically and when the crawl ends.
+    pub checkpoint_filename: String,
+    /// Time between two checkpoints (0 to only save one when the crawl ends).
+    pub checkpoint_interval_secs: u64,
+    /// Whether to continue the crawl saved in `checkpoint_filename` instead of starting from the seeds.
+    pub resume: bool,
+    /// Whether to tell on stderr when the threads of the crawl terminate, how the IO loop ended and where the checkpoints went (errors are told regardless).
+    pub verbose: bool,
+    /// Schemes urls may have (any if empty).
+    pub scope_allowed_schemes: Vec<String>,
+    /// Ports urls may have, explicitly or implied by their scheme (any if empty).
+    pub scope_allowed_ports: Vec<u16>,
+    /// Hosts urls may have (any if empty). `*.example.com` matches example.com and all of its subdomains.
+    pub scope_allowed_hosts: Vec<String>,
+    /// Hosts urls may not have, in the same form as `scope_allowed_hosts`.
+    pub scope_denied_hosts: Vec<String>,
+    /// Whether to stay on the registrable domains of the seeds (e.g. example.co.uk for www.example.co.uk).
+    pub scope_same_site: bool,
+    /// Copy of the public suffix list (https://publicsuffix.org/list/public_suffix_list.dat) telling registrable domains apart for `scope_same_site`. The copy bundled with the crawler is used if empty.
+    pub scope_public_suffix_file: String,
+    /// Prefixes one of which the paths of urls must start with (any path if empty).
+    pub scope_allowed_path_prefixes: Vec<String>,
+    /// Prefixes the paths of urls may not start with.
+    pub scope_denied_path_prefixes: Vec<String>,
+    /// Regexes one of which urls must match (any url if empty).
+    pub scope_include_patterns: Vec<String>,
+    /// Regexes urls may not match.
+    pub scope_exclude_patterns: Vec<String>,
+    /// Whether to sort the parameters of queries by name, so that their order does not tell urls apart.
+    pub canonical_sort_query: bool,
+    /// Names of the parameters stripped from the queries and path segments of urls (e.g. tracking and session ids). `utm_*` matches every name starting with utm_.
+    pub canonical_strip_params: Vec<String>,
+}
+
+impl Default for CrawlConfig {
+    fn default() -> CrawlConfig {
+        CrawlConfig{
+            seeds: vec!["http://cssdb.co".to_string()],
+            channel_buffer_size: 1024*8,
+            future_stream_buffer_size: 200,
+            sleep_millis_between_reports: 60000,
+            get_timeout_millis: 20000,
+            shutdown_timeout_millis: 30000,
+            max_html_bytes: 4*1024*1024,
+            max_css_bytes: 2*1024*1024,
+            max_redirects: 5,
+            retry_max_attempts: 3,
+            retry_base_delay_millis: 5000,
+            retry_max_delay_millis: 5*60*1000,
+            max_retry_queue_size: 64*1024,
+            tls_verify_certificates: true,
+            tls_ca_file: String::new(),
+            report_filename: "report.txt".to_string(),
+            report_json_filename: "report.jsonl".to_string(),
+            metrics_address: String::new(),
+            fetch_log_filename: String::new(),
+            fetch_log_max_bytes: 256*1024*1024,
+            fetch_log_max_files: 4,
+            fetch_log_buffer_size: 64*1024,
+            css_directory: "css".to_string(),
+            sleep_millis_on_empty_reservoir: 2000,
+            sleep_millis_on_full_channel: 8000,
+            max_urls_per_iter: 100,
+            max_urls_per_site: 2000,
+            max_host_sharing_urls_per_site: 5,
+            max_depth: 0,
+            reservoir_size: 1024*1024,
+            frontier: "reservoir".to_string(),
+            bloom_expected_urls: 200_000_000,
+            bloom_false_positive_rate: 0.001,
+            user_agent: "rustcrawl/0.1".to_string(),
+            robots_ttl_secs: 24*60*60,
+            robots_error_ttl_secs: 10*60,
+            robots_cache_size: 64*1024,
+            max_urls_awaiting_robots: 16*1024,
+            min_host_delay_millis: 1000,
+            max_crawl_delay_millis: 30000,
+            max_host_connections: 2,
+            max_ip_connections: 0,
+            max_scheduled_urls: 64*1024,
+            scheduler_tick_millis: 10,
+            checkpoint_filename: "checkpoint.bin".to_string(),
+            checkpoint_interval_secs: 30*60,
+            resume: false,
+            verbose: false,
+            scope_allowed_schemes: vec!["http".to_string(), "https".to_string()],
+            scope_allowed_ports: Vec::new(),
+            scope_allowed_hosts: Vec::new(),
+            scope_denied_hosts: Vec::new(),
+            scope_same_site: false,
+            scope_public_suffix_file: String::new(),
+            scope_allowed_path_prefixes: Vec::new(),
+            scope_denied_path_prefixes: Vec::new(),
+            scope_include_patterns: Vec::new(),
+            scope_exclude_patterns: Vec::new(),
+            canonical_sort_query: false,
+            canonical_strip_params: ["utm_*", "fbclid", "gclid", "dclid", "msclkid", "mc_eid", "jsessionid", "phpsessid", "aspsessionid", "sessionid", "session_id"].iter().map(|param| param.to_string()).collect(),
+        }
+    }
+}
+
+/// Parses `value` as the value of the flag `flag`, with an error message naming both on failure.
+fn parse_value<T: ::std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("invalid value for --{}: {:?}", flag, value))
+}
+
+impl CrawlConfig {
+    /// Reads a CrawlConfig from the file at `path`. Files ending in `.json` are
+    /// parsed as json, everything else as toml. Fields missing from the file
+    /// keep their default value.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the config file.
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> Result<CrawlConfig, String> {
+        let path=path.as_ref();
+        let mut content=String::new();
+        match fs::File::open(path).and_then(|mut f| f.read_to_string(&mut content)) {
+            Ok(_) => {},
+            Err(e) => return Err(format!("cannot read {:?}: {}", path, e)),
+        }
+
+        let is_json=path.extension().map_or(false, |extension| extension=="json");
+        if is_json {
+            serde_json::from_str(content.as_str()).map_err(|e| format!("cannot parse {:?}: {}", path, e))
+        } else {
+            toml::from_str(content.as_str()).map_err(|e| format!("cannot parse {:?}: {}", path, e))
+        }
+    }
+
+    /// Builds a CrawlConfig from command line arguments (without the program name).
+    /// Defaults are overridden by the file given with `--config`, which is in turn
+    /// overridden by the remaining flags, regardless of the order they were given in.
+    /// Returns `ArgsError::Help` if `--help` is among them.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Command line arguments, e.g. `env::args().skip(1)`.
+    pub fn from_args<I: IntoIterator<Item=String>>(args: I) -> Result<CrawlConfig, ArgsError> {
+        // Pair up every flag with its value.
+        let mut flags=Vec::new();
+        let mut args=args.into_iter();
+        while let Some(arg)=args.next() {
+            if !arg.starts_with("--") {
+                return Err(ArgsError::Invalid(format!("unexpected argument: {:?}", arg)));
+            }
+            let flag=arg[2..].to_string();
+            if flag=="help" {
+                return Err(ArgsError::Help);
+            }
+            if SWITCHES.contains(&flag.as_str()) {
+                flags.push((flag, "true".to_string()));
+                continue;
+            }
+
+            match args.next() {
+                Some(value) => flags.push((flag, value)),
+                None => return Err(ArgsError::Invalid(format!("missing value for --{}", flag))),
+            }
+        }
+
+        // Start from the config file, if any.
+        let mut config=match flags.iter().rev().find(|&&(ref flag, _)| flag=="config") {
+            Some(&(_, ref path)) => CrawlConfig::from_file(path)?,
+            None => CrawlConfig::default(),
+        };
+
+        // Override with the rest of the flags, gathering the values of list flags first.
+        let mut lists:Vec<(&str, Vec<String>)>=Vec::new();
+        for &(ref flag, ref value) in flags.iter() {
+            match flag.as_str() {
+                "config" => {},
+                flag if LISTS.contains(&flag) => match lists.iter().position(|&(list, _)| list==flag) {
+                    Some(i) => lists[i].1.push(value.clone()),
+                    None => lists.push((flag, vec![value.clone()])),
+                },
+                _ => config.set(flag.as_str(), value.as_str())?,
+            }
+        }
+        for (flag, values) in lists {
+            config.set_list(flag, values)?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Sets the field corresponding to the command line flag `flag` (without leading dashes).
+    fn set(&mut self, flag: &str, value: &str) -> Result<(), String> {
+        match flag {
+            "channel-buffer-size" => self.channel_buffer_size=parse_value(flag, value)?,
+            "future-stream-buffer-size" => self.future_stream_buffer_size=parse_value(flag, value)?,
+            "sleep-millis-between-reports" => self.sleep_millis_between_reports=parse_value(flag, value)?,
+            "get-timeout-millis" => self.get_timeout_millis=parse_value(flag, value)?,
+            "shutdown-timeout-millis" => self.shutdown_timeout_millis=parse_value(flag, value)?,
+            "max-html-bytes" => self.max_html_bytes=parse_value(flag, value)?,
+            "max-css-bytes" => self.max_css_bytes=parse_value(flag, value)?,
+            "max-redirects" => self.max_redirects=parse_value(flag, value)?,
+            "retry-max-attempts" => self.retry_max_attempts=parse_value(flag, value)?,
+            "retry-base-delay-millis" => self.retry_base_delay_millis=parse_value(flag, value)?,
+            "retry-max-delay-millis" => self.retry_max_delay_millis=parse_value(flag, value)?,
+            "max-retry-queue-size" => self.max_retry_queue_size=parse_value(flag, value)?,
+            "tls-verify-certificates" => self.tls_verify_certificates=parse_value(flag, value)?,
+            "tls-ca-file" => self.tls_ca_file=value.to_string(),
+            "report-filename" => self.report_filename=value.to_string(),
+            "report-json-filename" => self.report_json_filename=value.to_string(),
+            "metrics-address" => self.metrics_address=value.to_string(),
+            "fetch-log-filename" => self.fetch_log_filename=value.to_string(),
+            "fetch-log-max-bytes" => self.fetch_log_max_bytes=parse_value(flag, value)?,
+            "fetch-log-max-files" => self.fetch_log_max_files=parse_value(flag, value)?,
+            "fetch-log-buffer-size" => self.fetch_log_buffer_size=parse_value(flag, value)?,
+            "css-directory" => self.css_directory=value.to_string(),
+            "sleep-millis-on-empty-reservoir" => self.sleep_millis_on_empty_reservoir=parse_value(flag, value)?,
+            "sleep-millis-on-full-channel" => self.sleep_millis_on_full_channel=parse_value(flag, value)?,
+            "max-urls-per-iter" => self.max_urls_per_iter=parse_value(flag, value)?,
+            "max-urls-per-site" => self.max_urls_per_site=parse_value(flag, value)?,
+            "max-host-sharing-urls-per-site" => self.max_host_sharing_urls_per_site=parse_value(flag, value)?,
+            "max-depth" => self.max_depth=parse_value(flag, value)?,
+            "reservoir-size" => self.reservoir_size=parse_value(flag, value)?,
+            "frontier" => self.frontier=value.to_string(),
+            "bloom-expected-urls" => self.bloom_expected_urls=parse_value(flag, value)?,
+            "bloom-false-positive-rate" => self.bloom_false_positive_rate=parse_value(flag, value)?,
+            "user-agent" => self.user_agent=value.to_string(),
+            "robots-ttl-secs" => self.robots_ttl_secs=parse_value(flag, value)?,
+            "robots-error-ttl-secs" => self.robots_error_ttl_secs=parse_value(flag, value)?,
+            "robots-cache-size" => self.robots_cache_size=parse_value(flag, value)?,
+            "max-urls-awaiting-robots" => self.max_urls_awaiting_robots=parse_value(flag, value)?,
+            "min-host-delay-millis" => self.min_host_delay_millis=parse_value(flag, value)?,
+            "max-crawl-delay-millis" => self.max_crawl_delay_millis=parse_value(flag, value)?,
+            "max-host-connections" => self.max_host_connections=parse_value(flag, value)?,
+            "max-ip-connections" => self.max_ip_connections=parse_value(flag, value)?,
+            "max-scheduled-urls" => self.max_scheduled_urls=parse_value(flag, value)?,
+            "scheduler-tick-millis" => self.scheduler_tick_millis=parse_value(flag, value)?,
+            "checkpoint-filename" => self.checkpoint_filename=value.to_string(),
+            "checkpoint-interval-secs" => self.checkpoint_interval_secs=parse_value(flag, value)?,
+            "resume" => self.resume=parse_value(flag, value)?,
+            "verbose" => self.verbose=parse_value(flag, value)?,
+            "scope-same-site" => self.scope_same_site=parse_value(flag, value)?,
+            "scope-public-suffix-file" => self.scope_public_suffix_file=value.to_string(),
+            "canonical-sort-query" => self.canonical_sort_query=parse_value(flag, value)?,
+            _ => return Err(format!("unknown flag: --{}", flag)),
+        }
+        Ok(())
+    }
+
+    /// Sets the list field corresponding to the command line flag `flag` (without leading dashes) to `values`.
+    fn set_list(&mut self, flag: &str, values: Vec<String>) -> Result<(), String> {
+        match flag {
+            "seed" => self.seeds=values,
+            "scope-allowed-schemes" => self.scope_allowed_schemes=values,
+            "scope-allowed-ports" => self.scope_allowed_ports=values.iter().map(|value| parse_value(flag, value)).collect::<Result<_, _>>()?,
+            "scope-allowed-hosts" => self.scope_allowed_hosts=values,
+            "scope-denied-hosts" => self.scope_denied_hosts=values,
+            "scope-allowed-path-prefixes" => self.scope_allowed_path_prefixes=values,
+            "scope-denied-path-prefixes" => self.scope_denied_path_prefixes=values,
+            "scope-include-patterns" => self.scope_include_patterns=values,
+            "scope-exclude-patterns" => self.scope_exclude_patterns=values,
+            "canonical-strip-params" => self.canonical_strip_params=values,
+            _ => return Err(format!("unknown flag: --{}", flag)),
+        }
+        Ok(())
+    }
+
+    /// Rejects settings the workers cannot run with.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.seeds.is_empty() {
+            return Err("at least one seed is needed".to_string());
+        }
+        if self.future_stream_buffer_size==0 || self.max_urls_per_iter==0 || self.reservoir_size==0 || self.robots_cache_size==0 || self.max_host_connections==0 || self.max_html_bytes==0 || self.max_css_bytes==0 {
+            return Err("future_stream_buffer_size, max_urls_per_iter, reservoir_size, robots_cache_size, max_host_connections, max_html_bytes and max_css_bytes must be positive".to_string());
+        }
+        if !self.metrics_address.is_empty() && self.metrics_address.parse::<net::SocketAddr>().is_err() {
+            return Err(format!("invalid metrics_address {:?} (expected e.g. 127.0.0.1:9898)", self.metrics_address));
+        }
+        if !self.fetch_log_filename.is_empty() && (self.fetch_log_max_bytes==0 || self.fetch_log_buffer_size==0) {
+            return Err("fetch_log_max_bytes and fetch_log_buffer_size must be positive".to_string());
+        }
+        if self.retry_max_attempts==0 {
+            return Err("retry_max_attempts must be positive (1 for no retries)".to_string());
+        }
+        if self.bloom_expected_urls==0 || !(self.bloom_false_positive_rate>0.0 && self.bloom_false_positive_rate<1.0) {
+            return Err("bloom_expected_urls must be positive and bloom_false_positive_rate between 0 and 1".to_string());
+        }
+        if self.sleep_millis_between_reports==0 || self.scheduler_tick_millis==0 {
+            return Err("sleep_millis_between_reports and scheduler_tick_millis must be positive".to_string());
+        }
+        if frontier::FrontierKind::from_name(self.frontier.as_str()).is_none() {
+            return Err(format!("invalid frontier {:?} (expected reservoir or priority)", self.frontier));
+        }
+        for pattern in self.scope_include_patterns.iter().chain(self.scope_exclude_patterns.iter()) {
+            if let Err(e)=regex::Regex::new(pattern) {
+                return Err(format!("invalid scope pattern {:?}: {}", pattern, e));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util;
+    use std::io::Write;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|arg| arg.to_string()).collect()
+    }
+
+    fn write_file(dir: &test_util::TempDir, name: &str, content: &str) -> path::PathBuf {
+        let path=dir.join(name);
+        fs::File::create(&path).unwrap().write_all(content.as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_defaults() {
+        assert_eq!(CrawlConfig::from_args(args("")), Ok(CrawlConfig::default()));
+    }
+
+    #[test]
+    fn test_flags() {
+        let config=CrawlConfig::from_args(args("--get-timeout-millis 5 --seed http://a.com --report-filename r.txt --seed http://b.com")).unwrap();
+        assert_eq!(config.get_timeout_millis, 5);
+        assert_eq!(config.report_filename, "r.txt");
+        assert_eq!(config.seeds, vec!["http://a.com".to_string(), "http://b.com".to_string()]);
+        assert_eq!(config.reservoir_size, CrawlConfig::default().reservoir_size);
+        assert!(!config.resume);
+
+        let config=CrawlConfig::from_args(args("--resume --checkpoint-interval-secs 60 --verbose --tls-verify-certificates false")).unwrap();
+        assert!(config.resume && config.verbose);
+        assert!(!config.tls_verify_certificates);
+        assert_eq!(config.checkpoint_interval_secs, 60);
+
+        assert_eq!(CrawlConfig::from_args(args("--seed http://a.com --help")), Err(ArgsError::Help));
+        assert!(CrawlConfig::from_args(args("--get-timeout-millis")).is_err());
+        assert!(CrawlConfig::from_args(args("--get-timeout-millis soon")).is_err());
+        assert!(CrawlConfig::from_args(args("--tls-verify-certificates maybe")).is_err());
+        assert!(CrawlConfig::from_args(args("--no-such-flag 1")).is_err());
+        assert!(CrawlConfig::from_args(args("positional")).is_err());
+        assert!(CrawlConfig::from_args(args("--reservoir-size 0")).is_err());
+        assert!(CrawlConfig::from_args(args("--bloom-false-positive-rate 1.5")).is_err());
+        assert!(CrawlConfig::from_args(args("--metrics-address 127.0.0.1:9898")).is_ok());
+        assert!(CrawlConfig::from_args(args("--metrics-address localhost")).is_err());
+        assert_eq!(CrawlConfig::from_args(args("--frontier priority")).unwrap().frontier, "priority");
+        assert!(CrawlConfig::from_args(args("--frontier fifo")).is_err());
+
+        let config=CrawlConfig::from_args(args("--scope-allowed-hosts *.a.com --scope-same-site true --scope-allowed-ports 80 --scope-allowed-hosts b.com --scope-allowed-ports 8080")).unwrap();
+        assert_eq!(config.scope_allowed_hosts, vec!["*.a.com".to_string(), "b.com".to_string()]);
+        assert_eq!(config.scope_allowed_ports, vec![80, 8080]);
+        assert!(config.scope_same_site);
+        assert_eq!(config.scope_allowed_schemes, CrawlConfig::default().scope_allowed_schemes);
+        assert!(CrawlConfig::from_args(args("--scope-allowed-ports http")).is_err());
+        assert!(CrawlConfig::from_args(args("--scope-exclude-patterns [")).is_err());
+    }
+
+    #[test]
+    fn test_file_precedence() {
+        let dir=test_util::TempDir::new("config");
+        let toml_path=write_file(&dir, "config.toml", "seeds = [\"http://c.com\"]\nget_timeout_millis = 7\nmax_urls_per_iter = 3\n");
+        let config=CrawlConfig::from_args(vec!["--max-urls-per-iter".to_string(), "4".to_string(), "--config".to_string(), toml_path.to_string_lossy().into_owned()]).unwrap();
+        assert_eq!(config.seeds, vec!["http://c.com".to_string()]);
+        assert_eq!(config.get_timeout_millis, 7);
+        assert_eq!(config.max_urls_per_iter, 4);
+        assert_eq!(config.channel_buffer_size, CrawlConfig::default().channel_buffer_size);
+
+        let json_path=write_file(&dir, "config.json", "{\"reservoir_size\": 10, \"css_directory\": \"out\"}");
+        let config=CrawlConfig::from_file(&json_path).unwrap();
+        assert_eq!(config.reservoir_size, 10);
+        assert_eq!(config.css_directory, "out");
+
+        let bad_path=write_file(&dir, "bad_config.toml", "no_such_field = 1\n");
+        assert!(CrawlConfig::from_file(&bad_path).is_err());
+    }
+}
@@ -0,0 +1,406 @@
+#![allow(dead_code)]
+
+use body_decoder;
+use redirect;
+use retry;
+use hyper;
+use rustls;
+use std::error;
+use std::fmt;
+use std::io;
+use std::sync;
+
+/// Stage of the crawl at which a page (or url) was lost.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Stage {
+    Fetch,
+    Decode,
+    Parse,
+    Filter,
+    Storage,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Stage::Fetch => write!(f, "fetch"),
+            Stage::Decode => write!(f, "decode"),
+            Stage::Parse => write!(f, "parse"),
+            Stage::Filter => write!(f, "filter"),
+            Stage::Storage => write!(f, "storage"),
+        }
+    }
+}
+
+/// All stages, in the order they are reported.
+pub const STAGES: [Stage;5] = [Stage::Fetch, Stage::Decode, Stage::Parse, Stage::Filter, Stage::Storage];
+
+/// Names of the counters of each kind of error, in the order of `CrawlError::index`.
+pub const ERROR_NAMES: [&str;20] = [
+    "fetch_timeout",
+    "fetch_connection",
+    "fetch_dns",
+    "fetch_tls",
+    "fetch_status",
+    "fetch_too_large",
+    "fetch_redirect",
+    "fetch_other",
+    "decode_content_encoding",
+    "decode_charset",
+    "parse_url",
+    "parse_host",
+    "parse_origin",
+    "filter_chars",
+    "filter_short",
+    "filter_newlines",
+    "filter_duplicate",
+    "filter_robots_backlog",
+    "storage_io",
+    "storage_channel",
+];
+
+/// Reason a page (or url) was lost along the way from the reservoir to a saved file.
+#[derive(Debug)]
+pub enum CrawlError {
+    /// The request (including its body) took too long.
+    Timeout,
+    /// The connection was refused, reset or closed before the response was complete.
+    Connection,
+    /// The host name could not be resolved.
+    Dns,
+    /// The tls handshake (or a later tls record) failed.
+    Tls(String),
+    /// The response had an error status code.
+    Status(u16),
+    /// The body was larger than allowed for its content type.
+    BodyTooLarge,
+    /// A redirect chain was cut short because of its redirects.
+    BrokenRedirect(redirect::RedirectError),
+    /// Any other error of the hyper client, e.g. an invalid response.
+    Fetch(String),
+    /// The body had an unsupported or corrupt content encoding.
+    ContentEncoding(String),
+    /// The document contained bytes invalid in its charset.
+    Charset(&'static str),
+    /// An url could not be parsed.
+    InvalidUrl(String),
+    /// An url has no host.
+    MissingHost,
+    /// An url has no origin (scheme, host and port).
+    MissingOrigin,
+    /// Css code contained characters other than the allowed ones.
+    DisallowedChars,
+    /// Css code was shorter than the given amount of bytes.
+    TooShort(usize),
+    /// Css code had fewer newlines than the given amount.
+    TooFewNewlines(usize),
+    /// Css code was gathered before.
+    AlreadyGathered,
+    /// Too many urls were waiting for the robots.txt of their host.
+    RobotsBacklog,
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// The named worker no longer receives documents.
+    ChannelClosed(&'static str),
+}
+
+impl fmt::Display for CrawlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CrawlError::Timeout => write!(f, "timeout"),
+            CrawlError::Connection => write!(f, "connection failed"),
+            CrawlError::Dns => write!(f, "dns lookup failed"),
+            CrawlError::Tls(ref e) => write!(f, "tls error: {}", e),
+            CrawlError::Status(status) => write!(f, "status {}", status),
+            CrawlError::BodyTooLarge => write!(f, "body too large"),
+            CrawlError::BrokenRedirect(ref e) => write!(f, "{}", e),
+            CrawlError::Fetch(ref e) => write!(f, "fetch failed: {}", e),
+            CrawlError::ContentEncoding(ref e) => write!(f, "cannot decode body: {}", e),
+            CrawlError::Charset(encoding) => write!(f, "not valid {}", encoding),
+            CrawlError::InvalidUrl(ref e) => write!(f, "invalid url: {}", e),
+            CrawlError::MissingHost => write!(f, "url has no host"),
+            CrawlError::MissingOrigin => write!(f, "url has no origin"),
+            CrawlError::DisallowedChars => write!(f, "css contains disallowed chars"),
+            CrawlError::TooShort(len) => write!(f, "css len less than {}", len),
+            CrawlError::TooFewNewlines(newlines) => write!(f, "css has fewer than {} newline chars", newlines),
+            CrawlError::AlreadyGathered => write!(f, "css was already gathered"),
+            CrawlError::RobotsBacklog => write!(f, "too many urls awaiting robots.txt"),
+            CrawlError::Io(ref e) => write!(f, "{}", e),
+            CrawlError::ChannelClosed(worker) => write!(f, "{} is gone", worker),
+        }
+    }
+}
+
+impl error::Error for CrawlError {
+    fn description(&self) -> &str {
+        "page lost"
+    }
+}
+
+impl From<io::Error> for CrawlError {
+    fn from(e: io::Error) -> CrawlError {
+        CrawlError::Io(e)
+    }
+}
+
+impl CrawlError {
+    /// Returns the position of the error's kind within `ERROR_NAMES`.
+    pub fn index(&self) -> usize {
+        match *self {
+            CrawlError::Timeout => 0,
+            CrawlError::Connection => 1,
+            CrawlError::Dns => 2,
+            CrawlError::Tls(_) => 3,
+            CrawlError::Status(_) => 4,
+            CrawlError::BodyTooLarge => 5,
+            CrawlError::BrokenRedirect(_) => 6,
+            CrawlError::Fetch(_) => 7,
+            CrawlError::ContentEncoding(_) => 8,
+            CrawlError::Charset(_) => 9,
+            CrawlError::InvalidUrl(_) => 10,
+            CrawlError::MissingHost => 11,
+            CrawlError::MissingOrigin => 12,
+            CrawlError::DisallowedChars => 13,
+            CrawlError::TooShort(_) => 14,
+            CrawlError::TooFewNewlines(_) => 15,
+            CrawlError::AlreadyGathered => 16,
+            CrawlError::RobotsBacklog => 17,
+            CrawlError::Io(_) => 18,
+            CrawlError::ChannelClosed(_) => 19,
+        }
+    }
+
+    /// Returns the name of the counter of the error's kind.
+    pub fn name(&self) -> &'static str {
+        ERROR_NAMES[self.index()]
+    }
+
+    /// Returns the stage of the crawl the error happens at.
+    pub fn stage(&self) -> Stage {
+        match *self {
+            CrawlError::Timeout | CrawlError::Connection | CrawlError::Dns | CrawlError::Tls(_) | CrawlError::Status(_) |
+            CrawlError::BodyTooLarge | CrawlError::BrokenRedirect(_) | CrawlError::Fetch(_) => Stage::Fetch,
+            CrawlError::ContentEncoding(_) | CrawlError::Charset(_) => Stage::Decode,
+            CrawlError::InvalidUrl(_) | CrawlError::MissingHost | CrawlError::MissingOrigin => Stage::Parse,
+            CrawlError::DisallowedChars | CrawlError::TooShort(_) | CrawlError::TooFewNewlines(_) |
+            CrawlError::AlreadyGathered | CrawlError::RobotsBacklog => Stage::Filter,
+            CrawlError::Io(_) | CrawlError::ChannelClosed(_) => Stage::Storage,
+        }
+    }
+
+    /// Returns an error of every kind, in the order of `ERROR_NAMES`.
+    pub fn kinds() -> Vec<CrawlError> {
+        vec![
+            CrawlError::Timeout,
+            CrawlError::Connection,
+            CrawlError::Dns,
+            CrawlError::Tls(String::new()),
+            CrawlError::Status(0),
+            CrawlError::BodyTooLarge,
+            CrawlError::BrokenRedirect(redirect::RedirectError::MissingLocation),
+            CrawlError::Fetch(String::new()),
+            CrawlError::ContentEncoding(String::new()),
+            CrawlError::Charset(""),
+            CrawlError::InvalidUrl(String::new()),
+            CrawlError::MissingHost,
+            CrawlError::MissingOrigin,
+            CrawlError::DisallowedChars,
+            CrawlError::TooShort(0),
+            CrawlError::TooFewNewlines(0),
+            CrawlError::AlreadyGathered,
+            CrawlError::RobotsBacklog,
+            CrawlError::Io(io::Error::new(io::ErrorKind::Other, "")),
+            CrawlError::ChannelClosed(""),
+        ]
+    }
+
+    /// Returns the error behind a failed request of the hyper client, including
+    /// the errors wrapped into io errors along the way (error statuses, bodies
+    /// too large or undecodable and broken redirect chains).
+    ///
+    /// # Arguments
+    ///
+    /// * `e` - The error.
+    pub fn from_fetch_error(e: &hyper::Error) -> CrawlError {
+        if let hyper::Error::Io(ref io_error)=*e {
+            if body_decoder::is_too_large(io_error) {
+                return CrawlError::BodyTooLarge;
+            }
+            if let Some(redirect_error)=redirect::redirect_error(io_error) {
+                return CrawlError::BrokenRedirect(redirect_error.clone());
+            }
+            if let Some(tls_error)=io_error.get_ref().and_then(|inner| inner.downcast_ref::<rustls::TLSError>()) {
+                return CrawlError::Tls(tls_error.to_string());
+            }
+            if io_error.kind()==io::ErrorKind::InvalidData {
+                return CrawlError::ContentEncoding(io_error.to_string());
+            }
+        }
+
+        match retry::Failure::from_error(e) {
+            retry::Failure::Timeout => CrawlError::Timeout,
+            retry::Failure::Connection => CrawlError::Connection,
+            retry::Failure::Dns => CrawlError::Dns,
+            retry::Failure::ServerError(status) | retry::Failure::ClientError(status) => CrawlError::Status(status),
+            retry::Failure::TooManyRequests(_) => CrawlError::Status(429),
+            retry::Failure::Other => CrawlError::Fetch(e.to_string()),
+        }
+    }
+}
+
+/// Counters of the errors of each kind, shared among the threads of the crawl.
+pub struct ErrorCounters {
+    counters: Vec<sync::Arc<sync::atomic::AtomicUsize>>,
+    stages: Vec<Stage>,
+}
+
+impl ErrorCounters {
+    /// Creates and returns a new ErrorCounters structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - Returns the value a counter (given its name) starts from, e.g. as saved by a checkpoint.
+    pub fn new<F: Fn(&str) -> usize>(start: F) -> ErrorCounters {
+        ErrorCounters{
+            counters: ERROR_NAMES.iter().map(|name| sync::Arc::new(sync::atomic::AtomicUsize::new(start(name)))).collect(),
+            stages: CrawlError::kinds().iter().map(|e| e.stage()).collect(),
+        }
+    }
+
+    /// Counts `e`.
+    pub fn count(&self, e: &CrawlError) {
+        self.counters[e.index()].fetch_add(1, sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Logs `e` as having happened within `source`, and counts it.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - Part of the crawl the error happened in.
+    /// * `e` - The error.
+    pub fn record(&self, source: &str, e: &CrawlError) {
+        eprintln!("Error ({}): {}", source, e);
+        self.count(e);
+    }
+
+    /// Returns the value of the counter named `name`, or 0 if there is none.
+    pub fn get(&self, name: &str) -> usize {
+        ERROR_NAMES.iter().position(|&n| n==name).map_or(0, |i| self.counters[i].load(sync::atomic::Ordering::Relaxed))
+    }
+
+    /// Returns the counters along with their names, to be saved in checkpoints.
+    pub fn named(&self) -> Vec<(&'static str, sync::Arc<sync::atomic::AtomicUsize>)> {
+        ERROR_NAMES.iter().cloned().zip(self.counters.iter().cloned()).collect()
+    }
+
+    /// Returns the total of the errors at each stage.
+    pub fn by_stage(&self) -> Vec<(Stage, usize)> {
+        STAGES.iter().map(|&stage| {
+            let total=self.counters.iter().enumerate()
+            .filter(|&(i, _)| self.stages[i]==stage)
+            .map(|(_, counter)| counter.load(sync::atomic::Ordering::Relaxed))
+            .sum();
+            (stage, total)
+        }).collect()
+    }
+
+    /// Returns a line summing up the errors at each stage, detailing the kinds
+    /// that happened at least once, e.g. `fetch: 3 (timeout: 2, dns: 1), decode: 0`.
+    pub fn summary(&self) -> String {
+        self.by_stage().into_iter().map(|(stage, total)| {
+            let kinds=self.counters.iter().enumerate()
+            .filter(|&(i, _)| self.stages[i]==stage)
+            .map(|(i, counter)| (ERROR_NAMES[i], counter.load(sync::atomic::Ordering::Relaxed)))
+            .filter(|&(_, value)| value>0)
+            .map(|(name, value)| format!("{}: {}", &name[name.find('_').map_or(0, |i| i+1)..], value))
+            .collect::<Vec<_>>();
+            if kinds.is_empty() {
+                format!("{}: {}", stage, total)
+            } else {
+                format!("{}: {} ({})", stage, total, kinds.join(", "))
+            }
+        }).collect::<Vec<_>>().join(", ")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kinds() {
+        let errors=vec![
+            CrawlError::Timeout,
+            CrawlError::Tls("bad certificate".to_string()),
+            CrawlError::BrokenRedirect(redirect::RedirectError::MissingLocation),
+            CrawlError::Charset("windows-1252"),
+            CrawlError::MissingOrigin,
+            CrawlError::TooFewNewlines(5),
+            CrawlError::RobotsBacklog,
+            CrawlError::from(io::Error::new(io::ErrorKind::NotFound, "no such directory")),
+            CrawlError::ChannelClosed("html_processor"),
+        ];
+        let stages=[Stage::Fetch, Stage::Fetch, Stage::Fetch, Stage::Decode, Stage::Parse, Stage::Filter, Stage::Filter, Stage::Storage, Stage::Storage];
+        for (e, &stage) in errors.iter().zip(stages.iter()) {
+            assert_eq!(e.stage(), stage);
+            assert!(e.name().starts_with(stage.to_string().as_str()));
+        }
+        assert_eq!(CrawlError::TooShort(50).to_string(), "css len less than 50");
+
+        let kinds=CrawlError::kinds();
+        assert_eq!(kinds.len(), ERROR_NAMES.len());
+        for (i, e) in kinds.iter().enumerate() {
+            assert_eq!(e.index(), i);
+            assert!(e.name().starts_with(e.stage().to_string().as_str()));
+        }
+    }
+
+    #[test]
+    fn test_from_fetch_error() {
+        let wrapped=|payload: Box<error::Error+Send+Sync>| hyper::Error::from(io::Error::new(io::ErrorKind::Other, payload));
+
+        match CrawlError::from_fetch_error(&wrapped(Box::new(body_decoder::BodyTooLarge))) {
+            CrawlError::BodyTooLarge => {},
+            e => panic!("unexpected {:?}", e),
+        }
+        match CrawlError::from_fetch_error(&wrapped(Box::new(redirect::RedirectError::TooManyHops(5)))) {
+            CrawlError::BrokenRedirect(redirect::RedirectError::TooManyHops(5)) => {},
+            e => panic!("unexpected {:?}", e),
+        }
+        match CrawlError::from_fetch_error(&wrapped(Box::new(retry::Failure::ServerError(503)))) {
+            CrawlError::Status(503) => {},
+            e => panic!("unexpected {:?}", e),
+        }
+        match CrawlError::from_fetch_error(&hyper::Error::from(io::Error::new(io::ErrorKind::InvalidData, rustls::TLSError::NoCertificatesPresented))) {
+            CrawlError::Tls(_) => {},
+            e => panic!("unexpected {:?}", e),
+        }
+        match CrawlError::from_fetch_error(&hyper::Error::from(io::Error::new(io::ErrorKind::InvalidData, "unsupported content encoding \"zstd\""))) {
+            CrawlError::ContentEncoding(_) => {},
+            e => panic!("unexpected {:?}", e),
+        }
+        match CrawlError::from_fetch_error(&hyper::Error::from(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"))) {
+            CrawlError::Connection => {},
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_counters() {
+        let counters=ErrorCounters::new(|name| if name=="fetch_dns" {4} else {0});
+        counters.count(&CrawlError::Timeout);
+        counters.count(&CrawlError::Timeout);
+        counters.count(&CrawlError::AlreadyGathered);
+        assert_eq!(counters.get("fetch_timeout"), 2);
+        assert_eq!(counters.get("fetch_dns"), 4);
+        assert_eq!(counters.get("no_such_counter"), 0);
+        assert_eq!(counters.by_stage(), vec![(Stage::Fetch, 6), (Stage::Decode, 0), (Stage::Parse, 0), (Stage::Filter, 1), (Stage::Storage, 0)]);
+        assert_eq!(counters.summary(), "fetch: 6 (timeout: 2, dns: 4), decode: 0, parse: 0, filter: 1 (duplicate: 1), storage: 0");
+
+        let named=counters.named();
+        assert_eq!(named.len(), ERROR_NAMES.len());
+        assert_eq!(named[0].0, "fetch_timeout");
+        assert_eq!(named[0].1.load(sync::atomic::Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,1204 @@
+use body_decoder;
+use bloom_filter;
+use canonical;
+use checkpoint;
+use config;
+use crawl_error::CrawlError;
+use crawl_error;
+use css_processor;
+use fetch_log;
+use frontier;
+use host_scheduler;
+use html_processor;
+use metrics;
+use outstanding;
+use processor;
+use redirect;
+use report;
+use retry;
+use robots;
+use scope;
+use shutdown;
+use tls;
+use url_enqueuer;
+use url_meta;
+use bytes;
+use futures;
+use futures::Future;
+use futures::stream::Stream;
+use hyper;
+use rand;
+use tokio_core;
+use url;
+use std::cell;
+use std::collections;
+use std::io;
+use std::rc;
+use std::sync;
+use std::thread;
+use std::time;
+
+/// Response of a fetch, as handed from the request to where its body is sent to.
+struct Fetched {
+    status: u16,
+    /// Media type of the Content-Type header, without its parameters.
+    mime: Option<String>,
+    headers: hyper::Headers,
+    chain: Option<redirect::RedirectChain>,
+    body: bytes::Bytes,
+}
+
+/// Timeout of a fetch, whose deadline is pushed back by the time its redirects
+/// wait for their host to allow them, so that waiting does not make it time out.
+struct FetchTimeout {
+    timeout: tokio_core::reactor::Timeout,
+    deadline: rc::Rc<cell::Cell<time::Instant>>,
+}
+
+impl Future for FetchTimeout {
+    type Item=();
+    type Error=io::Error;
+
+    fn poll(&mut self) -> futures::Poll<(), io::Error> {
+        loop {
+            match self.timeout.poll()? {
+                futures::Async::Ready(()) => {
+                    let deadline=self.deadline.get();
+                    if deadline<=time::Instant::now() {
+                        return Ok(futures::Async::Ready(()));
+                    }
+                    self.timeout.reset(deadline);
+                },
+                futures::Async::NotReady => return Ok(futures::Async::NotReady),
+            }
+        }
+    }
+}
+
+fn get_timeout(duration: time::Duration, handle: &tokio_core::reactor::Handle) -> tokio_core::reactor::Timeout {
+    get_timeout_at(time::Instant::now()+duration, handle)
+}
+
+/// Returns a tokio_core::reactor::Timeout that fires at `at`, trying again until one can be created.
+fn get_timeout_at(at: time::Instant, handle: &tokio_core::reactor::Handle) -> tokio_core::reactor::Timeout {
+    loop {
+        match tokio_core::reactor::Timeout::new_at(at, &handle) {
+            Ok(timeout) => {
+                return timeout
+            },
+            Err(e) => {
+                eprintln!("Error (Timeout.new): {:?}", e);
+                continue;
+            },
+        }
+    }
+}
+
+/// Builds a get request for `uri` that identifies the crawler as `user_agent`.
+fn get_request(uri: hyper::Uri, user_agent: &str) -> hyper::Request {
+    let mut request=hyper::Request::new(hyper::Method::Get, uri);
+    request.headers_mut().set(hyper::header::UserAgent::new(user_agent.to_string()));
+    request.headers_mut().set_raw("Accept-Encoding", body_decoder::ACCEPT_ENCODING);
+    request
+}
+
+/// Returns the current values of the named `counters`, to be saved in a checkpoint.
+fn load_counters(counters: &[(&'static str, sync::Arc<sync::atomic::AtomicUsize>)]) -> Vec<(&'static str, usize)> {
+    counters.iter().map(|&(name, ref counter)| (name, counter.load(sync::atomic::Ordering::Relaxed))).collect()
+}
+
+/// Counters and structures shared by the threads of the crawl, to watch it through.
+#[derive(Clone)]
+struct SharedState {
+    counters: Vec<(&'static str, sync::Arc<sync::atomic::AtomicUsize>)>,
+    frontier: sync::Arc<sync::Mutex<frontier::Frontier>>,
+    host_scheduler: sync::Arc<sync::Mutex<host_scheduler::HostScheduler<(hyper::Uri, url_meta::UrlMeta)>>>,
+    retry_queue: sync::Arc<sync::Mutex<retry::RetryQueue>>,
+    robots_cache: sync::Arc<sync::Mutex<robots::RobotsCache>>,
+    bloom_filter: sync::Arc<bloom_filter::ConcurrentBloomFilter>,
+    fetch_log: Option<fetch_log::FetchLog>,
+}
+
+impl SharedState {
+    /// Returns the current values of the counters.
+    fn counters(&self) -> Vec<(&'static str, usize)> {
+        load_counters(&self.counters)
+    }
+
+    /// Returns the current sizes of the shared structures.
+    fn gauges(&self) -> Result<report::Gauges, String> {
+        let (scheduled_urls, scheduled_hosts)={
+            let mutex_guard=self.host_scheduler.lock().map_err(|e| e.to_string())?;
+            (mutex_guard.queued(), mutex_guard.hosts())
+        };
+
+        Ok(report::Gauges{
+            reservoir_urls: self.frontier.lock().map_err(|e| e.to_string())?.len(),
+            scheduled_urls: scheduled_urls,
+            scheduled_hosts: scheduled_hosts,
+            retries_waiting: self.retry_queue.lock().map_err(|e| e.to_string())?.len(),
+            robots_cached: self.robots_cache.lock().map_err(|e| e.to_string())?.len(),
+            bloom_urls: self.bloom_filter.approx_len(),
+            bloom_fill_ratio: self.bloom_filter.fill_ratio(),
+            bloom_false_positive_probability: self.bloom_filter.estimated_fpp(),
+            fetch_log_dropped: self.fetch_log.as_ref().map_or(0, |fetch_log| fetch_log.dropped()),
+        })
+    }
+}
+
+/// Counters and gauges of a running crawl.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// Every counter of the crawl, by name (those of a resumed crawl included).
+    pub counters: collections::BTreeMap<&'static str, usize>,
+    /// Current sizes of the structures shared by the threads of the crawl.
+    pub gauges: report::Gauges,
+    /// Whether the crawl is paused.
+    pub paused: bool,
+}
+
+impl Stats {
+    /// Returns the value of the counter named `name`, or 0 if there is none.
+    pub fn counter(&self, name: &str) -> usize {
+        self.counters.get(name).cloned().unwrap_or(0)
+    }
+}
+
+/// Builder of a Crawler, starting from the default settings.
+pub struct CrawlerBuilder {
+    config: config::CrawlConfig,
+    seeds: Option<Vec<String>>,
+    processors: Vec<Box<processor::ContentProcessor>>,
+    handle_signals: bool,
+}
+
+impl CrawlerBuilder {
+    /// Sets the settings of the crawl.
+    pub fn config(mut self, config: config::CrawlConfig) -> CrawlerBuilder {
+        self.config=config;
+        self
+    }
+
+    /// Sets the urls the crawl starts from, replacing the seeds of the settings.
+    pub fn seeds<I: IntoIterator<Item=S>, S: Into<String>>(mut self, seeds: I) -> CrawlerBuilder {
+        self.seeds=Some(seeds.into_iter().map(|seed| seed.into()).collect());
+        self
+    }
+
+    /// Adds a processor the pages of its media types are handed to, on top of
+    /// the html and css processors.
+    pub fn processor<P: processor::ContentProcessor + 'static>(mut self, processor: P) -> CrawlerBuilder {
+        self.processors.push(Box::new(processor));
+        self
+    }
+
+    /// Sets whether SIGINT and SIGTERM shut the crawl down gracefully, instead of killing the process.
+    pub fn handle_signals(mut self, handle_signals: bool) -> CrawlerBuilder {
+        self.handle_signals=handle_signals;
+        self
+    }
+
+    /// Validates the settings and returns the Crawler.
+    pub fn build(self) -> Result<Crawler, String> {
+        let mut config=self.config;
+        if let Some(seeds)=self.seeds {
+            config.seeds=seeds;
+        }
+        config.validate()?;
+
+        Ok(Crawler{
+            config: sync::Arc::new(config),
+            processors: self.processors,
+            handle_signals: self.handle_signals,
+        })
+    }
+}
+
+/// Crawler whose threads are started by `start`.
+pub struct Crawler {
+    config: sync::Arc<config::CrawlConfig>,
+    processors: Vec<Box<processor::ContentProcessor>>,
+    handle_signals: bool,
+}
+
+/// Handle of a running crawl, through which it can be watched, paused and shut down.
+pub struct CrawlerHandle {
+    state: SharedState,
+    paused: sync::Arc<sync::atomic::AtomicBool>,
+    shutdown: shutdown::Shutdown,
+    io_thread: thread::JoinHandle<Result<report::CrawlReport, String>>,
+    metrics_thread: Option<thread::JoinHandle<()>>,
+    verbose: bool,
+}
+
+impl CrawlerHandle {
+    /// Returns the current counters and gauges of the crawl.
+    pub fn stats(&self) -> Result<Stats, String> {
+        Ok(Stats{
+            counters: self.state.counters().into_iter().collect(),
+            gauges: self.state.gauges()?,
+            paused: self.is_paused(),
+        })
+    }
+
+    /// Stops sending urls to be fetched, letting the requests in flight finish.
+    pub fn pause(&self) {
+        self.paused.store(true, sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Sends urls to be fetched again after `pause`.
+    pub fn resume(&self) {
+        self.paused.store(false, sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Returns whether the crawl is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Returns whether the crawl was asked to stop (by `shutdown` or a signal).
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_requested()
+    }
+
+    /// Asks the crawl to stop and waits until it did, see `wait`.
+    pub fn shutdown(self) -> Result<report::CrawlReport, String> {
+        self.shutdown.request();
+        self.wait()
+    }
+
+    /// Waits until the crawl stopped (on a signal, or the IO loop failing), its
+    /// requests in flight being done, its workers drained, its final report
+    /// and checkpoint written, its fetch log flushed and its metrics server stopped.
+    /// Returns the final report.
+    pub fn wait(self) -> Result<report::CrawlReport, String> {
+        let CrawlerHandle{state, shutdown, io_thread, metrics_thread, verbose, ..}=self;
+        let result=io_thread.join();
+
+        // The IO loop requests the shutdown as it ends, unless it panicked.
+        shutdown.request();
+        if let Some(ref fetch_log)=state.fetch_log {
+            fetch_log.finish();
+            if verbose {
+                eprintln!("Fetch log writer terminated.");
+            }
+        }
+        if let Some(metrics_thread)=metrics_thread {
+            match metrics_thread.join() {
+                Ok(_) => if verbose {
+                    eprintln!("Metrics server terminated.");
+                },
+                Err(e) => eprintln!("Error (metrics): {:?}", e),
+            }
+        }
+        result.unwrap_or_else(|e| Err(format!("the IO loop panicked: {:?}", e)))
+    }
+}
+
+impl Crawler {
+    /// Returns a builder of a Crawler with the default settings.
+    pub fn builder() -> CrawlerBuilder {
+        CrawlerBuilder{
+            config: config::CrawlConfig::default(),
+            seeds: None,
+            processors: Vec::new(),
+            handle_signals: false,
+        }
+    }
+
+    /// Returns the settings of the crawl.
+    pub fn config(&self) -> &config::CrawlConfig {
+        &self.config
+    }
+
+    /// Starts the threads of the crawl and returns a handle of it. Fails if the
+    /// state of a crawl to be resumed, the tls settings or the fetch log cannot be loaded.
+    pub fn start(self) -> Result<CrawlerHandle, String> {
+        let config=self.config;
+        let processors=self.processors;
+
+        // Stop the crawl gracefully on SIGINT and SIGTERM, if asked to.
+        let shutdown=shutdown::Shutdown::new();
+        if self.handle_signals {
+            shutdown.register_signals().map_err(|e| format!("cannot handle signals: {:?}", e))?;
+        }
+        let paused=sync::Arc::new(sync::atomic::AtomicBool::new(false));
+
+        // Load the saved state of a previous crawl, if it is to be resumed.
+        let checkpoint=if config.resume {
+            match checkpoint::read_checkpoint(config.checkpoint_filename.as_str(), config.reservoir_size) {
+                Ok(checkpoint) => Some(checkpoint),
+                Err(e) => return Err(format!("cannot resume from {:?}: {:?}", config.checkpoint_filename, e)),
+            }
+        } else {
+            None
+        };
+        // Seeds differing from the saved ones (in any order) hint at the checkpoint of another crawl.
+        if let Some(ref checkpoint)=checkpoint {
+            let (mut saved_seeds, mut seeds)=(checkpoint.seeds.clone(), config.seeds.clone());
+            saved_seeds.sort();
+            seeds.sort();
+            if saved_seeds!=seeds {
+                return Err(format!("cannot resume from {:?}: it was started from the seeds {:?}, not {:?}", config.checkpoint_filename, checkpoint.seeds, config.seeds));
+            }
+        }
+        let counter_start=|name| checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.counter(name));
+
+        // Load the tls settings of the client speaking https.
+        let tls_ca_file=if config.tls_ca_file.is_empty() {None} else {Some(config.tls_ca_file.as_str())};
+        let tls_config=tls::client_config(config.tls_verify_certificates, tls_ca_file)?;
+
+        // Define atomic variables to keep track of some stats.
+        let css_written=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("css_written")));
+        let htmls_crawled=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("htmls_crawled")));
+        let urls_enqueued=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("urls_enqueued")));
+        let urls_gotten=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("urls_gotten")));
+        let retries_enqueued=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("retries_enqueued")));
+        let retries_gotten=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("retries_gotten")));
+        let urls_processed=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("urls_processed")));
+        let urls_timed_out=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("urls_timed_out")));
+        let urls_oversized=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("urls_oversized")));
+        let urls_disallowed=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("urls_disallowed")));
+        let pages_noindex=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("pages_noindex")));
+        let pages_nofollow=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("pages_nofollow")));
+        let links_nofollow=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("links_nofollow")));
+        let pages_rescued=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("pages_rescued")));
+        let redirects_followed=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("redirects_followed")));
+        let redirects_deferred=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("redirects_deferred")));
+        let redirects_known=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("redirects_known")));
+        let redirects_broken=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("redirects_broken")));
+        let redirects_out_of_scope=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("redirects_out_of_scope")));
+        let urls_too_deep=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("urls_too_deep")));
+        let http_succeeded=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("http_succeeded")));
+        let http_failed=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("http_failed")));
+        let https_succeeded=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("https_succeeded")));
+        let https_failed=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("https_failed")));
+        let fetches_retried=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("fetches_retried")));
+        let fetches_given_up=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("fetches_given_up")));
+        let fetches_failed_permanently=sync::Arc::new(sync::atomic::AtomicUsize::new(counter_start("fetches_failed_permanently")));
+        let errors=sync::Arc::new(crawl_error::ErrorCounters::new(|name: &str| checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.counter(name))));
+        let mut counters=vec![
+            ("css_written", css_written.clone()),
+            ("htmls_crawled", htmls_crawled.clone()),
+            ("urls_enqueued", urls_enqueued.clone()),
+            ("urls_gotten", urls_gotten.clone()),
+            ("retries_enqueued", retries_enqueued.clone()),
+            ("retries_gotten", retries_gotten.clone()),
+            ("urls_processed", urls_processed.clone()),
+            ("urls_timed_out", urls_timed_out.clone()),
+            ("urls_oversized", urls_oversized.clone()),
+            ("urls_disallowed", urls_disallowed.clone()),
+            ("pages_noindex", pages_noindex.clone()),
+            ("pages_nofollow", pages_nofollow.clone()),
+            ("links_nofollow", links_nofollow.clone()),
+            ("pages_rescued", pages_rescued.clone()),
+            ("redirects_followed", redirects_followed.clone()),
+            ("redirects_deferred", redirects_deferred.clone()),
+            ("redirects_known", redirects_known.clone()),
+            ("redirects_broken", redirects_broken.clone()),
+            ("redirects_out_of_scope", redirects_out_of_scope.clone()),
+            ("urls_too_deep", urls_too_deep.clone()),
+            ("http_succeeded", http_succeeded.clone()),
+            ("http_failed", http_failed.clone()),
+            ("https_succeeded", https_succeeded.clone()),
+            ("https_failed", https_failed.clone()),
+            ("fetches_retried", fetches_retried.clone()),
+            ("fetches_given_up", fetches_given_up.clone()),
+            ("fetches_failed_permanently", fetches_failed_permanently.clone()),
+        ];
+        counters.extend(errors.named());
+        let depths=sync::Arc::new(url_meta::DepthCounters::new(|name: &str| checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.counter(name))));
+        counters.extend(depths.named());
+
+        // Define the canonicalizer urls are rewritten with where they enter the crawl, so that the spellings of an url are not crawled twice.
+        let canonicalizer=sync::Arc::new(canonical::Canonicalizer::new(&config, |name: &str| checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.counter(name))));
+        counters.extend(canonicalizer.named());
+
+        // Define the scope of the crawl, which urls are checked against before they enter the frontier.
+        let scope=sync::Arc::new(scope::Scope::new(&config, |name: &str| checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.counter(name)))?);
+        counters.extend(scope.named());
+
+        // Start from the (canonical) seeds within the scope.
+        let mut seeds=config.seeds.clone();
+        if checkpoint.is_none() {
+            canonicalizer.canonicalize_all(&mut seeds);
+            seeds.retain(|seed| scope.allows(seed));
+            if seeds.is_empty() {
+                return Err("all seeds are out of scope".to_string());
+            }
+        }
+
+        // Define a bloom filter and frontier to keep track of used urls and store them respectively, and a bloom filter to keep
+        // track of the css code saved (or take them from the checkpoint, in the kind of frontier asked for).
+        let frontier_kind=frontier::FrontierKind::from_name(config.frontier.as_str()).unwrap_or(frontier::FrontierKind::Reservoir);
+        let (bloom_filter, css_bloom_filter, frontier, pending)=match checkpoint {
+            Some(checkpoint) => {
+                if checkpoint.frontier.kind()!=frontier_kind {
+                    eprintln!("Moving the urls of the {} frontier of the checkpoint to a {} frontier.", checkpoint.frontier.kind().name(), frontier_kind.name());
+                }
+                // The scope may have changed since the checkpoint was written, so the urls restored are checked against it again.
+                let mut frontier=checkpoint.frontier.into_kind(frontier_kind, config.reservoir_size, rand::StdRng::new().unwrap());
+                frontier.retain(|url| scope.allows(url));
+                let pending=checkpoint.pending.into_iter().filter(|&(ref url, _, _)| scope.allows(url)).collect();
+                (checkpoint.bloom_filter, checkpoint.css_bloom_filter, frontier, pending)
+            },
+            None => (
+                bloom_filter::ConcurrentBloomFilter::new(config.bloom_expected_urls, config.bloom_false_positive_rate, 0xb77c92ec),
+                css_processor::new_bloom_filter(),
+                frontier::Frontier::new(frontier_kind, seeds, config.reservoir_size, rand::StdRng::new().unwrap()),
+                Vec::new(),
+            ),
+        };
+        let bloom_filter=sync::Arc::new(bloom_filter);
+        let css_bloom_filter=sync::Arc::new(css_bloom_filter);
+        let frontier=sync::Arc::new(sync::Mutex::new(frontier));
+
+        // Define a cache for the robots.txt rules of the hosts being crawled.
+        let robots_cache=sync::Arc::new(sync::Mutex::new(robots::RobotsCache::new(config.robots_cache_size, time::Duration::from_millis(2*config.get_timeout_millis))));
+
+        // Define a scheduler that spaces out the requests to each host.
+        let host_scheduler=sync::Arc::new(sync::Mutex::new(host_scheduler::HostScheduler::new(
+            time::Duration::from_millis(config.min_host_delay_millis),
+            time::Duration::from_millis(config.max_crawl_delay_millis),
+            config.max_host_connections,
+            config.max_ip_connections,
+        )));
+
+        // Define a queue for the failed fetches to be tried again.
+        let retry_queue=sync::Arc::new(sync::Mutex::new(retry::RetryQueue::new(
+            retry::RetryPolicy{
+                max_attempts: config.retry_max_attempts,
+                base_delay: time::Duration::from_millis(config.retry_base_delay_millis),
+                max_delay: time::Duration::from_millis(config.retry_max_delay_millis),
+            },
+            config.max_retry_queue_size,
+        )));
+
+        // Keep track of the urls taken from the frontier until they are done with, and hand out those
+        // of the checkpoint again, which were neither in its frontier nor fetched when it was written.
+        let outstanding=sync::Arc::new(outstanding::Outstanding::new());
+        match retry_queue.lock() {
+            Ok(mut mutex_guard) => mutex_guard.restore(pending, time::Instant::now()),
+            Err(e) => eprintln!("Error (retry): {:?}", e),
+        }
+
+        // Define channel sink/stream pairs for uris and robots.txt uris to be gotten by the hyper::client::Client.
+        let (uri_sink, uri_stream)=futures::sync::mpsc::channel::<(hyper::Uri, url_meta::UrlMeta)>(config.channel_buffer_size);
+        let (robots_sink, robots_stream)=futures::sync::mpsc::channel::<hyper::Uri>(config.channel_buffer_size);
+
+        // Start the writer of the fetch log, if there is to be one.
+        let fetch_log=if config.fetch_log_filename.is_empty() {
+            None
+        } else {
+            match fetch_log::FetchLog::start(config.fetch_log_filename.as_str(), config.fetch_log_max_bytes, config.fetch_log_max_files, config.fetch_log_buffer_size) {
+                Ok(fetch_log) => Some(fetch_log),
+                Err(e) => return Err(format!("cannot open {:?}: {:?}", config.fetch_log_filename, e)),
+            }
+        };
+
+        // Keep the counters and the shared structures at hand, to watch the crawl through.
+        let state=SharedState{
+            counters: counters.clone(),
+            frontier: frontier.clone(),
+            host_scheduler: host_scheduler.clone(),
+            retry_queue: retry_queue.clone(),
+            robots_cache: robots_cache.clone(),
+            bloom_filter: bloom_filter.clone(),
+            fetch_log: fetch_log.clone(),
+        };
+
+        // Serve the counters, the sizes of the shared structures and the fetch latencies to prometheus, if asked to.
+        let latencies=sync::Arc::new(metrics::FetchLatencies::new());
+        let metrics_thread=if let Ok(address)=config.metrics_address.parse() {
+            let latencies=latencies.clone();
+            let state=state.clone();
+            Some(metrics::serve(address, move || {
+                let gauges=match state.gauges() {
+                    Ok(gauges) => gauges,
+                    Err(e) => {
+                        eprintln!("Error (metrics): {:?}", e);
+                        report::Gauges::default()
+                    },
+                };
+                let gauges=[
+                    ("reservoir_urls", "Urls in the reservoir.", gauges.reservoir_urls as f64),
+                    ("scheduled_urls", "Urls waiting for their host to allow a request.", gauges.scheduled_urls as f64),
+                    ("scheduled_hosts", "Hosts with urls waiting.", gauges.scheduled_hosts as f64),
+                    ("retries_waiting", "Failed fetches waiting to be tried again.", gauges.retries_waiting as f64),
+                    ("robots_cached", "Hosts whose robots.txt rules are cached.", gauges.robots_cached as f64),
+                    ("bloom_filter_fill_ratio", "Fraction of the bits of the bloom filter that are set.", gauges.bloom_fill_ratio),
+                    ("bloom_filter_urls", "Approximate number of urls in the bloom filter.", gauges.bloom_urls as f64),
+                    ("bloom_filter_false_positive_probability", "Estimated false positive probability of the bloom filter.", gauges.bloom_false_positive_probability),
+                    ("fetch_log_dropped", "Fetch log records dropped because the writer was behind.", gauges.fetch_log_dropped as f64),
+                ];
+                metrics::render(&state.counters, &gauges, &latencies)
+            }, shutdown.clone()))
+        } else {
+            None
+        };
+
+        // Run the html, css and user processors concurrently, each on a thread of its own. Links to
+        // resources other than pages (stylesheets, scripts, images...) are only followed if some processor takes them,
+        // and the priority frontier no longer scores them down.
+        let dispatcher={
+            let css_processor=css_processor::CssProcessor::new(css_written.clone(), pages_noindex.clone(), pages_rescued.clone(), css_bloom_filter.clone(), config.clone());
+            let mut wanted=processor::ContentProcessor::mime_types(&css_processor);
+            for processor in processors.iter() {
+                wanted.extend(processor.mime_types());
+            }
+
+            let wanted=processor::MimeMatcher::new(wanted);
+            frontier.lock().unwrap().lift_binary_hint(|mime| wanted.matches(mime));
+
+            let mut registry=processor::ProcessorRegistry::new();
+            registry.register(Box::new(html_processor::HtmlProcessor::new(htmls_crawled.clone(), pages_noindex.clone(), pages_nofollow.clone(), links_nofollow.clone(), pages_rescued.clone(), errors.clone(), wanted, config.clone())));
+            registry.register(Box::new(css_processor));
+            for processor in processors {
+                registry.register(processor);
+            }
+            registry.start(processor::Sinks{
+                bloom_filter: bloom_filter.clone(),
+                canonicalizer: canonicalizer.clone(),
+                scope: scope.clone(),
+                frontier: frontier.clone(),
+                errors: errors.clone(),
+                fetch_log: fetch_log.clone(),
+                max_depth: config.max_depth,
+                urls_too_deep: urls_too_deep.clone(),
+                verbose: config.verbose,
+            })
+        };
+
+        // Run a checkpointer that saves the state of the crawl concurrently, until the crawl is asked to stop
+        // (it is joined before the final checkpoint, which it must not overlap).
+        let checkpointer=if config.checkpoint_interval_secs>0 {
+            let bloom_filter=bloom_filter.clone();
+            let css_bloom_filter=css_bloom_filter.clone();
+            let frontier=frontier.clone();
+            let outstanding=outstanding.clone();
+            let retry_queue=retry_queue.clone();
+            let counters=counters.clone();
+            let shutdown=shutdown.clone();
+            let config=config.clone();
+            Some(thread::spawn(move || {
+                let checkpoint_interval=time::Duration::from_secs(config.checkpoint_interval_secs);
+                while !shutdown.wait_timeout(checkpoint_interval) {
+                    match checkpoint::write_checkpoint(config.checkpoint_filename.as_str(), &bloom_filter, &css_bloom_filter, &frontier, &outstanding, &retry_queue, &load_counters(&counters), &config.seeds) {
+                        Ok(_) => {},
+                        Err(e) => eprintln!("Error (checkpoint): {:?}", e),
+                    }
+                }
+            }))
+        } else {
+            None
+        };
+
+        // Run `url_enqueuer` concurrently.
+        let url_enqueuer_thread={
+            let bloom_filter=bloom_filter.clone();
+            let canonicalizer=canonicalizer.clone();
+            let urls_enqueued=urls_enqueued.clone();
+            let retries_enqueued=retries_enqueued.clone();
+            let urls_disallowed=urls_disallowed.clone();
+            let frontier=frontier.clone();
+            let outstanding=outstanding.clone();
+            let robots_cache=robots_cache.clone();
+            let host_scheduler=host_scheduler.clone();
+            let retry_queue=retry_queue.clone();
+            let errors=errors.clone();
+            let paused=paused.clone();
+            let shutdown=shutdown.clone();
+            let config=config.clone();
+            thread::spawn(move || {
+                url_enqueuer::url_enqueuer(uri_sink, robots_sink, urls_enqueued, retries_enqueued, urls_disallowed, bloom_filter, canonicalizer, frontier, outstanding, retry_queue, robots_cache, host_scheduler, errors, paused, shutdown, config);
+            })
+        };
+
+        // Run a reporter that reports on the crawl concurrently (until the final report is due), starting from the counters of a resumed crawl, if any.
+        let reporter=sync::Arc::new(sync::Mutex::new(report::Reporter::new(&load_counters(&counters))));
+        {
+            let reporter=reporter.clone();
+            let state=state.clone();
+            let errors=errors.clone();
+            let shutdown=shutdown.clone();
+            let config=config.clone();
+            thread::spawn(move || {
+                let sleep_duration_per_iter=time::Duration::from_millis(config.sleep_millis_between_reports);
+                loop {
+                    thread::sleep(sleep_duration_per_iter);
+                    if shutdown.is_requested() {
+                        break;
+                    }
+                    let gauges=match state.gauges() {
+                        Ok(gauges) => gauges,
+                        Err(e) => {eprintln!("Error (reporting): {:?}", e);break;},
+                    };
+
+                    let report=match reporter.lock() {
+                        Ok(mut mutex_guard) => mutex_guard.report(&state.counters(), gauges, errors.summary(), false),
+                        Err(e) => {eprintln!("Error (reporting): {:?}", e);break;},
+                    };
+
+                    match report::write_report(&report, config.report_filename.as_str(), config.report_json_filename.as_str()) {
+                        Ok(_) => {},
+                        Err(e) => eprintln!("Error (reporting): {:?}", e),
+                    }
+                }
+                if config.verbose {
+                    eprintln!("Reporter terminated.");
+                }
+            });
+        }
+
+        // Run the IO loop on a thread of its own, which stops the crawl once it is done.
+        let verbose=config.verbose;
+        let io_thread={
+            let state=state.clone();
+            let shutdown=shutdown.clone();
+            thread::spawn(move || {
+            // Define tokio Core and client (speaking both http and https) to be used in/as IO loop.
+            let mut core = tokio_core::reactor::Core::new().unwrap();
+            let handle = core.handle();
+            // let client = hyper::Client::new(&handle);
+            let client=hyper::Client::configure()
+            .connector(tls::HttpsConnector::new(tls_config, &handle))
+            .keep_alive(false)
+            // .keep_alive_timeout(Some(time::Duration::from_millis(config.get_timeout_millis)))
+            .build(&handle);
+
+            // Prepare work for the core.
+            let get_timeout_duration=time::Duration::from_millis(config.get_timeout_millis);
+            let user_agent=config.user_agent.as_str();
+            let work=uri_stream
+            .map(|(uri, meta)|{
+                let uri_string=uri.to_string();
+                // Retries of failed fetches are counted apart, so that urls are not counted as gotten twice.
+                let retrying=match retry_queue.lock() {
+                    Ok(mutex_guard) => mutex_guard.attempts(uri_string.as_str())>0,
+                    Err(e) => {eprintln!("Error (retry): {:?}", e);false},
+                };
+                if retrying {
+                    retries_gotten.fetch_add(1, sync::atomic::Ordering::Relaxed);
+                } else {
+                    urls_gotten.fetch_add(1, sync::atomic::Ordering::Relaxed);
+                    depths.count(meta.depth);
+                }
+                // println!("{}, {}", c, uri.host().unwrap_or(""));
+
+                let deadline=rc::Rc::new(cell::Cell::new(time::Instant::now()+get_timeout_duration));
+                let timeout=FetchTimeout{timeout: get_timeout(get_timeout_duration, &handle), deadline: deadline.clone()};
+                let event=fetch_log::FetchEvent::new(uri_string.clone(), &meta);
+                let host=host_scheduler::host_key(&uri);
+
+                // Follow redirects within the same origin, keeping track of the urls requested. Each
+                // redirect waits until the host allows another request, as the scheduler would have it.
+                let chain=url::Url::parse(uri_string.as_str()).ok().map(|start| redirect::RedirectChain::new(start, config.max_redirects));
+                let (client, canonicalizer, scope, bloom_filter, robots_cache, frontier, redirects_followed, hop_scheduler, handle)=(&client, &canonicalizer, &scope, &bloom_filter, &robots_cache, &frontier, &redirects_followed, &host_scheduler, &handle);
+                // The url last requested is the one tried again if the fetch fails, as those before it were answered with redirects.
+                let last_requested=rc::Rc::new(cell::RefCell::new(uri_string.clone()));
+                let (chain_meta, chain_host, chain_last_requested)=(meta.clone(), host.clone(), last_requested.clone());
+                futures::future::loop_fn((uri, chain, None), move |(uri, chain, wait_until)| {
+                    let (meta, host, deadline, last_requested)=(chain_meta.clone(), chain_host.clone(), deadline.clone(), chain_last_requested.clone());
+                    let wait=match wait_until {
+                        Some(at) => futures::future::Either::A(get_timeout_at(at, handle).map_err(hyper::Error::from)),
+                        None => futures::future::Either::B(futures::future::ok(())),
+                    };
+                    wait.and_then(move |_| client.request(get_request(uri, user_agent)))
+                    .and_then(move |res| {
+                        if !redirect::is_redirect(res.status()) {
+                            return Ok(futures::future::Loop::Break((res, chain)));
+                        }
+
+                        let mut chain=chain;
+                        let next=match chain.as_mut() {
+                            Some(chain) => redirect::next_hop(&res, chain, &meta, canonicalizer, scope, bloom_filter, robots_cache, frontier),
+                            None => Err(redirect::RedirectError::MissingLocation),
+                        };
+                        match next {
+                            Ok(uri) => {
+                                redirects_followed.fetch_add(1, sync::atomic::Ordering::Relaxed);
+                                let now=time::Instant::now();
+                                let at=match (host, hop_scheduler.lock()) {
+                                    (Some(host), Ok(mut mutex_guard)) => mutex_guard.hop(host.as_str(), now),
+                                    (_, Err(e)) => {eprintln!("Error (host_scheduler): {:?}", e);now},
+                                    (None, _) => now,
+                                };
+                                if at>now {
+                                    deadline.set(deadline.get()+(at-now));
+                                }
+                                *last_requested.borrow_mut()=uri.to_string();
+                                Ok(futures::future::Loop::Continue((uri, chain, Some(at))))
+                            },
+                            Err(e) => Err(hyper::Error::from(io::Error::new(io::ErrorKind::Other, e))),
+                        }
+                    })
+                })
+                .and_then(|(res, chain)| {
+                    // Responses with an error status are not processed, but may be tried again.
+                    if let Some(failure)=retry::Failure::from_status(res.status(), res.headers().get::<hyper::header::RetryAfter>()) {
+                        return futures::future::Either::A(futures::future::err(hyper::Error::from(io::Error::new(io::ErrorKind::Other, failure))));
+                    }
+
+                    let mime=res.headers().get::<hyper::header::ContentType>().map(|content_type| format!("{}/{}", content_type.type_(), content_type.subtype()));
+                    let headers=res.headers().clone();
+
+                    // Only bodies some processor wants are of use, so others are not downloaded at all.
+                    let max_bytes=match mime.as_ref().and_then(|mime| dispatcher.max_bytes(mime)) {
+                        Some(max_bytes) => max_bytes,
+                        None => return futures::future::Either::A(futures::future::ok(Fetched{status: u16::from(res.status()), mime: mime, headers: headers, chain: chain, body: bytes::Bytes::new()})),
+                    };
+
+                    // Abort downloads announcing a larger body than allowed before reading any of it.
+                    let content_length=res.headers().get::<hyper::header::ContentLength>().map(|content_length| content_length.0);
+                    if content_length.map_or(false, |content_length| content_length>max_bytes as u64) {
+                        return futures::future::Either::A(futures::future::err(hyper::Error::from(io::Error::new(io::ErrorKind::Other, body_decoder::BodyTooLarge))));
+                    }
+
+                    // Decode the body as it arrives, aborting the download as soon as it grows beyond the limit.
+                    let content_encoding=res.headers().get_raw("Content-Encoding").map(|raw| raw.iter().map(|line| String::from_utf8_lossy(line).into_owned()).collect::<Vec<_>>().join(","));
+                    let decoder=match body_decoder::ContentEncoding::from_header(content_encoding.as_ref().map(|e| e.as_str())) {
+                        Ok(encoding) => body_decoder::BodyDecoder::new(encoding, max_bytes),
+                        Err(e) => return futures::future::Either::A(futures::future::err(hyper::Error::from(io::Error::new(io::ErrorKind::InvalidData, e)))),
+                    };
+
+                    let status=u16::from(res.status());
+                    futures::future::Either::B(res.body()
+                    .fold(decoder, |mut decoder, chunk| decoder.write(&chunk).map(|_| decoder))
+                    .and_then(|decoder| decoder.finish().map_err(hyper::Error::from))
+                    // The decoded bytes are handed over to the workers without being copied.
+                    .map(move |body| Fetched{status: status, mime: mime, headers: headers, chain: chain, body: bytes::Bytes::from(body)}))
+                })
+                .select2(timeout)
+                .then(|t| {
+                    // Let the scheduler know the host has one request less in flight.
+                    if let Some(host)=host {
+                        match host_scheduler.lock() {
+                            Ok(mut mutex_guard) => mutex_guard.finish(host.as_str()),
+                            Err(e) => eprintln!("Error (host_scheduler): {:?}", e),
+                        }
+                    }
+
+                    // Keep track per scheme of the requests answered (redirects to urls not followed included) and those that were not.
+                    let succeeded=match t {
+                        Ok(futures::future::Either::A(_)) => true,
+                        Err(futures::future::Either::A((hyper::Error::Io(ref e), _))) => redirect::redirect_error(e).map_or(false, |e| !e.is_broken_chain()),
+                        _ => false,
+                    };
+                    let counter=match (uri_string.starts_with("https:"), succeeded) {
+                        (false, true) => &http_succeeded,
+                        (false, false) => &http_failed,
+                        (true, true) => &https_succeeded,
+                        (true, false) => &https_failed,
+                    };
+                    counter.fetch_add(1, sync::atomic::Ordering::Relaxed);
+
+                    // Try failed fetches again later if what made them fail may go away.
+                    let failure=match t {
+                        Ok(futures::future::Either::A(_)) => None,
+                        Ok(futures::future::Either::B(_)) => Some(retry::Failure::Timeout),
+                        Err(futures::future::Either::A((hyper::Error::Io(ref e), _))) if body_decoder::is_too_large(e) || redirect::redirect_error(e).is_some() => None,
+                        Err(futures::future::Either::A((ref get_error, _))) => Some(retry::Failure::from_error(get_error)),
+                        Err(futures::future::Either::B(_)) => None,
+                    };
+                    let last_requested=last_requested;
+                    let last_requested=last_requested.borrow().clone();
+                    match (retry_queue.lock(), failure) {
+                        (Ok(mut mutex_guard), Some(failure)) => {
+                            let counter=match mutex_guard.failed(last_requested, meta.clone(), failure, time::Instant::now()) {
+                                retry::RetryDecision::Retry(_) => &fetches_retried,
+                                retry::RetryDecision::GiveUp => &fetches_given_up,
+                                retry::RetryDecision::Permanent => &fetches_failed_permanently,
+                            };
+                            counter.fetch_add(1, sync::atomic::Ordering::Relaxed);
+                        },
+                        (Ok(mut mutex_guard), None) => mutex_guard.succeeded(last_requested.as_str()),
+                        (Err(e), _) => eprintln!("Error (retry): {:?}", e),
+                    }
+                    // Once in the retry queue, if at all, the url is no longer outstanding.
+                    outstanding.done(uri_string.as_str());
+
+                    // Describe the fetch for the fetch log, and count its latency.
+                    let event=event.finished();
+                    latencies.observe(time::Duration::from_millis(event.latency_millis), succeeded);
+                    let mut event=Some(event);
+
+                    // Log and count the pages lost, along with the url they were lost at.
+                    let (uri_string, meta)=(uri_string, meta);
+                    let lost=|e: CrawlError, event: &mut Option<fetch_log::FetchEvent>| {
+                        eprintln!("Error (fetch): {} ({:?})", e, uri_string);
+                        errors.count(&e);
+                        if let Some(ref mut event)=*event {
+                            event.outcome=e.name();
+                            if let CrawlError::Status(status)=e {
+                                event.status=Some(status);
+                            }
+                        }
+                    };
+                    match t {
+                        Ok(futures::future::Either::B((_, _))) => {lost(CrawlError::Timeout, &mut event);urls_timed_out.fetch_add(1, sync::atomic::Ordering::Relaxed);},
+                        Err(futures::future::Either::A((hyper::Error::Io(ref e), _))) if body_decoder::is_too_large(e) => {lost(CrawlError::BodyTooLarge, &mut event);urls_oversized.fetch_add(1, sync::atomic::Ordering::Relaxed);},
+                        Err(futures::future::Either::A((hyper::Error::Io(ref e), _))) if redirect::redirect_error(e).is_some() => {
+                            let (counter, outcome)=match redirect::redirect_error(e) {
+                                Some(&redirect::RedirectError::Deferred(_)) => (&redirects_deferred, "redirect_deferred"),
+                                Some(&redirect::RedirectError::AlreadySeen(_)) => (&redirects_known, "redirect_known"),
+                                Some(&redirect::RedirectError::Disallowed(_)) => (&urls_disallowed, "redirect_disallowed"),
+                                Some(&redirect::RedirectError::OutOfScope(_)) => (&redirects_out_of_scope, "redirect_out_of_scope"),
+                                Some(e) => {lost(CrawlError::BrokenRedirect(e.clone()), &mut event);(&redirects_broken, "fetch_redirect")},
+                                None => (&redirects_broken, "fetch_redirect"),
+                            };
+                            counter.fetch_add(1, sync::atomic::Ordering::Relaxed);
+                            if let Some(ref mut event)=event {
+                                event.outcome=outcome;
+                                event.redirect=redirect::redirect_error(e).and_then(|e| e.target()).map(|target| target.to_string());
+                            }
+                        },
+                        Err(futures::future::Either::A((get_error, _))) => lost(CrawlError::from_fetch_error(&get_error), &mut event),
+                        Err(futures::future::Either::B((timeout_error, _))) => lost(CrawlError::Fetch(format!("timer failed: {}", timeout_error)), &mut event),
+                        Ok(futures::future::Either::A((fetched, _))) => {
+                            let Fetched{status, mime, headers, chain, body}=fetched;
+                            urls_processed.fetch_add(1, sync::atomic::Ordering::Relaxed);
+                            if let Some(ref mut event)=event {
+                                event.status=Some(status);
+                                event.content_type=mime.clone();
+                                event.bytes=body.len();
+                            }
+                            // Links are resolved against the url the page was finally gotten from.
+                            match chain {
+                                Some(chain) => {
+                                    if chain.hops()>0 {
+                                        if let Some(ref mut event)=event {
+                                            event.redirect=Some(chain.current().to_string());
+                                        }
+                                    }
+                                    // The event is completed (and logged) by the first processor of the page, once it knows its links.
+                                    if let Some(ref mime)=mime {
+                                        match dispatcher.dispatch(chain.current().clone(), &meta, mime.as_str(), &headers, body, &mut event) {
+                                            Err(e) => lost(e, &mut event),
+                                            _ => {},
+                                        }
+                                    }
+                                },
+                                None => lost(CrawlError::InvalidUrl(uri_string.clone()), &mut event),
+                            }
+                        },
+                    }
+
+                    if let (Some(fetch_log), Some(event))=(fetch_log.as_ref(), event) {
+                        fetch_log.log(event);
+                    }
+                    Ok(())
+                })
+            })
+            .buffer_unordered(config.future_stream_buffer_size)
+            .for_each(|_| Ok(()));
+
+            // Prepare the fetching of robots.txt files, which are few enough to be spawned on the core as they come.
+            let robots_ttl=time::Duration::from_secs(config.robots_ttl_secs);
+            let robots_error_ttl=time::Duration::from_secs(config.robots_error_ttl_secs);
+            let robots_work=robots_stream
+            .for_each(|uri| {
+                let origin=match robots::origin(&uri) {
+                    Some(origin) => origin,
+                    None => {errors.record("robots", &CrawlError::MissingOrigin);return Ok(());},
+                };
+                let robots_cache=robots_cache.clone();
+
+                handle.spawn(robots::fetch_robots(&client, get_request(uri, config.user_agent.as_str()), config.user_agent.clone(), get_timeout_duration, &handle)
+                .then(move |robots| {
+                    // Hosts whose robots.txt is unreachable are not crawled until it is fetched again.
+                    match robots_cache.lock() {
+                        Ok(mut mutex_guard) => match robots {
+                            Ok(Some(robots)) => mutex_guard.insert(origin, robots, robots_ttl),
+                            _ => mutex_guard.insert_unreachable(origin, robots_error_ttl),
+                        },
+                        Err(e) => eprintln!("Error (robots): {:?}", e),
+                    }
+                    Ok(())
+                }));
+                Ok(())
+            });
+
+
+            // Once the crawl is asked to stop, give the requests in flight some time to finish, then stop the IO loop regardless.
+            let (deadline_sender, deadline_receiver)=futures::sync::oneshot::channel::<()>();
+            {
+                let shutdown=shutdown.clone();
+                let shutdown_timeout=time::Duration::from_millis(config.shutdown_timeout_millis);
+                thread::spawn(move || {
+                    shutdown.wait(time::Duration::from_millis(100));
+                    if verbose {
+                        eprintln!("Shutting down: no more urls are enqueued, the requests in flight have {:?} to finish.", shutdown_timeout);
+                    }
+                    thread::sleep(shutdown_timeout);
+                    let _=deadline_sender.send(());
+                });
+            }
+
+            // Run work (operations on stream) in tokio core, until `url_enqueuer` stops and the requests in flight are done.
+            match core.run(work.join(robots_work).select2(deadline_receiver)) {
+                Ok(futures::future::Either::A((o, _))) => if config.verbose {
+                    eprintln!("Ok (Core.run): {:?}", o);
+                },
+                Ok(futures::future::Either::B(_)) => eprintln!("Error (Core.run): {:?}", "requests still in flight at the shutdown timeout were dropped"),
+                Err(futures::future::Either::A((e, _))) => eprintln!("Error (Core.run): {:?}", e),
+                Err(futures::future::Either::B((e, _))) => eprintln!("Error (Core.run): {:?}", e),
+            }
+
+            if config.verbose {
+                eprintln!("IO loop terminated.");
+            }
+
+            // Stop whatever still runs, and let the processors finish with the pages already sent to them.
+            // Once `url_enqueuer` is joined, every url it took is either back in the frontier or the
+            // retry queue, or still outstanding, and thus saved by the final checkpoint.
+            shutdown.request();
+            if let Err(e)=url_enqueuer_thread.join() {
+                eprintln!("Error (url_enqueuer): {:?}", e);
+            }
+            if let Some(checkpointer)=checkpointer {
+                if let Err(e)=checkpointer.join() {
+                    eprintln!("Error (checkpoint): {:?}", e);
+                }
+            }
+            dispatcher.finish();
+
+            // Report on the crawl one last time.
+            let gauges=state.gauges().unwrap_or_else(|e| {
+                eprintln!("Error (report): {:?}", e);
+                report::Gauges::default()
+            });
+            let report=match reporter.lock() {
+                Ok(mut mutex_guard) => {
+                    let report=mutex_guard.report(&state.counters(), gauges, errors.summary(), true);
+                    match report::write_report(&report, config.report_filename.as_str(), config.report_json_filename.as_str()) {
+                        Ok(_) => {},
+                        Err(e) => eprintln!("Error (report): {:?}", e),
+                    }
+                    Ok(report)
+                },
+                Err(e) => Err(format!("cannot make the final report: {:?}", e)),
+            };
+
+            // Save the state of the crawl, so that it can be resumed.
+            match checkpoint::write_checkpoint(config.checkpoint_filename.as_str(), &bloom_filter, &css_bloom_filter, &frontier, &outstanding, &retry_queue, &load_counters(&counters), &config.seeds) {
+                Ok(_) => if config.verbose {
+                    eprintln!("Ok (checkpoint): {:?}", config.checkpoint_filename);
+                },
+                Err(e) => eprintln!("Error (checkpoint): {:?}", e),
+            }
+            report
+            })
+        };
+
+        Ok(CrawlerHandle{
+            state: state,
+            paused: paused,
+            shutdown: shutdown,
+            io_thread: io_thread,
+            metrics_thread: metrics_thread,
+            verbose: verbose,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net;
+
+    struct NoopProcessor;
+
+    impl processor::ContentProcessor for NoopProcessor {
+        fn name(&self) -> &'static str {
+            "noop"
+        }
+
+        fn mime_types(&self) -> Vec<String> {
+            vec!["*".to_string()]
+        }
+
+        fn max_bytes(&self) -> usize {
+            0
+        }
+
+        fn process(&mut self, _: &url::Url, _: &hyper::Headers, _: &[u8], _: &mut processor::Output) -> Result<(), CrawlError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_builder() {
+        let crawler=Crawler::builder()
+        .seeds(vec!["http://a.com", "http://b.com"])
+        .processor(NoopProcessor)
+        .build()
+        .unwrap();
+        assert_eq!(crawler.config().seeds, vec!["http://a.com".to_string(), "http://b.com".to_string()]);
+        assert_eq!(crawler.processors.len(), 1);
+        assert!(!crawler.handle_signals);
+
+        // The settings are validated, seeds included.
+        assert!(Crawler::builder().seeds(Vec::<String>::new()).build().is_err());
+        let mut config=config::CrawlConfig::default();
+        config.max_urls_per_iter=0;
+        assert!(Crawler::builder().config(config).build().is_err());
+
+        let stats=Stats{
+            counters: vec![("urls_gotten", 3)].into_iter().collect(),
+            gauges: report::Gauges::default(),
+            paused: false,
+        };
+        assert_eq!((stats.counter("urls_gotten"), stats.counter("css_written")), (3, 0));
+    }
+
+    /// Serves a site without robots.txt, answering each path with the media
+    /// type and body `page` returns for it, or with a 404 if it returns None.
+    fn serve_site<F: Fn(&str) -> Option<(&'static str, String)> + Send + 'static>(page: F) -> net::SocketAddr {
+        let listener=net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr=listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream=match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let mut buf=[0u8;4096];
+                let len=stream.read(&mut buf).unwrap_or(0);
+                let request=String::from_utf8_lossy(&buf[..len]).into_owned();
+                let path=request.split(' ').nth(1).unwrap_or("/").to_string();
+                let response=match page(path.as_str()) {
+                    Some((mime, body)) => format!("HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", mime, body.len(), body),
+                    None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                };
+                let _=stream.write_all(response.as_bytes());
+            }
+        });
+        addr
+    }
+
+    /// Returns settings crawling from `seed` quickly, with every file written within `dir`.
+    fn test_config(dir: &test_util::TempDir, seed: String) -> config::CrawlConfig {
+        let mut config=config::CrawlConfig::default();
+        config.seeds=vec![seed];
+        config.report_filename=dir.file("report.txt");
+        config.report_json_filename=String::new();
+        config.checkpoint_filename=dir.file("checkpoint.bin");
+        config.checkpoint_interval_secs=0;
+        config.css_directory=dir.file("css");
+        config.bloom_expected_urls=10_000;
+        config.reservoir_size=1000;
+        config.min_host_delay_millis=10;
+        config.sleep_millis_on_empty_reservoir=10;
+        config.sleep_millis_between_reports=50;
+        config.shutdown_timeout_millis=1000;
+        config
+    }
+
+    /// Polls `handle` until `done` holds for its stats, failing after 10 seconds.
+    fn wait_for<F: Fn(&Stats) -> bool>(handle: &CrawlerHandle, done: F) -> Stats {
+        for _ in 0..500 {
+            let stats=handle.stats().unwrap();
+            if done(&stats) {
+                return stats;
+            }
+            thread::sleep(time::Duration::from_millis(20));
+        }
+        panic!("timed out, stats: {:?}", handle.stats());
+    }
+
+    #[test]
+    fn test_crawl_local_server() {
+        let dir=test_util::TempDir::new("crawl");
+        // Page `/<n>` links to `/<n+1>`, so that the crawl never runs out of urls.
+        let addr=serve_site(|path| path[1..].parse::<usize>().ok().map(|n| ("text/html", format!("<html><body><a href=\"/{}\">next</a></body></html>", n+1))));
+
+        let handle=Crawler::builder().config(test_config(&dir, format!("http://{}/0", addr))).build().unwrap().start().unwrap();
+        let stats=wait_for(&handle, |stats| stats.counter("htmls_crawled")>=3);
+        assert!(stats.counter("urls_enqueued")>=stats.counter("urls_gotten") && stats.counter("urls_gotten")>=3);
+        assert!(!stats.paused && stats.gauges.bloom_urls>=3);
+
+        // Once paused (and the request in flight done), no more urls are gotten.
+        handle.pause();
+        assert!(handle.is_paused());
+        thread::sleep(time::Duration::from_millis(500));
+        let stats=handle.stats().unwrap();
+        assert!(stats.paused);
+        thread::sleep(time::Duration::from_millis(300));
+        assert_eq!(handle.stats().unwrap().counter("urls_gotten"), stats.counter("urls_gotten"));
+
+        handle.resume();
+        wait_for(&handle, |later| !later.paused && later.counter("urls_gotten")>stats.counter("urls_gotten"));
+
+        // Shutting down waits for the final report, which it returns, and checkpoint.
+        assert!(!handle.is_shutting_down());
+        let report=handle.shutdown().unwrap();
+        assert!(report.is_final && report.urls_gotten>stats.counter("urls_gotten"));
+        let checkpoint=checkpoint::read_checkpoint(dir.join("checkpoint.bin"), 1000).unwrap();
+        assert_eq!(checkpoint.counter("urls_gotten"), report.urls_gotten);
+        assert!(fs::metadata(dir.join("report.txt")).unwrap().len()>0);
+
+        // The crawl is only resumed from the same seeds.
+        let mut config=test_config(&dir, format!("http://{}/1", addr));
+        config.resume=true;
+        assert!(Crawler::builder().config(config).build().unwrap().start().is_err());
+        let mut config=test_config(&dir, format!("http://{}/0", addr));
+        config.resume=true;
+        let resumed=Crawler::builder().config(config).build().unwrap().start().unwrap().shutdown().unwrap();
+        assert!(resumed.urls_gotten>=report.urls_gotten);
+    }
+
+    /// Records the urls of the responses handed to it.
+    struct ImageProcessor {
+        urls: sync::Arc<sync::Mutex<Vec<String>>>,
+    }
+
+    impl processor::ContentProcessor for ImageProcessor {
+        fn name(&self) -> &'static str {
+            "image"
+        }
+
+        fn mime_types(&self) -> Vec<String> {
+            vec!["image/*".to_string()]
+        }
+
+        fn max_bytes(&self) -> usize {
+            1024
+        }
+
+        fn process(&mut self, url: &url::Url, _: &hyper::Headers, _: &[u8], _: &mut processor::Output) -> Result<(), CrawlError> {
+            self.urls.lock().unwrap().push(url.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_crawl_images() {
+        let dir=test_util::TempDir::new("crawl_images");
+        let requested=sync::Arc::new(sync::Mutex::new(Vec::new()));
+        let addr={
+            let requested=requested.clone();
+            serve_site(move |path| {
+                requested.lock().unwrap().push(path.to_string());
+                match path {
+                    "/" => Some(("text/html", "<html><body><script src=\"/app.js\"></script><img src=\"/logo.png\"></body></html>".to_string())),
+                    "/logo.png" => Some(("image/png", "png".to_string())),
+                    "/app.js" => Some(("application/javascript", "js".to_string())),
+                    _ => None,
+                }
+            })
+        };
+
+        // The images linked from crawled pages reach the processor taking them, while scripts, which none takes, are not fetched.
+        let urls=sync::Arc::new(sync::Mutex::new(Vec::new()));
+        let handle=Crawler::builder().config(test_config(&dir, format!("http://{}/", addr))).processor(ImageProcessor{urls: urls.clone()}).build().unwrap().start().unwrap();
+        wait_for(&handle, |stats| stats.counter("htmls_crawled")==1 && !urls.lock().unwrap().is_empty());
+        thread::sleep(time::Duration::from_millis(200));
+        handle.shutdown().unwrap();
+        assert_eq!(*urls.lock().unwrap(), vec![format!("http://{}/logo.png", addr)]);
+        assert!(!requested.lock().unwrap().contains(&"/app.js".to_string()));
+    }
+}
@@ -0,0 +1,162 @@
+use bloom_filter;
+use charset;
+use config;
+use crawl_error::CrawlError;
+use processor;
+use robots;
+use hyper;
+use regex;
+use url;
+use std::sync;
+use std::path;
+
+const MIN_CSS_LEN: usize = 50;
+const MIN_CSS_NEWLINES: usize = 5;
+const ALLOWED_CHARS: &str = "abcdefghijklmnopqrstuvwxzy0123456789\n\t\r \"'(){}[]+-*/.,:;_@#%$!?=\\<>~^|&`";
+
+
+/// Checks wether the input string `s` contains only allowed characters.
+///
+/// # Arguments
+///
+/// * `s` - String to be checked.
+///
+fn contains_only_allowed_chars(s: &String) -> bool{
+    s
+    .chars()
+    .all(|c| ALLOWED_CHARS.chars().any(|ca| ca==c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains_only_allowed_chars() {
+        assert!(contains_only_allowed_chars(&"hello".to_string()));
+        assert!(contains_only_allowed_chars(&"hello123".to_string()));
+        assert!(contains_only_allowed_chars(&"hello world !".to_string()));
+        assert!(contains_only_allowed_chars(&"fn a() -> bool {return true;}".to_string()));
+        assert!(contains_only_allowed_chars(&"0123456789,.-;:_[]@#! ?\"\n\t\r".to_string()));
+
+        assert!(!contains_only_allowed_chars(&"Hello".to_string()));
+        assert!(!contains_only_allowed_chars(&"helloª".to_string()));
+        assert!(!contains_only_allowed_chars(&"hello¨".to_string()));
+        assert!(!contains_only_allowed_chars(&"helloÇ".to_string()));
+        assert!(!contains_only_allowed_chars(&"ASD".to_string()));
+    }
+}
+
+
+/// Processor keeping css code that fulfills certain parameters (in an attempt to
+/// get nice looking ones only) and has not been obtained before, as files.
+pub struct CssProcessor {
+    css_written: sync::Arc<sync::atomic::AtomicUsize>,
+    pages_noindex: sync::Arc<sync::atomic::AtomicUsize>,
+    pages_rescued: sync::Arc<sync::atomic::AtomicUsize>,
+    config: sync::Arc<config::CrawlConfig>,
+    bloom_filter: sync::Arc<bloom_filter::ConcurrentBloomFilter>,
+    re_comments: regex::Regex,
+    re_breaklines: regex::Regex,
+}
+
+impl CssProcessor {
+    /// Creates and returns a new CssProcessor structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `css_written` - Atomic counter that counts the amount of css files saved.
+    /// * `pages_noindex` - Atomic counter that counts the css files not kept because of a noindex X-Robots-Tag header.
+    /// * `pages_rescued` - Atomic counter that counts the documents that were not valid utf8 but could be decoded.
+    /// * `bloom_filter` - ConcurrentBloomFilter that keeps track of the css code already saved (saved in checkpoints, see `new_bloom_filter`).
+    /// * `config` - Settings of the crawl (directory the css files are saved into, user agent and size limit).
+    pub fn new(css_written: sync::Arc<sync::atomic::AtomicUsize>, pages_noindex: sync::Arc<sync::atomic::AtomicUsize>, pages_rescued: sync::Arc<sync::atomic::AtomicUsize>, bloom_filter: sync::Arc<bloom_filter::ConcurrentBloomFilter>, config: sync::Arc<config::CrawlConfig>) -> CssProcessor {
+        CssProcessor{
+            css_written: css_written,
+            pages_noindex: pages_noindex,
+            pages_rescued: pages_rescued,
+            config: config,
+            bloom_filter: bloom_filter,
+            re_comments: regex::Regex::new(r"/\*(.|\n)*?\*/").unwrap(),
+            re_breaklines: regex::Regex::new(r"\n{3,}").unwrap(),
+        }
+    }
+}
+
+/// Creates and returns the bloom filter keeping track of the css code already
+/// saved, for a crawl that is not resumed.
+pub fn new_bloom_filter() -> bloom_filter::ConcurrentBloomFilter {
+    // Far fewer css files than urls are gathered, so a smaller filter with fewer false positives suffices.
+    bloom_filter::ConcurrentBloomFilter::new(10_000_000, 0.000001, 0x41be6a18)
+}
+
+impl processor::ContentProcessor for CssProcessor {
+    fn name(&self) -> &'static str {
+        "css_processor"
+    }
+
+    fn mime_types(&self) -> Vec<String> {
+        vec!["text/css".to_string()]
+    }
+
+    fn max_bytes(&self) -> usize {
+        self.config.max_css_bytes
+    }
+
+    fn process(&mut self, _: &url::Url, headers: &hyper::Headers, body: &[u8], output: &mut processor::Output) -> Result<(), CrawlError> {
+        // Nothing is kept of css code whose headers say noindex.
+        if robots::PageDirectives::from_headers(headers, self.config.user_agent.as_str()).noindex{
+            self.pages_noindex.fetch_add(1, sync::atomic::Ordering::Relaxed);
+            return Ok(());
+        }
+
+        // Transcode it into a utf8 String, discarding it if it cannot be decoded.
+        let header_charset=processor::header_charset(headers);
+        let decoded=charset::decode(body, header_charset.as_ref().map(|c| c.as_str()), charset::DocumentKind::Css);
+        if decoded.had_errors{
+            return Err(CrawlError::Charset(decoded.encoding.name()));
+        }
+        if decoded.rescued{
+            self.pages_rescued.fetch_add(1, sync::atomic::Ordering::Relaxed);
+        }
+        let mut css_content=decoded.text.into_owned();
+
+        // Transform to lower case.
+        css_content=css_content.to_lowercase();
+        if !contains_only_allowed_chars(&css_content){
+            return Err(CrawlError::DisallowedChars);
+        }
+
+        // Remove all comments, parts where there are too many newlines next to each other, and trim (strip in python).
+        css_content=String::from(self.re_comments.replace_all(css_content.as_str(), ""));
+        css_content=String::from(self.re_breaklines.replace_all(css_content.as_str(), "\n\n"));
+        css_content=css_content.trim().to_string();
+        // If the code is too small, discard it.
+        if css_content.len()<=MIN_CSS_LEN{
+            return Err(CrawlError::TooShort(MIN_CSS_LEN));
+        }
+
+        // If code has too few newlines, discard it.
+        if css_content.chars().filter(|&c| c=='\n').count()<MIN_CSS_NEWLINES{
+            return Err(CrawlError::TooFewNewlines(MIN_CSS_NEWLINES));
+        }
+
+        // If code was saved into a file before, discard it.
+        if self.bloom_filter.contains_add(css_content.as_bytes()){
+            return Err(CrawlError::AlreadyGathered);
+        }
+
+        // Have the code saved into a file, numbered after those written before (by a resumed crawl too). Only this thread counts them, once written.
+        let c=self.css_written.load(sync::atomic::Ordering::Relaxed)+1;
+        output.artifacts.push(processor::Artifact{
+            path: path::Path::new(self.config.css_directory.as_str()).join(format!("css{:06}.css", c)),
+            bytes: css_content.into_bytes(),
+        });
+        Ok(())
+    }
+
+    fn artifact_written(&mut self, _: &processor::Artifact) {
+        // Keep track of the number of css files with atomic counter `css_written`.
+        self.css_written.fetch_add(1, sync::atomic::Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,321 @@
+#![allow(dead_code)]
+
+use url_meta;
+use serde_json;
+use url;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path;
+use std::sync;
+use std::thread;
+use std::time;
+
+/// Record of a single fetch, written to the fetch log as a line of json.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FetchEvent {
+    /// Url requested.
+    pub url: String,
+    /// Host of `url`.
+    pub host: Option<String>,
+    /// Time the fetch started, in milliseconds since the unix epoch.
+    pub timestamp: u64,
+    /// Status code of the (last) response, if one was received.
+    pub status: Option<u16>,
+    /// Media type of the response, without its parameters.
+    pub content_type: Option<String>,
+    /// Size of the (decoded) body.
+    pub bytes: usize,
+    /// Time from the start of the fetch until it was done with (redirects included).
+    pub latency_millis: u64,
+    /// `ok`, the name of the CrawlError the page was lost to, or what became of a redirect not followed.
+    pub outcome: &'static str,
+    /// Url the fetch was redirected to last, if any.
+    pub redirect: Option<String>,
+    /// Number of links the first processor of the page found in it, if it looks for any.
+    pub links: Option<usize>,
+    /// Number of links followed from a seed to `url`.
+    pub depth: u16,
+    /// Url of the page `url` was found in, None for seeds.
+    pub parent: Option<String>,
+    /// Time the fetch started, to measure its latency with.
+    #[serde(skip)]
+    started: time::Instant,
+}
+
+impl FetchEvent {
+    /// Creates and returns a new FetchEvent of a successful fetch starting now,
+    /// whose other fields are to be filled in as they become known.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - Url requested.
+    /// * `meta` - UrlMeta of the url.
+    pub fn new(url: String, meta: &url_meta::UrlMeta) -> FetchEvent {
+        let started_at=time::SystemTime::now();
+        let host=url::Url::parse(url.as_str()).ok().and_then(|url| url.host_str().map(|host| host.to_string()));
+        FetchEvent{
+            url: url,
+            host: host,
+            timestamp: millis(started_at.duration_since(time::UNIX_EPOCH).unwrap_or(time::Duration::from_secs(0))),
+            status: None,
+            content_type: None,
+            bytes: 0,
+            latency_millis: 0,
+            outcome: "ok",
+            redirect: None,
+            links: None,
+            depth: meta.depth,
+            parent: meta.parent.as_ref().map(|parent| parent.to_string()),
+            started: time::Instant::now(),
+        }
+    }
+
+    /// Returns the event with its latency set to the time since it was created.
+    pub fn finished(mut self) -> FetchEvent {
+        self.latency_millis=millis(self.started.elapsed());
+        self
+    }
+}
+
+fn millis(duration: time::Duration) -> u64 {
+    duration.as_secs()*1000+(duration.subsec_nanos()/1_000_000) as u64
+}
+
+/// File that is rotated once it would grow beyond `max_bytes`: it is renamed to
+/// `path.1`, the previously rotated files shifting up to `path.2` and so on, the
+/// oldest beyond `max_files` being removed.
+pub struct RotatingWriter {
+    path: path::PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    w: io::BufWriter<fs::File>,
+    len: u64,
+}
+
+impl RotatingWriter {
+    /// Opens the file at `path` for appending, and returns a RotatingWriter writing to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the file.
+    /// * `max_bytes` - Size the file may reach before it is rotated.
+    /// * `max_files` - Number of rotated files kept.
+    pub fn open<P: AsRef<path::Path>>(path: P, max_bytes: u64, max_files: usize) -> io::Result<RotatingWriter> {
+        let path=path.as_ref().to_path_buf();
+        let f=fs::OpenOptions::new().append(true).create(true).open(&path)?;
+        let len=f.metadata()?.len();
+        Ok(RotatingWriter{
+            path: path,
+            max_bytes: max_bytes,
+            max_files: max_files,
+            w: io::BufWriter::new(f),
+            len: len,
+        })
+    }
+
+    /// Returns the path the `i`th rotated file is renamed to.
+    pub fn rotated_path(&self, i: usize) -> path::PathBuf {
+        let mut rotated_path=self.path.as_os_str().to_owned();
+        rotated_path.push(format!(".{}", i));
+        path::PathBuf::from(rotated_path)
+    }
+
+    /// Writes `line` followed by a newline, rotating the file first if it would
+    /// grow beyond its limit. Lines are never split between two files.
+    pub fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.len>0 && self.len+line.len() as u64+1>self.max_bytes {
+            self.rotate()?;
+        }
+        self.w.write_all(line)?;
+        self.w.write_all(b"\n")?;
+        self.len+=line.len() as u64+1;
+        Ok(())
+    }
+
+    /// Writes out the buffered lines.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.w.flush()?;
+        if self.max_files==0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            // Shift the rotated files up, dropping the oldest one.
+            for i in (1..self.max_files).rev() {
+                match fs::rename(self.rotated_path(i), self.rotated_path(i+1)) {
+                    Err(ref e) if e.kind()==io::ErrorKind::NotFound => {},
+                    r => r?,
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.w=io::BufWriter::new(fs::OpenOptions::new().write(true).create(true).truncate(true).open(&self.path)?);
+        self.len=0;
+        Ok(())
+    }
+}
+
+/// Handle through which fetches are logged. The records are written by a
+/// dedicated thread, so that logging never blocks: records that do not fit into
+/// its buffer are dropped (and counted) instead.
+#[derive(Clone)]
+pub struct FetchLog {
+    sender: sync::Arc<sync::Mutex<Option<sync::mpsc::SyncSender<FetchEvent>>>>,
+    writer: sync::Arc<sync::Mutex<Option<thread::JoinHandle<()>>>>,
+    dropped: sync::Arc<sync::atomic::AtomicUsize>,
+}
+
+impl FetchLog {
+    /// Opens the fetch log at `path` and starts the thread writing to it. The
+    /// thread terminates once `finish` is called.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the fetch log.
+    /// * `max_bytes` - Size the fetch log may reach before it is rotated.
+    /// * `max_files` - Number of rotated fetch logs kept.
+    /// * `buffer_size` - Number of records that may wait to be written.
+    pub fn start<P: AsRef<path::Path>>(path: P, max_bytes: u64, max_files: usize, buffer_size: usize) -> io::Result<FetchLog> {
+        let writer=RotatingWriter::open(path, max_bytes, max_files)?;
+        let (sender, receiver)=sync::mpsc::sync_channel(buffer_size);
+        let writer=thread::spawn(move || {
+            write_events(receiver, writer);
+        });
+        Ok(FetchLog{
+            sender: sync::Arc::new(sync::Mutex::new(Some(sender))),
+            writer: sync::Arc::new(sync::Mutex::new(Some(writer))),
+            dropped: sync::Arc::new(sync::atomic::AtomicUsize::new(0)),
+        })
+    }
+
+    /// Hands `event` to the writer thread, or drops it if the thread is behind
+    /// or finished.
+    pub fn log(&self, event: FetchEvent) {
+        let sent=match self.sender.lock() {
+            Ok(mutex_guard) => match *mutex_guard {
+                Some(ref sender) => sender.try_send(event).is_ok(),
+                None => false,
+            },
+            Err(e) => {eprintln!("Error (fetch_log): {:?}", e);false},
+        };
+        if !sent {
+            self.dropped.fetch_add(1, sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    /// Closes the fetch log and waits for the writer thread to write the
+    /// records still buffered. Records logged afterwards are dropped.
+    pub fn finish(&self) {
+        match self.sender.lock() {
+            Ok(mut mutex_guard) => {mutex_guard.take();},
+            Err(e) => eprintln!("Error (fetch_log): {:?}", e),
+        }
+        let writer=match self.writer.lock() {
+            Ok(mut mutex_guard) => mutex_guard.take(),
+            Err(e) => {eprintln!("Error (fetch_log): {:?}", e);None},
+        };
+        if let Some(writer)=writer {
+            if let Err(e)=writer.join() {
+                eprintln!("Error (fetch_log): {:?}", e);
+            }
+        }
+    }
+
+    /// Returns the number of records dropped so far.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(sync::atomic::Ordering::Relaxed)
+    }
+}
+
+/// Writes the events received through `receiver` with `writer`, flushing
+/// whenever no more are waiting.
+fn write_events(receiver: sync::mpsc::Receiver<FetchEvent>, mut writer: RotatingWriter) {
+    while let Ok(event)=receiver.recv() {
+        for event in Some(event).into_iter().chain(receiver.try_iter()) {
+            let written=serde_json::to_vec(&event)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            .and_then(|line| writer.write_line(&line));
+            match written {
+                Ok(_) => {},
+                Err(e) => eprintln!("Error (fetch_log): {:?}", e),
+            }
+        }
+
+        match writer.flush() {
+            Ok(_) => {},
+            Err(e) => eprintln!("Error (fetch_log): {:?}", e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util;
+    use std::io::Read;
+
+    fn read_to_string<P: AsRef<path::Path>>(path: P) -> String {
+        let mut s=String::new();
+        fs::File::open(path).unwrap().read_to_string(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn test_event() {
+        let meta=url_meta::UrlMeta::seed().child(sync::Arc::from("http://a.com:8080/"), 0);
+        let mut event=FetchEvent::new("http://a.com:8080/x".to_string(), &meta).finished();
+        assert_eq!(event.host, Some("a.com".to_string()));
+        assert!(event.timestamp>0 && event.latency_millis<1000);
+        event.timestamp=1500;
+        event.latency_millis=42;
+        event.status=Some(200);
+        event.links=Some(3);
+        assert_eq!(serde_json::to_string(&event).unwrap(), "{\"url\":\"http://a.com:8080/x\",\"host\":\"a.com\",\"timestamp\":1500,\"status\":200,\"content_type\":null,\"bytes\":0,\"latency_millis\":42,\"outcome\":\"ok\",\"redirect\":null,\"links\":3,\"depth\":1,\"parent\":\"http://a.com:8080/\"}");
+    }
+
+    #[test]
+    fn test_rotating_writer() {
+        let dir=test_util::TempDir::new("fetch_log");
+        let path=dir.join("fetch_log.jsonl");
+        let mut writer=RotatingWriter::open(&path, 10, 2).unwrap();
+        for line in ["aaaa", "bbbb", "cccc", "dddd", "eeeeeeeeeeee", "ffff"].iter() {
+            writer.write_line(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        // Lines longer than the limit still get a file of their own, and the oldest file is dropped.
+        assert_eq!(read_to_string(&path), "ffff\n");
+        assert_eq!(read_to_string(writer.rotated_path(1)), "eeeeeeeeeeee\n");
+        assert_eq!(read_to_string(writer.rotated_path(2)), "cccc\ndddd\n");
+        assert!(!writer.rotated_path(3).exists());
+
+        // Reopening appends to the current file.
+        drop(writer);
+        let mut writer=RotatingWriter::open(&path, 10, 2).unwrap();
+        writer.write_line(b"g").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(read_to_string(&path), "ffff\ng\n");
+        drop(writer);
+    }
+
+    #[test]
+    fn test_finish() {
+        let dir=test_util::TempDir::new("fetch_log_finish");
+        let path=dir.join("fetch_log.jsonl");
+        let fetch_log=FetchLog::start(&path, 1024*1024, 1, 16).unwrap();
+        let meta=url_meta::UrlMeta::seed();
+        fetch_log.clone().log(FetchEvent::new("http://a.com/".to_string(), &meta).finished());
+        fetch_log.finish();
+        assert_eq!(read_to_string(&path).lines().count(), 1);
+
+        // Records logged once the fetch log is finished are dropped.
+        fetch_log.log(FetchEvent::new("http://a.com/x".to_string(), &meta).finished());
+        assert_eq!(fetch_log.dropped(), 1);
+        fetch_log.finish();
+    }
+}
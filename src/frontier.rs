@@ -0,0 +1,232 @@
+#![allow(dead_code)]
+use checkpoint;
+use priority_frontier;
+use url_meta;
+use url_reservoir;
+use rand;
+use std::io;
+
+/// Kinds of frontier the urls waiting to be crawled can be kept in.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum FrontierKind {
+    /// UrlReservoir: urls are retrieved, and replaced when it is full, at random.
+    Reservoir,
+    /// PriorityFrontier: urls are retrieved highest scored first, and the lowest scored are dropped when it is full.
+    Priority,
+}
+
+impl FrontierKind {
+    /// Returns the kind named `name` (as in the `frontier` setting), or None if there is none.
+    pub fn from_name(name: &str) -> Option<FrontierKind> {
+        match name {
+            "reservoir" => Some(FrontierKind::Reservoir),
+            "priority" => Some(FrontierKind::Priority),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the kind, as in the `frontier` setting.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            FrontierKind::Reservoir => "reservoir",
+            FrontierKind::Priority => "priority",
+        }
+    }
+
+    /// Returns the tag the kind is saved with in checkpoints.
+    fn tag(&self) -> u32 {
+        match *self {
+            FrontierKind::Reservoir => 0,
+            FrontierKind::Priority => 1,
+        }
+    }
+}
+
+/// Urls waiting to be crawled, kept in the kind of frontier chosen at startup.
+/// All kinds share the interface of the UrlReservoir, which this forwards to.
+pub enum Frontier {
+    Reservoir(url_reservoir::UrlReservoir),
+    Priority(priority_frontier::PriorityFrontier),
+}
+
+impl Frontier {
+    /// Creates and returns a new Frontier structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - Kind of frontier.
+    /// * `starting_urls` - urls the structure should contain right after creation, as seeds.
+    /// * `size` - amount of urls the structure can hold before it starts replacing or dropping them.
+    /// * `rng` - Random number generator (of the UrlReservoir).
+    pub fn new(kind: FrontierKind, starting_urls: Vec<String>, size: usize, rng: rand::StdRng) -> Frontier {
+        match kind {
+            FrontierKind::Reservoir => Frontier::Reservoir(url_reservoir::UrlReservoir::new(starting_urls, size, rng)),
+            FrontierKind::Priority => Frontier::Priority(priority_frontier::PriorityFrontier::new(starting_urls, size)),
+        }
+    }
+
+    /// Returns the kind of the frontier.
+    pub fn kind(&self) -> FrontierKind {
+        match *self {
+            Frontier::Reservoir(_) => FrontierKind::Reservoir,
+            Frontier::Priority(_) => FrontierKind::Priority,
+        }
+    }
+
+    /// Returns a frontier of kind `kind` holding the urls of this one, which is
+    /// returned as it is if it already is of that kind.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - Kind of frontier.
+    /// * `size` - amount of urls the new frontier can hold before it starts replacing or dropping them.
+    /// * `rng` - Random number generator (of the UrlReservoir).
+    pub fn into_kind(mut self, kind: FrontierKind, size: usize, rng: rand::StdRng) -> Frontier {
+        if self.kind()==kind {
+            return self;
+        }
+        let mut urls=Vec::with_capacity(self.len());
+        while let Some(url)=self.get_url() {
+            urls.push(url);
+        }
+        let mut frontier=Frontier::new(kind, Vec::new(), size, rng);
+        // Urls are popped from the end, so the first ones retrieved from this frontier are added last.
+        frontier.add_urls_popping(&mut urls);
+        frontier
+    }
+
+    /// Returns the amount of urls contained within the frontier.
+    #[inline]
+    pub fn len(&self) -> usize {
+        match *self {
+            Frontier::Reservoir(ref reservoir) => reservoir.len(),
+            Frontier::Priority(ref frontier) => frontier.len(),
+        }
+    }
+
+    /// Adds urls to the frontier, making room if it is already full.
+    ///
+    /// # Arguments
+    ///
+    /// * `urls` - vector of urls (with their UrlMeta) to add to the frontier.
+    pub fn add_urls(&mut self, urls: Vec<(String, url_meta::UrlMeta)>) {
+        match *self {
+            Frontier::Reservoir(ref mut reservoir) => reservoir.add_urls(urls),
+            Frontier::Priority(ref mut frontier) => frontier.add_urls(urls),
+        }
+    }
+
+    /// Adds urls to the frontier, making room if it is already full. In contrast
+    /// to add_urls, it does not consume the vector itself, but only its contents.
+    ///
+    /// # Arguments
+    ///
+    /// * `urls` - vector of urls (with their UrlMeta) to add to the frontier.
+    pub fn add_urls_popping(&mut self, urls: &mut Vec<(String, url_meta::UrlMeta)>) {
+        match *self {
+            Frontier::Reservoir(ref mut reservoir) => reservoir.add_urls_popping(urls),
+            Frontier::Priority(ref mut frontier) => frontier.add_urls_popping(urls),
+        }
+    }
+
+    /// Stops scoring down the urls whose extension stands for a media type some
+    /// processor reads, in frontiers that score urls.
+    ///
+    /// # Arguments
+    ///
+    /// * `read` - Returns whether some processor reads a media type.
+    pub fn lift_binary_hint<F: Fn(&str) -> bool>(&mut self, read: F) {
+        if let Frontier::Priority(ref mut frontier)=*self {
+            frontier.lift_binary_hint(read);
+        }
+    }
+
+    /// Keeps only the urls for which `f` returns true.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - Returns whether an url is to be kept.
+    pub fn retain<F: FnMut(&str) -> bool>(&mut self, f: F) {
+        match *self {
+            Frontier::Reservoir(ref mut reservoir) => reservoir.retain(f),
+            Frontier::Priority(ref mut frontier) => frontier.retain(f),
+        }
+    }
+
+    /// Retrieves the next url to be crawled along with its UrlMeta, or None if
+    /// the frontier is empty.
+    pub fn get_url(&mut self) -> Option<(String, url_meta::UrlMeta)> {
+        match *self {
+            Frontier::Reservoir(ref mut reservoir) => reservoir.get_url(),
+            Frontier::Priority(ref mut frontier) => frontier.get_url(),
+        }
+    }
+
+    /// Writes the kind of the frontier followed by its contents to `w`.
+    ///
+    /// # Arguments
+    ///
+    /// * `w` - Writer to write to.
+    pub fn write_to<W: io::Write>(&mut self, w: &mut W) -> io::Result<()> {
+        checkpoint::write_u32(w, self.kind().tag())?;
+        match *self {
+            Frontier::Reservoir(ref mut reservoir) => reservoir.write_to(w),
+            Frontier::Priority(ref frontier) => frontier.write_to(w),
+        }
+    }
+
+    /// Reads a Frontier structure written by `write_to` from `r`, of the kind it was written as.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - Reader to read from.
+    /// * `size` - amount of urls the structure can hold before it starts replacing or dropping them.
+    pub fn read_from<R: io::Read>(r: &mut R, size: usize) -> io::Result<Frontier> {
+        match checkpoint::read_u32(r)? {
+            0 => url_reservoir::UrlReservoir::read_from(r, size).map(Frontier::Reservoir),
+            1 => priority_frontier::PriorityFrontier::read_from(r, size).map(Frontier::Priority),
+            tag => Err(checkpoint::invalid_data(format!("unknown frontier kind {}", tag).as_str())),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frontier() {
+        assert_eq!(FrontierKind::from_name("priority"), Some(FrontierKind::Priority));
+        assert_eq!(FrontierKind::from_name(FrontierKind::Reservoir.name()), Some(FrontierKind::Reservoir));
+        assert_eq!(FrontierKind::from_name("fifo"), None);
+
+        let meta=url_meta::UrlMeta{depth: 1, parent: None, discovered_secs: 0};
+        for &kind in &[FrontierKind::Reservoir, FrontierKind::Priority] {
+            let mut frontier=Frontier::new(kind, vec!["http://a.com/".into()], 4, rand::StdRng::new().unwrap());
+            frontier.add_urls(vec![("http://a.com/x.png".into(), meta.clone()), ("http://b.com/".into(), meta.clone()), ("http://c.com/".into(), meta.clone())]);
+            frontier.retain(|url| !url.starts_with("http://c.com/"));
+            assert_eq!(frontier.len(), 3);
+
+            let mut buf=Vec::new();
+            frontier.write_to(&mut buf).unwrap();
+            let restored=Frontier::read_from(&mut io::Cursor::new(buf), 4).unwrap();
+            assert_eq!((restored.kind(), restored.len()), (kind, 3));
+
+            // Either kind can be turned into the other, keeping every url.
+            let other=if kind==FrontierKind::Reservoir {FrontierKind::Priority} else {FrontierKind::Reservoir};
+            let mut converted=restored.into_kind(other, 4, rand::StdRng::new().unwrap());
+            assert_eq!((converted.kind(), converted.len()), (other, 3));
+            let mut urls:Vec<String>=(0..3).filter_map(|_| converted.get_url().map(|(url, _)| url)).collect();
+            urls.sort();
+            assert_eq!(urls, vec!["http://a.com/".to_string(), "http://a.com/x.png".to_string(), "http://b.com/".to_string()]);
+        }
+
+        // The priority frontier gives the seed first and the image last.
+        let mut frontier=Frontier::new(FrontierKind::Priority, vec!["http://a.com/".into()], 4, rand::StdRng::new().unwrap());
+        frontier.add_urls(vec![("http://a.com/x.png".into(), meta.clone()), ("http://b.com/".into(), meta.clone())]);
+        let urls:Vec<String>=(0..3).filter_map(|_| frontier.get_url().map(|(url, _)| url)).collect();
+        assert_eq!(urls, vec!["http://a.com/".to_string(), "http://b.com/".to_string(), "http://a.com/x.png".to_string()]);
+        assert!(Frontier::read_from(&mut io::Cursor::new(vec![7u8, 0, 0, 0]), 4).is_err());
+    }
+}
@@ -0,0 +1,562 @@
+use hyper;
+use std::cmp;
+use std::collections;
+use std::net;
+use std::net::ToSocketAddrs;
+use std::sync;
+use std::thread;
+use std::time;
+
+const MIN_IP_RETRY_MILLIS: u64 = 100;
+/// Number of threads an IpResolver looks up hosts on.
+const RESOLVER_THREADS: usize = 4;
+/// Number of answers an IpResolver remembers before it starts over.
+const MAX_CACHED_IPS: usize = 64*1024;
+
+struct HostState<T> {
+    queue: collections::VecDeque<T>,
+    in_flight: usize,
+    next_allowed: time::Instant,
+    /// What `next_allowed` was before the last url was handed out, for urls given back.
+    previous_allowed: time::Instant,
+    crawl_delay: Option<time::Duration>,
+    ip: Option<net::IpAddr>,
+    /// Whether the urls of the host are held back until its ip address is known.
+    resolving: bool,
+    /// Time the host is in the heap for, if it is. Heap entries of other times are stale.
+    heap_at: Option<time::Instant>,
+}
+
+impl<T> HostState<T> {
+    /// Puts the host (named `host`) into `heap` at the time it allows a request,
+    /// if it has urls to hand out and is not in it for that time or sooner already.
+    fn push(&mut self, host: &str, heap: &mut collections::BinaryHeap<cmp::Reverse<(time::Instant, String)>>, max_connections: usize) {
+        if self.queue.is_empty() || self.in_flight>=max_connections || self.resolving {
+            return;
+        }
+        if self.heap_at.map_or(true, |at| at>self.next_allowed) {
+            self.heap_at=Some(self.next_allowed);
+            heap.push(cmp::Reverse((self.next_allowed, host.to_string())));
+        }
+    }
+}
+
+/// Data structure that holds urls to be gotten, grouped by host, and hands them
+/// out such that every host waits a minimum delay between requests and has a
+/// limited amount of requests in flight (optionally also per ip address). Hosts
+/// are handed out in order of the time they are allowed to be requested again,
+/// so requests to different hosts get interleaved. Along with each url goes
+/// whatever its user needs back once it is handed out (`T`).
+pub struct HostScheduler<T> {
+    hosts: collections::HashMap<String, HostState<T>>,
+    heap: collections::BinaryHeap<cmp::Reverse<(time::Instant, String)>>,
+    ip_in_flight: collections::HashMap<net::IpAddr, usize>,
+    queued: usize,
+    purge_mark: usize,
+    min_delay: time::Duration,
+    max_crawl_delay: time::Duration,
+    max_connections: usize,
+    max_ip_connections: usize,
+}
+
+/// Returns the key `uri` is scheduled under (its lowercase host).
+pub fn host_key(uri: &hyper::Uri) -> Option<String> {
+    uri.host().map(|host| host.to_lowercase())
+}
+
+/// Resolves the host of `uri` to an ip address. This blocks while the dns
+/// lookup takes place, see `IpResolver` for lookups that do not.
+pub fn resolve_ip(uri: &hyper::Uri) -> Option<net::IpAddr> {
+    let host=uri.host()?.trim_matches(|c| c=='[' || c==']');
+    let port=uri.port().unwrap_or(if uri.scheme()==Some("https") {443} else {80});
+    match (host, port).to_socket_addrs() {
+        Ok(mut addrs) => addrs.next().map(|addr| addr.ip()),
+        Err(e) => {eprintln!("Error (resolve_ip): {:?}", e);None},
+    }
+}
+
+/// Resolves the ip addresses of hosts on threads of its own, so that its user
+/// does not block while dns lookups take place, and remembers the answers,
+/// failed lookups included, so that hosts are not looked up over and over.
+pub struct IpResolver {
+    requests: sync::mpsc::Sender<(String, hyper::Uri)>,
+    answers: sync::mpsc::Receiver<(String, Option<net::IpAddr>)>,
+    cache: collections::HashMap<String, Option<net::IpAddr>>,
+    pending: collections::HashSet<String>,
+}
+
+impl IpResolver {
+    /// Creates and returns a new IpResolver structure, along with the threads
+    /// it looks up hosts on. They end once it is dropped.
+    pub fn new() -> IpResolver {
+        let (requests, receiver)=sync::mpsc::channel::<(String, hyper::Uri)>();
+        let (sender, answers)=sync::mpsc::channel();
+        let receiver=sync::Arc::new(sync::Mutex::new(receiver));
+        for _ in 0..RESOLVER_THREADS {
+            let receiver=receiver.clone();
+            let sender=sender.clone();
+            thread::spawn(move || {
+                loop {
+                    let request=match receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => break,
+                    };
+                    let (host, uri)=match request {
+                        Ok(request) => request,
+                        Err(_) => break,
+                    };
+                    if sender.send((host, resolve_ip(&uri))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        IpResolver{
+            requests: requests,
+            answers: answers,
+            cache: collections::HashMap::new(),
+            pending: collections::HashSet::new(),
+        }
+    }
+
+    /// Returns the ip address of `host` (None if the lookup failed) if it is
+    /// known already. Otherwise, it has it looked up and returns None, the
+    /// answer coming up through `answers` later.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - key of the host, see `host_key`.
+    /// * `uri` - an url of the host.
+    pub fn lookup(&mut self, host: &str, uri: &hyper::Uri) -> Option<Option<net::IpAddr>> {
+        if let Some(&ip)=self.cache.get(host) {
+            return Some(ip);
+        }
+        if !self.pending.contains(host) {
+            match self.requests.send((host.to_string(), uri.clone())) {
+                Ok(_) => {self.pending.insert(host.to_string());},
+                Err(e) => {eprintln!("Error (resolve_ip): {:?}", e);return Some(None);},
+            }
+        }
+        None
+    }
+
+    /// Returns the answers of the lookups that have completed since the last call, remembering them.
+    pub fn answers(&mut self) -> Vec<(String, Option<net::IpAddr>)> {
+        let answers:Vec<(String, Option<net::IpAddr>)>=self.answers.try_iter().collect();
+        for &(ref host, ip) in answers.iter() {
+            self.pending.remove(host);
+            if self.cache.len()>=MAX_CACHED_IPS {
+                self.cache.clear();
+            }
+            self.cache.insert(host.clone(), ip);
+        }
+        answers
+    }
+}
+
+impl<T> HostScheduler<T> {
+    /// Creates and returns a new HostScheduler structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `min_delay` - minimum time between two requests to the same host.
+    /// * `max_crawl_delay` - upper bound for the Crawl-delay hosts may ask for.
+    /// * `max_connections` - maximum amount of requests in flight per host.
+    /// * `max_ip_connections` - maximum amount of requests in flight per ip address, 0 meaning no limit.
+    pub fn new(min_delay: time::Duration, max_crawl_delay: time::Duration, max_connections: usize, max_ip_connections: usize) -> HostScheduler<T> {
+        HostScheduler{
+            hosts: collections::HashMap::new(),
+            heap: collections::BinaryHeap::new(),
+            ip_in_flight: collections::HashMap::new(),
+            queued: 0,
+            purge_mark: 1024,
+            min_delay: min_delay,
+            max_crawl_delay: max_crawl_delay,
+            max_connections: cmp::max(max_connections, 1),
+            max_ip_connections: max_ip_connections,
+        }
+    }
+
+    /// Returns the amount of urls waiting to be handed out.
+    #[inline]
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    /// Returns the amount of hosts the HostScheduler structure keeps track of.
+    #[inline]
+    pub fn hosts(&self) -> usize {
+        self.hosts.len()
+    }
+
+    /// Returns whether `host` is known, i.e. whether its ip address would be
+    /// ignored by `schedule`.
+    #[inline]
+    pub fn knows_host(&self, host: &str) -> bool {
+        self.hosts.contains_key(host)
+    }
+
+    /// Returns whether requests should be limited per ip address.
+    #[inline]
+    pub fn limits_ips(&self) -> bool {
+        self.max_ip_connections>0
+    }
+
+    /// Adds an url to be handed out once its host allows it.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - key of the host of the url, see `host_key`.
+    /// * `ip` - ip address of the host, only used if the host is not known yet.
+    /// * `uri` - the url (along with whatever goes with it).
+    /// * `crawl_delay` - Crawl-delay of the host, if known.
+    pub fn schedule(&mut self, host: String, ip: Option<net::IpAddr>, uri: T, crawl_delay: Option<time::Duration>) {
+        let now=time::Instant::now();
+        self.queued+=1;
+
+        {
+            let state=self.host_state(host.as_str(), ip, now);
+            if crawl_delay.is_some() {
+                state.crawl_delay=crawl_delay;
+            }
+            state.queue.push_back(uri);
+            if state.next_allowed<now {
+                state.next_allowed=now;
+            }
+        }
+        if let Some(state)=self.hosts.get_mut(host.as_str()) {
+            state.push(host.as_str(), &mut self.heap, self.max_connections);
+        }
+
+        self.purge_idle(now);
+    }
+
+    /// Returns the state of `host`, which starts with ip address `ip` if it is not known yet.
+    fn host_state(&mut self, host: &str, ip: Option<net::IpAddr>, now: time::Instant) -> &mut HostState<T> {
+        if !self.hosts.contains_key(host) {
+            self.hosts.insert(host.to_string(), HostState{
+                queue: collections::VecDeque::new(),
+                in_flight: 0,
+                next_allowed: now,
+                previous_allowed: now,
+                crawl_delay: None,
+                ip: ip,
+                resolving: false,
+                heap_at: None,
+            });
+        }
+        self.hosts.get_mut(host).expect("HostScheduler needs fixing: host vanished")
+    }
+
+    /// Holds back the urls of `host`, which is not known yet, until `resolved`
+    /// tells its ip address. Hosts that are known already are left alone.
+    pub fn hold(&mut self, host: &str) {
+        if !self.hosts.contains_key(host) {
+            let now=time::Instant::now();
+            self.host_state(host, None, now).resolving=true;
+        }
+    }
+
+    /// Sets the ip address of `host` (None if it could not be looked up), and
+    /// hands out its urls if they were held back.
+    pub fn resolved(&mut self, host: &str, ip: Option<net::IpAddr>) {
+        let max_connections=self.max_connections;
+        if let Some(state)=self.hosts.get_mut(host) {
+            if state.resolving {
+                state.ip=ip;
+                state.resolving=false;
+                state.push(host, &mut self.heap, max_connections);
+            }
+        }
+    }
+
+    /// Hands out the next url whose host allows a request at time `now`, together
+    /// with its host key, or None if there is none. Every url handed out counts
+    /// as in flight until `finish` is called for its host.
+    pub fn next_ready(&mut self, now: time::Instant) -> Option<(String, T)> {
+        loop {
+            match self.heap.peek() {
+                Some(&cmp::Reverse((at, _))) if at<=now => {},
+                _ => return None,
+            }
+            let (at, host)=match self.heap.pop() {
+                Some(cmp::Reverse((at, host))) => (at, host),
+                None => return None,
+            };
+
+            let (min_delay, max_crawl_delay, max_connections, max_ip_connections)=(self.min_delay, self.max_crawl_delay, self.max_connections, self.max_ip_connections);
+            let state=match self.hosts.get_mut(&host) {
+                Some(state) => state,
+                None => continue,
+            };
+            if state.heap_at!=Some(at) {
+                continue;
+            }
+            state.heap_at=None;
+
+            // If a redirect followed since pushed the host back, it waits until then.
+            if state.next_allowed>now {
+                state.push(host.as_str(), &mut self.heap, max_connections);
+                continue;
+            }
+
+            // If the ip address of the host is busy, try again later.
+            if let Some(ip)=state.ip {
+                if max_ip_connections>0 && self.ip_in_flight.get(&ip).map_or(false, |&n| n>=max_ip_connections) {
+                    let retry=now+cmp::max(min_delay, time::Duration::from_millis(MIN_IP_RETRY_MILLIS));
+                    state.heap_at=Some(retry);
+                    self.heap.push(cmp::Reverse((retry, host)));
+                    continue;
+                }
+            }
+
+            let uri=match state.queue.pop_front() {
+                Some(uri) => uri,
+                None => continue,
+            };
+            if let Some(ip)=state.ip {
+                *self.ip_in_flight.entry(ip).or_insert(0)+=1;
+            }
+            self.queued-=1;
+            state.in_flight+=1;
+            let delay=cmp::max(min_delay, cmp::min(state.crawl_delay.unwrap_or(min_delay), max_crawl_delay));
+            state.previous_allowed=state.next_allowed;
+            state.next_allowed=now+delay;
+
+            state.push(host.as_str(), &mut self.heap, max_connections);
+            return Some((host, uri));
+        }
+    }
+
+    /// Returns the time the next url will be ready to be handed out, if any url is waiting.
+    pub fn next_wakeup(&self) -> Option<time::Instant> {
+        self.heap.peek().map(|&cmp::Reverse((at, _))| at)
+    }
+
+    /// Charges `host` for a redirect to be followed within a request handed out
+    /// by `next_ready`, as for any other request, and returns when it may be made.
+    pub fn hop(&mut self, host: &str, now: time::Instant) -> time::Instant {
+        let (min_delay, max_crawl_delay)=(self.min_delay, self.max_crawl_delay);
+        match self.hosts.get_mut(host) {
+            Some(state) => {
+                let at=cmp::max(state.next_allowed, now);
+                state.next_allowed=at+cmp::max(min_delay, cmp::min(state.crawl_delay.unwrap_or(min_delay), max_crawl_delay));
+                at
+            },
+            None => now,
+        }
+    }
+
+    /// Marks a request to `host` that was handed out by `next_ready` as finished.
+    pub fn finish(&mut self, host: &str) {
+        let max_connections=self.max_connections;
+        if let Some(state)=self.hosts.get_mut(host) {
+            if state.in_flight>0 {
+                state.in_flight-=1;
+                if let Some(ip)=state.ip {
+                    if let Some(n)=self.ip_in_flight.get_mut(&ip) {
+                        *n=n.saturating_sub(1);
+                    }
+                }
+            }
+            state.push(host, &mut self.heap, max_connections);
+        }
+    }
+
+    /// Hands a url that was handed out by `next_ready` but could not be sent
+    /// back. It goes first in the queue of its host, and as no request was made,
+    /// the host is not charged the delay it was charged for handing it out.
+    pub fn give_back(&mut self, host: String, uri: T) {
+        match self.hosts.get_mut(host.as_str()) {
+            Some(state) => {
+                state.queue.push_front(uri);
+                state.next_allowed=state.previous_allowed;
+            },
+            None => {
+                self.schedule(host, None, uri, None);
+                return;
+            },
+        }
+        self.queued+=1;
+        self.finish(host.as_str());
+    }
+
+    /// Forgets hosts without queued urls, requests in flight or pending delay,
+    /// once their amount has doubled since the last time this was done.
+    fn purge_idle(&mut self, now: time::Instant) {
+        if self.hosts.len()<2*self.purge_mark {
+            return;
+        }
+
+        self.hosts.retain(|_, state| !state.queue.is_empty() || state.in_flight>0 || state.next_allowed>now || state.resolving);
+        self.ip_in_flight.retain(|_, n| *n>0);
+        self.purge_mark=cmp::max(self.hosts.len(), 1024);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(s: &str) -> hyper::Uri {
+        s.parse::<hyper::Uri>().unwrap()
+    }
+
+    #[test]
+    fn test_host_key() {
+        assert_eq!(host_key(&uri("http://A.com:8080/x")), Some("a.com".to_string()));
+        assert_eq!(host_key(&uri("/x")), None);
+        assert_eq!(resolve_ip(&uri("http://127.0.0.1:8080/x")), Some(net::IpAddr::V4(net::Ipv4Addr::new(127, 0, 0, 1))));
+    }
+
+    #[test]
+    fn test_min_delay_and_interleaving() {
+        let second=time::Duration::from_secs(1);
+        let mut host_scheduler=HostScheduler::new(second, 10*second, 4, 0);
+        host_scheduler.schedule("a.com".to_string(), None, uri("http://a.com/1"), None);
+        host_scheduler.schedule("a.com".to_string(), None, uri("http://a.com/2"), None);
+        host_scheduler.schedule("b.com".to_string(), None, uri("http://b.com/1"), None);
+        assert_eq!(host_scheduler.queued(), 3);
+
+        let now=time::Instant::now();
+        let mut first=vec![host_scheduler.next_ready(now).unwrap().0, host_scheduler.next_ready(now).unwrap().0];
+        first.sort();
+        assert_eq!(first, vec!["a.com".to_string(), "b.com".to_string()]);
+        assert!(host_scheduler.next_ready(now).is_none());
+        assert!(host_scheduler.next_wakeup().unwrap()>now);
+
+        assert_eq!(host_scheduler.next_ready(now+second).map(|(_, uri)| uri.to_string()), Some("http://a.com/2".to_string()));
+        assert_eq!(host_scheduler.queued(), 0);
+        assert!(host_scheduler.next_ready(now+10*second).is_none());
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let mut host_scheduler=HostScheduler::new(time::Duration::from_secs(0), time::Duration::from_secs(0), 1, 0);
+        host_scheduler.schedule("a.com".to_string(), None, uri("http://a.com/1"), None);
+        host_scheduler.schedule("a.com".to_string(), None, uri("http://a.com/2"), None);
+
+        let now=time::Instant::now();
+        assert!(host_scheduler.next_ready(now).is_some());
+        assert!(host_scheduler.next_ready(now).is_none());
+        host_scheduler.finish("a.com");
+        assert!(host_scheduler.next_ready(now).is_some());
+
+        // Urls that could not be sent are handed out again.
+        host_scheduler.give_back("a.com".to_string(), uri("http://a.com/2"));
+        assert_eq!(host_scheduler.next_ready(time::Instant::now()).map(|(_, uri)| uri.to_string()), Some("http://a.com/2".to_string()));
+    }
+
+    #[test]
+    fn test_give_back() {
+        let second=time::Duration::from_secs(1);
+        let mut host_scheduler=HostScheduler::new(second, 10*second, 2, 0);
+        host_scheduler.schedule("a.com".to_string(), None, uri("http://a.com/1"), None);
+        host_scheduler.schedule("a.com".to_string(), None, uri("http://a.com/2"), None);
+
+        // Urls given back go first, and right away, as their host was not requested.
+        let now=time::Instant::now();
+        let (host, first)=host_scheduler.next_ready(now).unwrap();
+        assert!(host_scheduler.next_ready(now).is_none());
+        host_scheduler.give_back(host, first);
+        assert_eq!(host_scheduler.queued(), 2);
+        assert_eq!(host_scheduler.next_ready(now).map(|(_, uri)| uri.to_string()), Some("http://a.com/1".to_string()));
+        assert!(host_scheduler.next_ready(now).is_none());
+        assert_eq!(host_scheduler.next_ready(now+second).map(|(_, uri)| uri.to_string()), Some("http://a.com/2".to_string()));
+        assert_eq!(host_scheduler.queued(), 0);
+    }
+
+    #[test]
+    fn test_hop() {
+        let second=time::Duration::from_secs(1);
+        let mut host_scheduler=HostScheduler::new(second, 10*second, 2, 0);
+        host_scheduler.schedule("a.com".to_string(), None, uri("http://a.com/1"), None);
+        host_scheduler.schedule("a.com".to_string(), None, uri("http://a.com/2"), None);
+
+        // Redirects wait for the host like any request, and the urls waiting wait for them.
+        let now=time::Instant::now();
+        assert!(host_scheduler.next_ready(now).is_some());
+        assert_eq!(host_scheduler.hop("a.com", now), now+second);
+        assert_eq!(host_scheduler.hop("a.com", now), now+2*second);
+        assert!(host_scheduler.next_ready(now+second).is_none());
+        assert!(host_scheduler.next_ready(now+2*second).is_none());
+        assert_eq!(host_scheduler.next_ready(now+3*second).map(|(_, uri)| uri.to_string()), Some("http://a.com/2".to_string()));
+        assert_eq!(host_scheduler.hop("b.com", now), now);
+    }
+
+    #[test]
+    fn test_hold() {
+        let ip=Some(net::IpAddr::V4(net::Ipv4Addr::new(10, 0, 0, 1)));
+        let mut host_scheduler=HostScheduler::new(time::Duration::from_secs(0), time::Duration::from_secs(0), 4, 1);
+        host_scheduler.hold("a.com");
+        host_scheduler.schedule("a.com".to_string(), None, uri("http://a.com/1"), None);
+        host_scheduler.schedule("a.com".to_string(), None, uri("http://a.com/2"), None);
+        host_scheduler.schedule("b.com".to_string(), ip, uri("http://b.com/1"), None);
+
+        // The urls of hosts being resolved wait, while those of the others do not.
+        let now=time::Instant::now();
+        assert_eq!(host_scheduler.next_ready(now).map(|(host, _)| host), Some("b.com".to_string()));
+        assert!(host_scheduler.next_ready(now).is_none());
+        host_scheduler.resolved("a.com", ip);
+        assert!(host_scheduler.next_ready(now).is_none());
+        host_scheduler.finish("b.com");
+        assert_eq!(host_scheduler.next_ready(now+time::Duration::from_secs(1)).map(|(host, _)| host), Some("a.com".to_string()));
+    }
+
+    #[test]
+    fn test_ip_resolver() {
+        let mut ip_resolver=IpResolver::new();
+        let localhost=uri("http://127.0.0.1:8080/x");
+        assert_eq!(ip_resolver.lookup("127.0.0.1", &localhost), None);
+        assert_eq!(ip_resolver.lookup("127.0.0.1", &localhost), None);
+        let mut answers=Vec::new();
+        for _ in 0..100 {
+            answers.extend(ip_resolver.answers());
+            if !answers.is_empty() {
+                break;
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+
+        // Hosts are looked up once, and their answers remembered.
+        let ip=Some(net::IpAddr::V4(net::Ipv4Addr::new(127, 0, 0, 1)));
+        assert_eq!(answers, vec![("127.0.0.1".to_string(), ip)]);
+        assert_eq!(ip_resolver.lookup("127.0.0.1", &localhost), Some(ip));
+        thread::sleep(time::Duration::from_millis(20));
+        assert!(ip_resolver.answers().is_empty());
+    }
+
+    #[test]
+    fn test_crawl_delay() {
+        let second=time::Duration::from_secs(1);
+        let mut host_scheduler=HostScheduler::new(second, 5*second, 4, 0);
+        host_scheduler.schedule("a.com".to_string(), None, uri("http://a.com/1"), Some(3*second));
+        host_scheduler.schedule("a.com".to_string(), None, uri("http://a.com/2"), None);
+        host_scheduler.schedule("b.com".to_string(), None, uri("http://b.com/1"), Some(60*second));
+        host_scheduler.schedule("b.com".to_string(), None, uri("http://b.com/2"), None);
+
+        let now=time::Instant::now();
+        assert!(host_scheduler.next_ready(now).is_some());
+        assert!(host_scheduler.next_ready(now).is_some());
+        assert!(host_scheduler.next_ready(now+2*second).is_none());
+        assert_eq!(host_scheduler.next_ready(now+3*second).map(|(host, _)| host), Some("a.com".to_string()));
+        // Crawl-delay is capped by `max_crawl_delay`.
+        assert_eq!(host_scheduler.next_ready(now+5*second).map(|(host, _)| host), Some("b.com".to_string()));
+    }
+
+    #[test]
+    fn test_max_ip_connections() {
+        let ip=Some(net::IpAddr::V4(net::Ipv4Addr::new(10, 0, 0, 1)));
+        let mut host_scheduler=HostScheduler::new(time::Duration::from_secs(0), time::Duration::from_secs(0), 4, 1);
+        host_scheduler.schedule("a.com".to_string(), ip, uri("http://a.com/1"), None);
+        host_scheduler.schedule("b.com".to_string(), ip, uri("http://b.com/1"), None);
+
+        let now=time::Instant::now();
+        let (host, _)=host_scheduler.next_ready(now).unwrap();
+        assert!(host_scheduler.next_ready(now).is_none());
+        host_scheduler.finish(host.as_str());
+        assert!(host_scheduler.next_ready(now+time::Duration::from_secs(1)).is_some());
+    }
+}
@@ -0,0 +1,488 @@
+#![allow(dead_code)]
+
+use robots;
+use regex;
+use url;
+use std::char;
+
+/// Kind of element a link was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    /// `<a href>` or `<area href>`.
+    Anchor,
+    /// `<link rel=stylesheet href>`.
+    Stylesheet,
+    /// `url()` or `@import` within a `<style>` element or a `style` attribute.
+    InlineStyle,
+    /// `<script src>`.
+    Script,
+    /// `<img src>`.
+    Image,
+    /// `<iframe src>` or `<frame src>`.
+    Iframe,
+    /// `<form action>`.
+    FormAction,
+}
+
+/// Link found within an html document, resolved to an absolute url.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub kind: LinkKind,
+    pub url: url::Url,
+    /// Whether the element has `rel="nofollow"`.
+    pub nofollow: bool,
+}
+
+/// Links of an html document along with the directives of its robots meta tags.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub links: Vec<Link>,
+    pub directives: robots::PageDirectives,
+}
+
+/// Extracts typed links out of html code by tokenizing it, so that text within
+/// comments and scripts is not taken for links.
+pub struct LinkExtractor {
+    re_css_url: regex::Regex,
+}
+
+impl LinkExtractor {
+    /// Creates and returns a new LinkExtractor structure.
+    pub fn new() -> LinkExtractor {
+        LinkExtractor{
+            re_css_url: regex::Regex::new(r#"(?i)url\(\s*["']?([^"')\s]+)["']?\s*\)|@import\s+["']([^"']+)["']"#).unwrap(),
+        }
+    }
+
+    /// Returns the links within `html`, in document order, resolved against its
+    /// `<base href>` if it has one and against `page_url` otherwise. Attribute
+    /// values have their character references decoded, and links with the
+    /// `javascript:`, `mailto:` or `data:` schemes are skipped. The directives
+    /// of all `<meta name="robots">` tags are returned along.
+    ///
+    /// # Arguments
+    ///
+    /// * `html` - Html code to extract the links from.
+    /// * `page_url` - Url the html code was gotten from.
+    pub fn extract(&self, html: &str, page_url: &url::Url) -> Page {
+        let mut raw_links=Vec::new();
+        let mut base=None;
+        let mut directives=robots::PageDirectives::default();
+
+        for token in Tokenizer::new(html) {
+            match token {
+                Token::Tag(name, attributes) => {
+                    let attribute=|wanted: &str| attributes.iter().find(|&&(ref name, _)| name==wanted).map(|&(_, ref value)| value.as_str());
+                    let nofollow=attribute("rel").map_or(false, |rel| rel.split_whitespace().any(|rel| rel.eq_ignore_ascii_case("nofollow")));
+
+                    match name.as_str() {
+                        "a" | "area" => raw_links.extend(attribute("href").map(|href| (LinkKind::Anchor, href.to_string(), nofollow))),
+                        "link" => {
+                            let is_stylesheet=attribute("rel").map_or(false, |rel| rel.split_whitespace().any(|rel| rel.eq_ignore_ascii_case("stylesheet")));
+                            if is_stylesheet {
+                                raw_links.extend(attribute("href").map(|href| (LinkKind::Stylesheet, href.to_string(), nofollow)));
+                            }
+                        },
+                        "script" => raw_links.extend(attribute("src").map(|src| (LinkKind::Script, src.to_string(), false))),
+                        "img" => raw_links.extend(attribute("src").map(|src| (LinkKind::Image, src.to_string(), false))),
+                        "iframe" | "frame" => raw_links.extend(attribute("src").map(|src| (LinkKind::Iframe, src.to_string(), false))),
+                        "form" => raw_links.extend(attribute("action").map(|action| (LinkKind::FormAction, action.to_string(), nofollow))),
+                        "base" => {
+                            // Only the first base with an href counts, and it is resolved against the page url.
+                            if base.is_none() {
+                                base=attribute("href").and_then(|href| page_url.join(href.trim()).ok());
+                            }
+                        },
+                        "meta" => {
+                            let is_robots=attribute("name").map_or(false, |name| name.trim().eq_ignore_ascii_case("robots"));
+                            if is_robots {
+                                directives.merge(robots::PageDirectives::parse(attribute("content").unwrap_or("")));
+                            }
+                        },
+                        _ => {},
+                    }
+
+                    if let Some(style)=attribute("style") {
+                        self.extend_css_urls(style, &mut raw_links);
+                    }
+                },
+                Token::Style(css) => self.extend_css_urls(css, &mut raw_links),
+            }
+        }
+
+        let base=base.as_ref().unwrap_or(page_url);
+        let links=raw_links.into_iter().filter_map(|(kind, value, nofollow)| {
+            let value=value.trim();
+            if value.is_empty() || has_skipped_scheme(value) {
+                return None;
+            }
+            base.join(value).ok().map(|url| Link{kind: kind, url: url, nofollow: nofollow})
+        }).collect();
+
+        Page{links: links, directives: directives}
+    }
+
+    /// Adds the urls referenced by `url()` and `@import` within `css` to `raw_links`.
+    fn extend_css_urls(&self, css: &str, raw_links: &mut Vec<(LinkKind, String, bool)>) {
+        for cap in self.re_css_url.captures_iter(css) {
+            if let Some(m)=cap.get(1).or_else(|| cap.get(2)) {
+                raw_links.push((LinkKind::InlineStyle, m.as_str().to_string(), false));
+            }
+        }
+    }
+}
+
+/// Returns the label of the encoding declared by the first `<meta charset>` or
+/// `<meta http-equiv="content-type">` tag within `html`, if any.
+///
+/// # Arguments
+///
+/// * `html` - Beginning of the html code.
+pub fn meta_charset(html: &str) -> Option<String> {
+    for token in Tokenizer::new(html) {
+        let attributes=match token {
+            Token::Tag(ref name, ref attributes) if name=="meta" => attributes,
+            _ => continue,
+        };
+        let attribute=|wanted: &str| attributes.iter().find(|&&(ref name, _)| name==wanted).map(|&(_, ref value)| value.as_str());
+
+        if let Some(charset)=attribute("charset") {
+            return Some(charset.trim().to_string());
+        }
+        let is_content_type=attribute("http-equiv").map_or(false, |http_equiv| http_equiv.trim().eq_ignore_ascii_case("content-type"));
+        if let (true, Some(content))=(is_content_type, attribute("content")) {
+            let lower=content.to_ascii_lowercase();
+            if let Some(i)=lower.find("charset=") {
+                let charset=content[i+8..].trim().trim_matches(|c| c=='"' || c=='\'');
+                let end=charset.find(|c: char| c==';' || c=='"' || c=='\'' || c.is_whitespace()).unwrap_or(charset.len());
+                return Some(charset[..end].to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Returns whether `value` is a link with a scheme that is not to be followed.
+fn has_skipped_scheme(value: &str) -> bool {
+    // Browsers ignore tabs and newlines within urls, so "java\tscript:" is still javascript.
+    let scheme: String=value.chars().filter(|&c| c!='\t' && c!='\n' && c!='\r').take(11).collect::<String>().to_ascii_lowercase();
+    scheme.starts_with("javascript:") || scheme.starts_with("mailto:") || scheme.starts_with("data:")
+}
+
+/// Part of an html document relevant to link extraction.
+enum Token<'a> {
+    /// Start tag, with its lower case name and its attributes (lower case names, decoded values).
+    Tag(String, Vec<(String, String)>),
+    /// Contents of a `<style>` element.
+    Style(&'a str),
+}
+
+/// Splits html code into start tags and style contents, skipping text, comments,
+/// end tags and the contents of raw text elements such as `<script>`.
+struct Tokenizer<'a> {
+    html: &'a str,
+    pos: usize,
+    /// Contents of the raw text element whose start tag was just returned.
+    pending_style: Option<&'a str>,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(html: &'a str) -> Tokenizer<'a> {
+        Tokenizer{html: html, pos: 0, pending_style: None}
+    }
+
+    /// Moves past the next occurrence of `needle` (or to the end), returning the skipped text.
+    fn skip_past(&mut self, needle: &str) -> &'a str {
+        let rest=&self.html[self.pos..];
+        match rest.find(needle) {
+            Some(i) => {
+                self.pos+=i+needle.len();
+                &rest[..i]
+            },
+            None => {
+                self.pos=self.html.len();
+                rest
+            },
+        }
+    }
+
+    /// Moves past the end tag of the raw text element `name`, returning its contents.
+    fn skip_raw_text(&mut self, name: &str) -> &'a str {
+        let rest=&self.html[self.pos..];
+        let bytes=rest.as_bytes();
+        let mut i=0;
+        while let Some(j)=rest[i..].find("</") {
+            let start=i+j;
+            let name_end=start+2+name.len();
+            if name_end<=bytes.len() && bytes[start+2..name_end].eq_ignore_ascii_case(name.as_bytes())
+                && (name_end==bytes.len() || is_tag_name_end(bytes[name_end])) {
+                self.pos+=start;
+                self.skip_past(">");
+                return &rest[..start];
+            }
+            i=start+2;
+        }
+        self.pos=self.html.len();
+        rest
+    }
+
+    /// Parses the start tag whose name begins at the current position.
+    fn start_tag(&mut self) -> (String, Vec<(String, String)>) {
+        let bytes=self.html.as_bytes();
+        let name_start=self.pos;
+        while self.pos<bytes.len() && !is_tag_name_end(bytes[self.pos]) {
+            self.pos+=1;
+        }
+        let name=self.html[name_start..self.pos].to_ascii_lowercase();
+
+        let mut attributes=Vec::new();
+        loop {
+            while self.pos<bytes.len() && (is_space(bytes[self.pos]) || bytes[self.pos]==b'/') {
+                self.pos+=1;
+            }
+            if self.pos>=bytes.len() {
+                break;
+            }
+            if bytes[self.pos]==b'>' {
+                self.pos+=1;
+                break;
+            }
+
+            // Attribute name.
+            let attribute_start=self.pos;
+            self.pos+=1;
+            while self.pos<bytes.len() && !is_space(bytes[self.pos]) && bytes[self.pos]!=b'=' && bytes[self.pos]!=b'>' && bytes[self.pos]!=b'/' {
+                self.pos+=1;
+            }
+            let attribute_name=self.html[attribute_start..self.pos].to_ascii_lowercase();
+
+            // Optional value, quoted or not.
+            let mut after_name=self.pos;
+            while after_name<bytes.len() && is_space(bytes[after_name]) {
+                after_name+=1;
+            }
+            let mut value=String::new();
+            if after_name<bytes.len() && bytes[after_name]==b'=' {
+                self.pos=after_name+1;
+                while self.pos<bytes.len() && is_space(bytes[self.pos]) {
+                    self.pos+=1;
+                }
+                if self.pos<bytes.len() && (bytes[self.pos]==b'"' || bytes[self.pos]==b'\'') {
+                    let quote=if bytes[self.pos]==b'"' {"\""} else {"'"};
+                    self.pos+=1;
+                    value=decode_entities(self.skip_past(quote));
+                } else {
+                    let value_start=self.pos;
+                    while self.pos<bytes.len() && !is_space(bytes[self.pos]) && bytes[self.pos]!=b'>' {
+                        self.pos+=1;
+                    }
+                    value=decode_entities(&self.html[value_start..self.pos]);
+                }
+            }
+
+            // Later duplicates of an attribute are ignored, as browsers do.
+            if !attributes.iter().any(|&(ref name, _)| *name==attribute_name) {
+                attributes.push((attribute_name, value));
+            }
+        }
+
+        (name, attributes)
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item=Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        if let Some(css)=self.pending_style.take() {
+            return Some(Token::Style(css));
+        }
+
+        loop {
+            self.skip_past("<");
+            let rest=&self.html[self.pos..];
+            if rest.is_empty() {
+                return None;
+            }
+
+            if rest.starts_with("!--") {
+                self.pos+=3;
+                self.skip_past("-->");
+            } else if rest.starts_with("!") || rest.starts_with("?") || rest.starts_with("/") {
+                // Doctype, processing instruction or end tag.
+                self.skip_past(">");
+            } else if rest.as_bytes()[0].is_ascii_alphabetic() {
+                let (name, attributes)=self.start_tag();
+                match name.as_str() {
+                    "style" => self.pending_style=Some(self.skip_raw_text("style")),
+                    "script" | "textarea" | "title" | "xmp" | "noembed" | "noframes" => {
+                        let _=self.skip_raw_text(name.as_str());
+                    },
+                    _ => {},
+                }
+                return Some(Token::Tag(name, attributes));
+            }
+            // Otherwise the "<" is just text.
+        }
+    }
+}
+
+fn is_space(b: u8) -> bool {
+    b==b' ' || b==b'\t' || b==b'\n' || b==b'\r' || b==0x0c
+}
+
+fn is_tag_name_end(b: u8) -> bool {
+    is_space(b) || b==b'>' || b==b'/'
+}
+
+/// Decodes the character references (`&amp;`, `&#39;`, `&#x27;`, ...) within an attribute value.
+/// Unknown named references are left as they are.
+fn decode_entities(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_string();
+    }
+
+    let mut decoded=String::with_capacity(value.len());
+    let mut rest=value;
+    while let Some(i)=rest.find('&') {
+        decoded.push_str(&rest[..i]);
+        rest=&rest[i..];
+
+        let reference_len=rest[1..].find(|c: char| !c.is_ascii_alphanumeric() && c!='#').map_or(rest.len()-1, |j| j);
+        let reference=&rest[1..1+reference_len];
+        let has_semicolon=rest[1+reference_len..].starts_with(';');
+
+        let c=if reference.starts_with("#x") || reference.starts_with("#X") {
+            u32::from_str_radix(&reference[2..], 16).ok().map(|n| char::from_u32(n).unwrap_or('\u{fffd}'))
+        } else if reference.starts_with('#') {
+            reference[1..].parse::<u32>().ok().map(|n| char::from_u32(n).unwrap_or('\u{fffd}'))
+        } else if has_semicolon {
+            named_entity(reference)
+        } else {
+            None
+        };
+
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest=&rest[1+reference_len+if has_semicolon {1} else {0}..];
+            },
+            None => {
+                decoded.push('&');
+                rest=&rest[1..];
+            },
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Returns the character of the named references likely to show up within urls.
+fn named_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        "sol" => Some('/'),
+        "colon" => Some(':'),
+        "quest" => Some('?'),
+        "equals" => Some('='),
+        "num" => Some('#'),
+        "percnt" => Some('%'),
+        "tab" => Some('\t'),
+        "NewLine" => Some('\n'),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(html: &str) -> Vec<(LinkKind, String)> {
+        let page_url=url::Url::parse("http://a.com/dir/page.html").unwrap();
+        LinkExtractor::new().extract(html, &page_url).links.into_iter().map(|link| (link.kind, link.url.into_string())).collect()
+    }
+
+    #[test]
+    fn test_typed_links() {
+        let html=r#"<!DOCTYPE html>
+            <html><head>
+            <LINK REL="alternate stylesheet" href=/main.css>
+            <link rel=icon href=/favicon.ico>
+            <style>body { background: url("bg.png") } @import 'more.css';</style>
+            <script src="app.js"></script>
+            </head><body>
+            <a href='x.html?a=1&amp;b=2' class=nav>x</a>
+            <a href = other.html>other</a>
+            <img src="/img.png" alt="<a href=not-a-link.html>">
+            <iframe src="//b.com/frame"></iframe>
+            <form action="search"><input name=q></form>
+            <div style="background-image: url(div.png)"></div>
+            </body></html>"#;
+
+        assert_eq!(links(html), vec![
+            (LinkKind::Stylesheet, "http://a.com/main.css".to_string()),
+            (LinkKind::InlineStyle, "http://a.com/dir/bg.png".to_string()),
+            (LinkKind::InlineStyle, "http://a.com/dir/more.css".to_string()),
+            (LinkKind::Script, "http://a.com/dir/app.js".to_string()),
+            (LinkKind::Anchor, "http://a.com/dir/x.html?a=1&b=2".to_string()),
+            (LinkKind::Anchor, "http://a.com/dir/other.html".to_string()),
+            (LinkKind::Image, "http://a.com/img.png".to_string()),
+            (LinkKind::Iframe, "http://b.com/frame".to_string()),
+            (LinkKind::FormAction, "http://a.com/dir/search".to_string()),
+            (LinkKind::InlineStyle, "http://a.com/dir/div.png".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_ignored_text() {
+        let html=r#"<!-- <a href="commented.html"> -->
+            <script>var s='<a href="scripted.html">'; if (a<b) {}</script>
+            <textarea><a href="typed.html"></textarea>
+            <p>1 < 2 and href=text.html</p>
+            <a href="javascript:void(0)">js</a>
+            <a href=" JavaScript:alert(1)">js</a>
+            <a href="java&#x09;script:alert(1)">js</a>
+            <a href="mailto:me@a.com">mail</a>
+            <img src="data:image/png;base64,AAAA">
+            <a href="">empty</a>
+            <a name="no-href">
+            <a href="kept.html">kept</a>"#;
+
+        assert_eq!(links(html), vec![(LinkKind::Anchor, "http://a.com/dir/kept.html".to_string())]);
+    }
+
+    #[test]
+    fn test_base() {
+        let html=r#"<a href="before.html"></a><base href="/other/"><base href="http://c.com/"><a href="after.html"></a>"#;
+        assert_eq!(links(html), vec![
+            (LinkKind::Anchor, "http://a.com/other/before.html".to_string()),
+            (LinkKind::Anchor, "http://a.com/other/after.html".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_nofollow() {
+        let page_url=url::Url::parse("http://a.com/").unwrap();
+        let link_extractor=LinkExtractor::new();
+
+        let page=link_extractor.extract(r#"<a href="x.html" rel="external NoFollow">x</a><a href="y.html">y</a>"#, &page_url);
+        assert_eq!(page.links.iter().map(|link| link.nofollow).collect::<Vec<_>>(), vec![true, false]);
+        assert_eq!(page.directives, robots::PageDirectives::default());
+
+        let page=link_extractor.extract(r#"<meta name="ROBOTS" content="noindex"><meta name="description" content="nofollow"><meta name=robots content=nofollow>"#, &page_url);
+        assert_eq!(page.directives, robots::PageDirectives{noindex: true, nofollow: true});
+    }
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(decode_entities("a&amp;b&lt;&gt;&quot;&#39;&#x2F;&#47"), "a&b<>\"'//");
+        assert_eq!(decode_entities("?a=1&b=2&unknown;&"), "?a=1&b=2&unknown;&");
+        assert_eq!(decode_entities("&amp"), "&amp");
+    }
+}
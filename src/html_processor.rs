@@ -0,0 +1,152 @@
+use charset;
+use config;
+use crawl_error::CrawlError;
+use crawl_error;
+use html_links;
+use processor;
+use robots;
+use hyper;
+use url;
+use std::sync;
+
+/// Returns the media types of the resources links of kind `kind` point to, or
+/// None for links to pages, which may be of any media type.
+fn linked_mime_types(kind: html_links::LinkKind) -> Option<&'static [&'static str]> {
+    match kind {
+        html_links::LinkKind::Anchor | html_links::LinkKind::Iframe => None,
+        html_links::LinkKind::Stylesheet => Some(&["text/css"]),
+        html_links::LinkKind::InlineStyle => Some(&["text/css", "image/*", "font/*", "application/font-woff", "application/vnd.ms-fontobject", "application/x-font-ttf"]),
+        html_links::LinkKind::Script => Some(&["application/javascript", "text/javascript", "application/x-javascript"]),
+        html_links::LinkKind::Image => Some(&["image/*"]),
+        html_links::LinkKind::FormAction => Some(&[]),
+    }
+}
+
+/// Processor looking for unique links to pages and frames within html content,
+/// as well as to stylesheets, scripts, images and fonts some processor takes,
+/// which it hands on to be added to the reservoir. Links marked
+/// `rel="nofollow"`, and all links of pages whose robots meta tags or
+/// X-Robots-Tag headers say nofollow, are skipped.
+pub struct HtmlProcessor {
+    htmls_crawled: sync::Arc<sync::atomic::AtomicUsize>,
+    pages_noindex: sync::Arc<sync::atomic::AtomicUsize>,
+    pages_nofollow: sync::Arc<sync::atomic::AtomicUsize>,
+    links_nofollow: sync::Arc<sync::atomic::AtomicUsize>,
+    pages_rescued: sync::Arc<sync::atomic::AtomicUsize>,
+    errors: sync::Arc<crawl_error::ErrorCounters>,
+    config: sync::Arc<config::CrawlConfig>,
+    wanted: processor::MimeMatcher,
+    link_extractor: html_links::LinkExtractor,
+    hosts_nums: Vec<(String, usize)>,
+}
+
+impl HtmlProcessor {
+    /// Creates and returns a new HtmlProcessor structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `htmls_crawled` - Atomic counter that counts the times urls were gotten out of received html code.
+    /// * `pages_noindex` - Atomic counter that counts the html documents marked noindex.
+    /// * `pages_nofollow` - Atomic counter that counts the html documents whose links were not followed because of a nofollow directive.
+    /// * `links_nofollow` - Atomic counter that counts the links skipped because of `rel="nofollow"`.
+    /// * `pages_rescued` - Atomic counter that counts the documents that were not valid utf8 but could be decoded.
+    /// * `errors` - ErrorCounters counting the links that could not be used.
+    /// * `wanted` - Media types taken by the other processors, which links to resources other than pages must point to.
+    /// * `config` - Settings of the crawl (limits on the urls taken from a single html document, user agent and size limit).
+    pub fn new(htmls_crawled: sync::Arc<sync::atomic::AtomicUsize>, pages_noindex: sync::Arc<sync::atomic::AtomicUsize>, pages_nofollow: sync::Arc<sync::atomic::AtomicUsize>, links_nofollow: sync::Arc<sync::atomic::AtomicUsize>, pages_rescued: sync::Arc<sync::atomic::AtomicUsize>, errors: sync::Arc<crawl_error::ErrorCounters>, wanted: processor::MimeMatcher, config: sync::Arc<config::CrawlConfig>) -> HtmlProcessor {
+        HtmlProcessor{
+            htmls_crawled: htmls_crawled,
+            pages_noindex: pages_noindex,
+            pages_nofollow: pages_nofollow,
+            links_nofollow: links_nofollow,
+            pages_rescued: pages_rescued,
+            errors: errors,
+            hosts_nums: Vec::with_capacity(config.max_urls_per_site),
+            config: config,
+            wanted: wanted,
+            link_extractor: html_links::LinkExtractor::new(),
+        }
+    }
+}
+
+impl processor::ContentProcessor for HtmlProcessor {
+    fn name(&self) -> &'static str {
+        "html_processor"
+    }
+
+    fn mime_types(&self) -> Vec<String> {
+        vec!["text/html".to_string()]
+    }
+
+    fn max_bytes(&self) -> usize {
+        self.config.max_html_bytes
+    }
+
+    fn process(&mut self, url: &url::Url, headers: &hyper::Headers, body: &[u8], output: &mut processor::Output) -> Result<(), CrawlError> {
+        // Transcode the html content into a utf8 string. Invalid bytes are replaced, as the links around them can still be found.
+        let header_charset=processor::header_charset(headers);
+        let decoded=charset::decode(body, header_charset.as_ref().map(|c| c.as_str()), charset::DocumentKind::Html);
+        if decoded.rescued{
+            self.pages_rescued.fetch_add(1, sync::atomic::Ordering::Relaxed);
+        }
+        let html_content=decoded.text;
+
+        // Extract the links, and follow none of them if the page (or its headers) say so. Noindex pages are only counted, as nothing of them is kept.
+        let mut page=self.link_extractor.extract(&html_content, url);
+        page.directives.merge(robots::PageDirectives::from_headers(headers, self.config.user_agent.as_str()));
+        output.links=Some(page.links.len());
+        if page.directives.noindex{
+            self.pages_noindex.fetch_add(1, sync::atomic::Ordering::Relaxed);
+        }
+        if page.directives.nofollow{
+            self.pages_nofollow.fetch_add(1, sync::atomic::Ordering::Relaxed);
+            page.links.clear();
+        }
+        let nofollow_links=page.links.iter().filter(|link| link.nofollow).count();
+        self.links_nofollow.fetch_add(nofollow_links, sync::atomic::Ordering::Relaxed);
+
+        self.hosts_nums.clear();
+        // For every followable link to a page, or to something some processor takes, found within the html code (already an "absolute path" url).
+        let wanted=&self.wanted;
+        let links=page.links.into_iter().filter(|link| !link.nofollow && match linked_mime_types(link.kind) {
+            Some(mime_types) => mime_types.iter().any(|mime| wanted.overlaps(mime)),
+            None => true,
+        });
+        for link in links.take(self.config.max_urls_per_site){
+            let url=link.url;
+
+            // Make sure only a few url with the same host are gotten.
+            let surpassed_host_num_limit={
+                let host=match url.host_str() {
+                    Some(host) => host,
+                    None => {self.errors.record(self.name(), &CrawlError::MissingHost);continue;},
+                };
+
+                let (hosts_nums, max_host_sharing_urls_per_site)=(&mut self.hosts_nums, self.config.max_host_sharing_urls_per_site);
+                (||{
+                    for host_num in hosts_nums.iter_mut(){
+                        if (*host_num).0==host{
+                            if (*host_num).1<max_host_sharing_urls_per_site{
+                                (*host_num).1+=1;
+                                return false;
+                            } else {
+                                return true;
+                            }
+                        }
+                    }
+                    hosts_nums.push((host.into(), 0));
+                    return false;
+                })()
+            };
+            if surpassed_host_num_limit{
+                continue;
+            }
+
+            output.urls.push(url.into_string());
+        }
+
+        // Keep track of number of html code from websites were searched for urls with atomic counter `htmls_crawled`.
+        self.htmls_crawled.fetch_add(1, sync::atomic::Ordering::Relaxed);
+        Ok(())
+    }
+}
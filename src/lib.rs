@@ -0,0 +1,59 @@
+// #![feature(box_syntax)]
+
+extern crate rand;
+extern crate url;
+extern crate regex;
+extern crate futures;
+extern crate hyper;
+extern crate tokio_core;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
+extern crate encoding_rs;
+extern crate flate2;
+extern crate brotli_decompressor;
+extern crate bytes;
+extern crate rustls;
+extern crate tokio_io;
+extern crate tokio_rustls;
+extern crate tokio_service;
+extern crate webpki;
+extern crate webpki_roots;
+extern crate signal_hook;
+mod body_decoder;
+mod canonical;
+mod charset;
+mod checkpoint;
+pub mod config;
+pub mod crawl_error;
+pub mod crawler;
+mod murmur;
+mod bloom_filter;
+mod url_reservoir;
+mod css_processor;
+mod fetch_log;
+mod frontier;
+mod html_links;
+mod html_processor;
+mod metrics;
+mod outstanding;
+pub mod processor;
+mod priority_frontier;
+mod public_suffix;
+mod host_scheduler;
+mod redirect;
+pub mod report;
+mod retry;
+mod robots;
+mod scope;
+mod shutdown;
+#[cfg(test)]
+mod test_util;
+mod tls;
+mod url_enqueuer;
+mod url_meta;
+
+pub use crawler::{Crawler, CrawlerBuilder, CrawlerHandle, Stats};
+pub use processor::{ContentProcessor, Output, Artifact};
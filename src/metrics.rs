@@ -0,0 +1,235 @@
+#![allow(dead_code)]
+
+use futures;
+use hyper;
+use shutdown;
+use tokio_service;
+use futures::Future;
+use std::fmt::Write;
+use std::net;
+use std::sync;
+use std::thread;
+use std::time;
+
+/// Upper bounds (in seconds) of the buckets of the fetch latency histograms.
+pub const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 60.0];
+
+/// Histogram of durations with fixed buckets, which can be observed into from
+/// several threads at once.
+pub struct Histogram {
+    bounds: Vec<f64>,
+    /// Observations per bucket (not cumulative), the last one being the `+Inf` bucket.
+    buckets: Vec<sync::atomic::AtomicUsize>,
+    sum_micros: sync::atomic::AtomicUsize,
+}
+
+impl Histogram {
+    /// Creates and returns a new Histogram structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `bounds` - Ascending upper bounds of the buckets, in seconds.
+    pub fn new(bounds: &[f64]) -> Histogram {
+        Histogram{
+            bounds: bounds.to_vec(),
+            buckets: (0..bounds.len()+1).map(|_| sync::atomic::AtomicUsize::new(0)).collect(),
+            sum_micros: sync::atomic::AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts `duration` into its bucket.
+    pub fn observe(&self, duration: time::Duration) {
+        let seconds=duration.as_secs() as f64+duration.subsec_nanos() as f64/1e9;
+        let i=self.bounds.iter().position(|&bound| seconds<=bound).unwrap_or(self.bounds.len());
+        self.buckets[i].fetch_add(1, sync::atomic::Ordering::Relaxed);
+        self.sum_micros.fetch_add((seconds*1e6) as usize, sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Appends the histogram in the prometheus text format (without its HELP and
+    /// TYPE lines) to `out`.
+    ///
+    /// # Arguments
+    ///
+    /// * `out` - String to append to.
+    /// * `name` - Name of the metric.
+    /// * `labels` - Labels distinguishing this histogram from others of the same name, e.g. `outcome="ok"`.
+    pub fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator=if labels.is_empty() {""} else {","};
+        let mut cumulative=0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative+=bucket.load(sync::atomic::Ordering::Relaxed);
+            let bound=self.bounds.get(i).map_or("+Inf".to_string(), |bound| bound.to_string());
+            let _=writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, separator, bound, cumulative);
+        }
+        let sum=self.sum_micros.load(sync::atomic::Ordering::Relaxed) as f64/1e6;
+        let _=writeln!(out, "{}_sum{{{}}} {}", name, labels, sum);
+        let _=writeln!(out, "{}_count{{{}}} {}", name, labels, cumulative);
+    }
+}
+
+/// Latencies of the fetches, split by whether they were answered.
+pub struct FetchLatencies {
+    pub succeeded: Histogram,
+    pub failed: Histogram,
+}
+
+impl FetchLatencies {
+    /// Creates and returns a new FetchLatencies structure with the buckets of `LATENCY_BUCKETS`.
+    pub fn new() -> FetchLatencies {
+        FetchLatencies{
+            succeeded: Histogram::new(LATENCY_BUCKETS),
+            failed: Histogram::new(LATENCY_BUCKETS),
+        }
+    }
+
+    /// Counts the latency of a fetch.
+    ///
+    /// # Arguments
+    ///
+    /// * `latency` - Time the fetch took.
+    /// * `succeeded` - Whether a response was received (and its body, if wanted).
+    pub fn observe(&self, latency: time::Duration, succeeded: bool) {
+        if succeeded {
+            self.succeeded.observe(latency);
+        } else {
+            self.failed.observe(latency);
+        }
+    }
+}
+
+/// Returns the metrics in the prometheus text format.
+///
+/// # Arguments
+///
+/// * `counters` - Named counters of the crawl, exposed as `rustcrawl_<name>_total`.
+/// * `gauges` - Names, descriptions and current values of gauges, exposed as `rustcrawl_<name>`.
+/// * `latencies` - Latencies of the fetches, exposed as `rustcrawl_fetch_duration_seconds`.
+pub fn render(counters: &[(&str, sync::Arc<sync::atomic::AtomicUsize>)], gauges: &[(&str, &str, f64)], latencies: &FetchLatencies) -> String {
+    let mut out=String::new();
+    for &(name, ref counter) in counters.iter() {
+        let _=writeln!(out, "# TYPE rustcrawl_{}_total counter", name);
+        let _=writeln!(out, "rustcrawl_{}_total {}", name, counter.load(sync::atomic::Ordering::Relaxed));
+    }
+    for &(name, help, value) in gauges.iter() {
+        let _=writeln!(out, "# HELP rustcrawl_{} {}", name, help);
+        let _=writeln!(out, "# TYPE rustcrawl_{} gauge", name);
+        let _=writeln!(out, "rustcrawl_{} {}", name, value);
+    }
+    let _=writeln!(out, "# HELP rustcrawl_fetch_duration_seconds Time fetches took, redirects and body included.");
+    let _=writeln!(out, "# TYPE rustcrawl_fetch_duration_seconds histogram");
+    latencies.succeeded.render(&mut out, "rustcrawl_fetch_duration_seconds", "outcome=\"succeeded\"");
+    latencies.failed.render(&mut out, "rustcrawl_fetch_duration_seconds", "outcome=\"failed\"");
+    out
+}
+
+/// Service answering `GET /metrics` with the output of `render`.
+#[derive(Clone)]
+struct MetricsService {
+    render: sync::Arc<Fn() -> String + Send + Sync>,
+}
+
+impl tokio_service::Service for MetricsService {
+    type Request = hyper::Request;
+    type Response = hyper::Response;
+    type Error = hyper::Error;
+    type Future = futures::future::FutureResult<hyper::Response, hyper::Error>;
+
+    fn call(&self, req: hyper::Request) -> Self::Future {
+        let response=match (req.method(), req.path()) {
+            (&hyper::Method::Get, "/metrics") => {
+                let body=(self.render)();
+                hyper::Response::new()
+                .with_header(hyper::header::ContentType("text/plain; version=0.0.4".parse().unwrap()))
+                .with_header(hyper::header::ContentLength(body.len() as u64))
+                .with_body(body)
+            },
+            _ => hyper::Response::new().with_status(hyper::StatusCode::NotFound),
+        };
+        futures::future::ok(response)
+    }
+}
+
+/// Starts a thread serving the metrics returned by `render` at
+/// `http://<address>/metrics`, to be scraped by prometheus, until `shutdown`
+/// is requested.
+///
+/// # Arguments
+///
+/// * `address` - Socket address to listen on.
+/// * `render` - Returns the current metrics in the prometheus text format.
+/// * `shutdown` - Shutdown flag telling the server to stop.
+pub fn serve<F: Fn() -> String + Send + Sync + 'static>(address: net::SocketAddr, render: F, shutdown: shutdown::Shutdown) -> thread::JoinHandle<()> {
+    let service=MetricsService{render: sync::Arc::new(render)};
+    thread::spawn(move || {
+        let (stop_sender, stop_receiver)=futures::sync::oneshot::channel::<()>();
+        thread::spawn(move || {
+            shutdown.wait(time::Duration::from_millis(100));
+            let _=stop_sender.send(());
+        });
+
+        let server=hyper::server::Http::new().bind(&address, move || Ok(service.clone()));
+        match server.and_then(|server| server.run_until(stop_receiver.map_err(|_| ()))) {
+            Ok(_) => {},
+            Err(e) => eprintln!("Error (metrics): {:?}", e),
+        }
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_histogram() {
+        let histogram=Histogram::new(&[0.1, 1.0]);
+        histogram.observe(time::Duration::from_millis(50));
+        histogram.observe(time::Duration::from_millis(100));
+        histogram.observe(time::Duration::from_millis(500));
+        histogram.observe(time::Duration::from_secs(3));
+        let mut out=String::new();
+        histogram.render(&mut out, "latency", "outcome=\"ok\"");
+        assert_eq!(out, "latency_bucket{outcome=\"ok\",le=\"0.1\"} 2\nlatency_bucket{outcome=\"ok\",le=\"1\"} 3\nlatency_bucket{outcome=\"ok\",le=\"+Inf\"} 4\nlatency_sum{outcome=\"ok\"} 3.65\nlatency_count{outcome=\"ok\"} 4\n");
+    }
+
+    #[test]
+    fn test_serve() {
+        let counters=vec![("urls_gotten", sync::Arc::new(sync::atomic::AtomicUsize::new(7)))];
+        let latencies=sync::Arc::new(FetchLatencies::new());
+        latencies.observe(time::Duration::from_millis(20), true);
+
+        // Find a free port to serve on.
+        let address=net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let shutdown=shutdown::Shutdown::new();
+        let server={
+            let latencies=latencies.clone();
+            serve(address, move || render(&counters, &[("reservoir_urls", "Urls in the reservoir.", 3.0)], &latencies), shutdown.clone())
+        };
+
+        let get=|path: &str| {
+            let mut stream=(0..50).filter_map(|_| {
+                thread::sleep(time::Duration::from_millis(20));
+                net::TcpStream::connect(address).ok()
+            }).next().unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).unwrap();
+            let mut response=String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let response=get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("\r\n\r\n# TYPE rustcrawl_urls_gotten_total counter\nrustcrawl_urls_gotten_total 7\n"));
+        assert!(response.contains("\nrustcrawl_reservoir_urls 3\n"));
+        assert!(response.contains("\nrustcrawl_fetch_duration_seconds_bucket{outcome=\"succeeded\",le=\"0.05\"} 1\n"));
+        assert!(response.contains("\nrustcrawl_fetch_duration_seconds_count{outcome=\"failed\"} 0\n"));
+
+        assert!(get("/other").starts_with("HTTP/1.1 404 Not Found"));
+
+        // The server stops once the shutdown is requested.
+        shutdown.request();
+        server.join().unwrap();
+        assert!(net::TcpStream::connect(address).is_err());
+    }
+}
@@ -18,8 +18,8 @@ pub fn murmur_hash3_x64_128(input: &[u8], seed: u32) -> (u64,u64) {
 
     for i in 0..nblocks{
         let (mut k1, mut k2):(u64,u64)=unsafe{
-            ((0..8).fold(0u64, |acc, n| acc|(*input.get_unchecked(i*16+n) as u64) << n),
-            (0..8).fold(0u64, |acc, n| acc|(*input.get_unchecked(i*16+n+8) as u64) << n))
+            ((0..8).fold(0u64, |acc, n| acc|(*input.get_unchecked(i*16+n) as u64) << (8*n)),
+            (0..8).fold(0u64, |acc, n| acc|(*input.get_unchecked(i*16+n+8) as u64) << (8*n)))
         };
 
         k1=k1.wrapping_mul(c1);
@@ -143,9 +143,9 @@ pub fn murmur_hash3_32(input: &[u8], seed: u32) -> u32 {
     let c1=0xcc9e2d51u32;
     let c2=0x1b873593u32;
 
-    for chunk in input.chunks(4).rev().skip_while(|x| x.len()<4){ // for i in (0..nblocks).rev(){ let chunk=&input[i*4..i*4+4];
+    for chunk in input.chunks(4).take(nblocks){
         assert!(chunk.len()==4);
-        let mut k1=chunk.iter().enumerate().fold(0u32, |acc, (i, &e)| acc|(e as u32) << i);
+        let mut k1=chunk.iter().enumerate().fold(0u32, |acc, (i, &e)| acc|(e as u32) << (8*i));
         k1=k1.wrapping_mul(c1);
         k1=k1.rotate_left(15);
         k1=k1.wrapping_mul(c2);
@@ -239,8 +239,8 @@ impl hash::Hasher for MurmurHasher {
                 const C2: u64 = 0x4cf5ad432745937fu64;
 
                 let (mut k1, mut k2):(u64,u64)=unsafe{
-                    ((0..8).fold(0u64, |acc, n| acc|(*self.bytes.get_unchecked(n) as u64) << n),
-                    (0..8).fold(0u64, |acc, n| acc|(*self.bytes.get_unchecked(n+8) as u64) << n))
+                    ((0..8).fold(0u64, |acc, n| acc|(*self.bytes.get_unchecked(n) as u64) << (8*n)),
+                    (0..8).fold(0u64, |acc, n| acc|(*self.bytes.get_unchecked(n+8) as u64) << (8*n)))
                 };
 
                 k1=k1.wrapping_mul(C1);
@@ -357,4 +357,41 @@ impl hash::Hasher for MurmurHasher {
 
         h1
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_values() {
+        let input=b"The quick brown fox jumps over the lazy dog";
+        assert_eq!(murmur_hash3_32(b"hello", 0), 0x248bfa47);
+        assert_eq!(murmur_hash3_32(input, 0), 0x2e4ff723);
+        assert_eq!(murmur_hash3_32(input, 42), 0x347ca102);
+        assert_eq!(murmur_hash3_x64_128(b"hello", 0), (0xcbd8a7b341bd9b02, 0x5b1e906a48ae1d19));
+        assert_eq!(murmur_hash3_x64_128(input, 0), (0xe34bbc7bbc071b6c, 0x7a433ca9c49a9347));
+        assert_eq!(murmur_hash3_x64_128(input, 42), (0x740dcf93fe0bd5d7, 0xc4546cf4ec705c8f));
+    }
+
+    #[test]
+    fn test_hasher_reference_values() {
+        use std::hash::{BuildHasher, Hasher};
+
+        let input=b"The quick brown fox jumps over the lazy dog";
+        let mut hasher=MurmurHasher::new(0);
+        hasher.write(input);
+        assert_eq!(hasher.finish(), 0xe34bbc7bbc071b6c);
+
+        // The hash does not depend on how the input is split into writes, across 16-byte blocks or not.
+        for len in [0, 1, 5, 15, 16, 17, 32, input.len()].iter() {
+            for &split in [0, 1, 7, 16].iter() {
+                let (head, tail)=input[..*len].split_at(split.min(*len));
+                let mut hasher=MurmurHasherBuilder::new(42).build_hasher();
+                hasher.write(head);
+                hasher.write(tail);
+                assert_eq!(hasher.finish(), murmur_hash3_x64_128(&input[..*len], 42).0, "len {}, split {}", len, split);
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+#![allow(dead_code)]
+
+use checkpoint;
+use url_meta;
+use std::collections;
+use std::io;
+use std::sync;
+
+/// Urls taken from the frontier (or the retry queue) whose fetch has not
+/// finished yet, as they wait for their robots.txt, their host or the IO loop,
+/// or are being fetched. Neither the frontier nor the retry queue holds them
+/// meanwhile, so they are saved in checkpoints on their own, along with whether
+/// they were sent (and thus added to the bloom filter) already.
+pub struct Outstanding {
+    urls: sync::Mutex<collections::HashMap<String, (url_meta::UrlMeta, bool)>>,
+}
+
+impl Outstanding {
+    /// Creates and returns a new, empty Outstanding structure.
+    pub fn new() -> Outstanding {
+        Outstanding{urls: sync::Mutex::new(collections::HashMap::new())}
+    }
+
+    /// Returns the amount of urls outstanding.
+    pub fn len(&self) -> usize {
+        match self.urls.lock() {
+            Ok(mutex_guard) => mutex_guard.len(),
+            Err(e) => {eprintln!("Error (outstanding): {:?}", e);0},
+        }
+    }
+
+    /// Records that `url` (with `meta`) was taken to be fetched.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The url, as its hyper::Uri is written.
+    /// * `meta` - UrlMeta of the url.
+    /// * `sent` - Whether the url was sent before, as retries were.
+    pub fn taken(&self, url: String, meta: &url_meta::UrlMeta, sent: bool) {
+        match self.urls.lock() {
+            Ok(mut mutex_guard) => {mutex_guard.insert(url, (meta.clone(), sent));},
+            Err(e) => eprintln!("Error (outstanding): {:?}", e),
+        }
+    }
+
+    /// Records that `url` was added to the bloom filter, to be sent.
+    pub fn sent(&self, url: &str) {
+        match self.urls.lock() {
+            Ok(mut mutex_guard) => {
+                if let Some(entry)=mutex_guard.get_mut(url) {
+                    entry.1=true;
+                }
+            },
+            Err(e) => eprintln!("Error (outstanding): {:?}", e),
+        }
+    }
+
+    /// Records that `url` is no longer outstanding, as it was fetched, dropped,
+    /// or handed to the frontier or the retry queue.
+    pub fn done(&self, url: &str) {
+        match self.urls.lock() {
+            Ok(mut mutex_guard) => {mutex_guard.remove(url);},
+            Err(e) => eprintln!("Error (outstanding): {:?}", e),
+        }
+    }
+
+    /// Returns the urls outstanding, along with their UrlMeta and whether they were sent.
+    pub fn urls(&self) -> Vec<(String, url_meta::UrlMeta, bool)> {
+        match self.urls.lock() {
+            Ok(mutex_guard) => mutex_guard.iter().map(|(url, &(ref meta, sent))| (url.clone(), meta.clone(), sent)).collect(),
+            Err(e) => {eprintln!("Error (outstanding): {:?}", e);Vec::new()},
+        }
+    }
+}
+
+/// Writes `urls` (along with their UrlMeta and whether they were sent) to `w`, for checkpoints.
+pub fn write_urls<W: io::Write>(w: &mut W, urls: &[(String, url_meta::UrlMeta, bool)]) -> io::Result<()> {
+    checkpoint::write_u64(w, urls.len() as u64)?;
+    for &(ref url, ref meta, sent) in urls.iter() {
+        checkpoint::write_bytes(w, url.as_bytes())?;
+        meta.write_to(w)?;
+        checkpoint::write_u32(w, sent as u32)?;
+    }
+    Ok(())
+}
+
+/// Reads urls written by `write_urls` from `r`.
+pub fn read_urls<R: io::Read>(r: &mut R) -> io::Result<Vec<(String, url_meta::UrlMeta, bool)>> {
+    let len=checkpoint::read_u64(r)?;
+    let mut urls=Vec::new();
+    let mut parents=collections::HashMap::new();
+    for _ in 0..len {
+        let url=String::from_utf8(checkpoint::read_bytes(r, url_meta::MAX_URL_LEN)?).map_err(|_| checkpoint::invalid_data("url is not utf8"))?;
+        let meta=url_meta::UrlMeta::read_from(r, &mut parents)?;
+        let sent=checkpoint::read_u32(r)?!=0;
+        urls.push((url, meta, sent));
+    }
+    Ok(urls)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outstanding() {
+        let outstanding=Outstanding::new();
+        let meta=url_meta::UrlMeta{depth: 2, parent: Some(sync::Arc::from("http://a.com/")), discovered_secs: 7};
+        outstanding.taken("http://a.com/x".to_string(), &meta, false);
+        outstanding.taken("http://a.com/y".to_string(), &meta, false);
+        outstanding.taken("http://b.com/".to_string(), &meta, true);
+        outstanding.sent("http://a.com/x");
+        outstanding.done("http://a.com/y");
+        assert_eq!(outstanding.len(), 2);
+
+        let mut urls=outstanding.urls();
+        urls.sort();
+        assert_eq!(urls, vec![("http://a.com/x".to_string(), meta.clone(), true), ("http://b.com/".to_string(), meta.clone(), true)]);
+
+        let mut buf=Vec::new();
+        write_urls(&mut buf, &urls).unwrap();
+        assert_eq!(read_urls(&mut io::Cursor::new(buf)).unwrap(), urls);
+    }
+}
@@ -0,0 +1,490 @@
+#![allow(dead_code)]
+use checkpoint;
+use url_meta;
+use std::cmp;
+use std::collections;
+use std::io;
+use std::sync;
+
+/// Score an url loses for every link followed from a seed to it.
+const DEPTH_WEIGHT: i64 = 100;
+/// Score an url gains every time the number of pages linking to it doubles.
+const INLINK_WEIGHT: i64 = 40;
+/// Score an url loses every time the number of other urls of its host waiting doubles.
+const HOST_WEIGHT: i64 = 20;
+/// Score of urls whose extension says they are pages (or that have none).
+const PAGE_HINT: i64 = 50;
+/// Score of urls whose extension says they are of a kind no processor reads.
+const BINARY_HINT: i64 = -400;
+
+/// Extensions of urls that lead to pages, as far as their name tells.
+const PAGE_EXTENSIONS: &[&str] = &["html", "htm", "xhtml", "php", "asp", "aspx", "jsp", "shtml", "cgi"];
+/// Extensions of urls that lead to content other than pages, along with the media
+/// types they stand for, as far as their name tells. Unless some processor reads
+/// one of its media types, an extension hints at content no processor reads.
+const BINARY_EXTENSIONS: &[(&str, &str)] = &[
+    ("jpg", "image/jpeg"), ("jpeg", "image/jpeg"), ("png", "image/png"), ("gif", "image/gif"), ("webp", "image/webp"),
+    ("svg", "image/svg+xml"), ("ico", "image/x-icon"), ("ico", "image/vnd.microsoft.icon"), ("bmp", "image/bmp"),
+    ("tif", "image/tiff"), ("tiff", "image/tiff"),
+    ("mp3", "audio/mpeg"), ("mp4", "video/mp4"), ("m4a", "audio/mp4"), ("avi", "video/x-msvideo"), ("mov", "video/quicktime"),
+    ("mkv", "video/x-matroska"), ("webm", "video/webm"), ("ogg", "audio/ogg"), ("wav", "audio/wav"), ("flac", "audio/flac"),
+    ("pdf", "application/pdf"), ("zip", "application/zip"), ("gz", "application/gzip"), ("tgz", "application/gzip"),
+    ("bz2", "application/x-bzip2"), ("xz", "application/x-xz"), ("rar", "application/vnd.rar"), ("7z", "application/x-7z-compressed"),
+    ("tar", "application/x-tar"), ("exe", "application/vnd.microsoft.portable-executable"), ("dmg", "application/x-apple-diskimage"),
+    ("iso", "application/x-iso9660-image"), ("apk", "application/vnd.android.package-archive"), ("msi", "application/x-msi"),
+    ("bin", "application/octet-stream"),
+    ("doc", "application/msword"), ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("xls", "application/vnd.ms-excel"), ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("ppt", "application/vnd.ms-powerpoint"), ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("woff", "font/woff"), ("woff2", "font/woff2"), ("ttf", "font/ttf"), ("otf", "font/otf"), ("eot", "application/vnd.ms-fontobject"),
+    ("js", "application/javascript"), ("js", "text/javascript"), ("json", "application/json"), ("xml", "application/xml"), ("xml", "text/xml"),
+];
+
+/// Returns the number of bits needed to write `n`, i.e. how many times it doubled from 1.
+fn bits(n: u32) -> i64 {
+    (32-n.leading_zeros()) as i64
+}
+
+/// Returns the host (along with the port, if any) of `url`, or an empty string if there is none.
+fn host_of(url: &str) -> &str {
+    let rest=match url.find("://") {
+        Some(i) => &url[i+3..],
+        None => return "",
+    };
+    let end=rest.find(|c: char| c=='/' || c=='?' || c=='#').unwrap_or(rest.len());
+    let host=&rest[..end];
+    host.rfind('@').map_or(host, |i| &host[i+1..])
+}
+
+/// Returns the score the extension of the path of `url` hints at: that of pages
+/// for known page extensions and paths without any, that of binaries for those
+/// of `binary_extensions`, and 0 for the others (e.g. css).
+fn extension_hint(url: &str, binary_extensions: &[&str]) -> i64 {
+    let after_host=url.find("://").map_or(url, |i| &url[i+3..]);
+    let path=match after_host.find('/') {
+        Some(i) => &after_host[i..],
+        None => return PAGE_HINT,
+    };
+    let path=path.split(|c: char| c=='?' || c=='#').next().unwrap_or("");
+    let name=&path[path.rfind('/').map_or(0, |i| i+1)..];
+    let extension=match name.rfind('.') {
+        Some(i) => name[i+1..].to_lowercase(),
+        None => return PAGE_HINT,
+    };
+    if PAGE_EXTENSIONS.contains(&extension.as_str()) {
+        PAGE_HINT
+    } else if binary_extensions.contains(&extension.as_str()) {
+        BINARY_HINT
+    } else {
+        0
+    }
+}
+
+/// Returns the score of an url as far as the url itself tells, the higher the
+/// sooner it is to be crawled. The penalty of its host comes on top of it.
+///
+/// # Arguments
+///
+/// * `url` - The url.
+/// * `meta` - UrlMeta of the url (its depth counts).
+/// * `inlinks` - Number of times the url was found.
+/// * `binary_extensions` - Extensions hinting at content no processor reads.
+pub fn score(url: &str, meta: &url_meta::UrlMeta, inlinks: u32, binary_extensions: &[&str]) -> i64 {
+    -DEPTH_WEIGHT*meta.depth as i64+INLINK_WEIGHT*bits(inlinks)+extension_hint(url, binary_extensions)
+}
+
+/// Returns the score the urls of a host lose while `waiting` of its urls are waiting.
+pub fn host_penalty(waiting: usize) -> i64 {
+    HOST_WEIGHT*bits(cmp::min(waiting.saturating_sub(1), u32::max_value() as usize) as u32)
+}
+
+/// Score of an url along with the order it was added in, which breaks ties
+/// between equal scores (first added, first out).
+type Key = (i64, cmp::Reverse<u64>);
+
+/// Url waiting in a PriorityFrontier, along with what its score is made of.
+struct Entry {
+    meta: url_meta::UrlMeta,
+    inlinks: u32,
+    /// Order the url was added in.
+    seq: u64,
+    /// Score of the url, without the penalty of its host.
+    score: i64,
+}
+
+impl Entry {
+    fn key(&self) -> Key {
+        (self.score, cmp::Reverse(self.seq))
+    }
+}
+
+/// Data structure holding a large but finite amount of urls, each with a score
+/// made of its depth, the number of pages linking to it, how many other urls of
+/// its host are waiting and what its extension hints at. Urls are retrieved
+/// highest score first, and adding urls beyond capacity drops the lowest scored
+/// ones (possibly the added url itself).
+///
+/// Urls found again while waiting are not added twice, but have their number of
+/// in-links (and thus their score) raised, and keep the shallowest UrlMeta found.
+/// The penalty of a host follows the number of its urls waiting, so the urls of
+/// a host move up as they are crawled. As it is the same for all of them, urls
+/// are indexed by score per host, and hosts by the score of their best and worst
+/// url once penalized. Each url is held once, shared between the index by url
+/// and that of its host, so an url takes about twice the space it takes in a
+/// UrlReservoir.
+pub struct PriorityFrontier {
+    entries: collections::HashMap<sync::Arc<str>, Entry>,
+    /// Urls waiting per host, by score.
+    hosts: collections::HashMap<String, collections::BTreeMap<Key, sync::Arc<str>>>,
+    /// Hosts by the penalized score of their highest scored url.
+    best: collections::BTreeMap<Key, String>,
+    /// Hosts by the penalized score of their lowest scored url.
+    worst: collections::BTreeMap<Key, String>,
+    size: usize,
+    next_seq: u64,
+    /// Extensions hinting at content no processor reads.
+    binary_extensions: Vec<&'static str>,
+}
+
+impl PriorityFrontier {
+    /// Creates and returns a new PriorityFrontier structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `starting_urls` - urls the structure should contain right after creation, as seeds.
+    /// * `size` - amount of urls the structure can hold before it starts dropping them.
+    pub fn new(starting_urls: Vec<String>, size: usize) -> PriorityFrontier {
+        let mut frontier=PriorityFrontier{
+            entries: collections::HashMap::new(),
+            hosts: collections::HashMap::new(),
+            best: collections::BTreeMap::new(),
+            worst: collections::BTreeMap::new(),
+            size: size,
+            next_seq: 0,
+            binary_extensions: BINARY_EXTENSIONS.iter().map(|&(extension, _)| extension).collect(),
+        };
+        let meta=url_meta::UrlMeta::seed();
+        frontier.add_urls(starting_urls.into_iter().map(|url| (url, meta.clone())).collect());
+        frontier
+    }
+
+    /// Returns the amount of urls contained within the PriorityFrontier structure.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the keys of the best and worst urls of `host` once penalized, or None if none of its urls are waiting.
+    fn host_keys(&self, host: &str) -> Option<(Key, Key)> {
+        let urls=self.hosts.get(host)?;
+        let penalty=host_penalty(urls.len());
+        let penalized=|key: &Key| (key.0-penalty, key.1);
+        match (urls.keys().next_back(), urls.keys().next()) {
+            (Some(best), Some(worst)) => Some((penalized(best), penalized(worst))),
+            _ => None,
+        }
+    }
+
+    /// Takes `host` out of the indexes of hosts, before its urls change.
+    fn detach(&mut self, host: &str) {
+        if let Some((best, worst))=self.host_keys(host) {
+            self.best.remove(&best);
+            self.worst.remove(&worst);
+        }
+    }
+
+    /// Puts `host` back into the indexes of hosts, once its urls changed.
+    fn attach(&mut self, host: &str) {
+        match self.host_keys(host) {
+            Some((best, worst)) => {
+                self.best.insert(best, host.to_string());
+                self.worst.insert(worst, host.to_string());
+            },
+            None => {self.hosts.remove(host);},
+        }
+    }
+
+    /// Adds `url` (found `inlinks` times) to the structure, or counts it as found
+    /// once more if it is already contained. Returns whether it was kept.
+    fn add(&mut self, url: String, meta: url_meta::UrlMeta, inlinks: u32) -> bool {
+        let host=host_of(url.as_str()).to_string();
+        if self.entries.contains_key(url.as_str()) {
+            self.detach(host.as_str());
+            let entry=self.entries.get_mut(url.as_str()).expect("PriorityFrontier needs fixing: entry vanished");
+            let urls=self.hosts.get_mut(host.as_str()).expect("PriorityFrontier needs fixing: host missing from hosts");
+            let shared=urls.remove(&entry.key()).expect("PriorityFrontier needs fixing: entry missing from its host");
+            entry.inlinks=entry.inlinks.saturating_add(inlinks);
+            if meta.depth<entry.meta.depth {
+                entry.meta=meta;
+            }
+            entry.score=score(&shared, &entry.meta, entry.inlinks, &self.binary_extensions);
+            urls.insert(entry.key(), shared);
+            self.attach(host.as_str());
+            return true;
+        }
+        if self.size==0 {
+            return false;
+        }
+
+        let entry=Entry{score: score(url.as_str(), &meta, inlinks, &self.binary_extensions), meta: meta, inlinks: inlinks, seq: self.next_seq};
+        self.next_seq+=1;
+
+        // Make room by dropping the lowest scored url, unless the new one scores no higher once its host is penalized.
+        if self.entries.len()>=self.size {
+            let waiting=self.hosts.get(host.as_str()).map_or(0, |urls| urls.len());
+            let penalized=(entry.score-host_penalty(waiting+1), cmp::Reverse(entry.seq));
+            let lowest=self.worst.iter().next().map(|(&key, lowest_host)| (key, lowest_host.clone()));
+            match lowest {
+                Some((key, lowest_host)) if key<penalized => {
+                    let lowest_key=self.hosts.get(lowest_host.as_str()).and_then(|urls| urls.keys().next().cloned());
+                    if let Some(lowest_key)=lowest_key {
+                        self.remove(lowest_host.as_str(), lowest_key);
+                    }
+                },
+                _ => return false,
+            }
+        }
+
+        let shared:sync::Arc<str>=sync::Arc::from(url.as_str());
+        self.detach(host.as_str());
+        self.hosts.entry(host.clone()).or_insert_with(collections::BTreeMap::new).insert(entry.key(), shared.clone());
+        self.entries.insert(shared, entry);
+        self.attach(host.as_str());
+        true
+    }
+
+    /// Removes the url of `host` with score key `key`, returning it along with its entry.
+    fn remove(&mut self, host: &str, key: Key) -> Option<(sync::Arc<str>, Entry)> {
+        self.detach(host);
+        let shared=self.hosts.get_mut(host).and_then(|urls| urls.remove(&key));
+        self.attach(host);
+        let shared=shared?;
+        let entry=self.entries.remove(&shared).expect("PriorityFrontier needs fixing: entry missing from entries");
+        Some((shared, entry))
+    }
+
+    /// Adds urls to the PriorityFrontier structure, dropping the lowest scored
+    /// ones if it is already full.
+    ///
+    /// # Arguments
+    ///
+    /// * `urls` - vector of urls (with their UrlMeta) to add to the PriorityFrontier structure.
+    pub fn add_urls(&mut self, urls: Vec<(String, url_meta::UrlMeta)>) {
+        for (url, meta) in urls.into_iter() {
+            self.add(url, meta, 1);
+        }
+        assert!(self.entries.len()<=self.size, "PriorityFrontier needs fixing: size was exceeded in add_urls");
+    }
+
+    /// Adds urls to the PriorityFrontier structure, dropping the lowest scored
+    /// ones if it is already full. In contrast to add_urls, it does not consume
+    /// the vector itself, but only its contents.
+    ///
+    /// # Arguments
+    ///
+    /// * `urls` - vector of urls (with their UrlMeta) to add to the PriorityFrontier structure.
+    pub fn add_urls_popping(&mut self, urls: &mut Vec<(String, url_meta::UrlMeta)>) {
+        while let Some((url, meta))=urls.pop() {
+            self.add(url, meta, 1);
+        }
+        assert!(self.entries.len()<=self.size, "PriorityFrontier needs fixing: size was exceeded in add_urls_popping");
+    }
+
+    /// Stops scoring down the urls whose extension stands for a media type some
+    /// processor reads, and scores the contained urls again.
+    ///
+    /// # Arguments
+    ///
+    /// * `read` - Returns whether some processor reads a media type.
+    pub fn lift_binary_hint<F: Fn(&str) -> bool>(&mut self, read: F) {
+        let lifted:Vec<&str>=BINARY_EXTENSIONS.iter().filter(|&&(_, mime)| read(mime)).map(|&(extension, _)| extension).collect();
+        self.binary_extensions.retain(|extension| !lifted.contains(extension));
+
+        // Urls are added back in the order they were first added, so ties are still broken the same way.
+        let mut entries:Vec<(sync::Arc<str>, Entry)>=self.entries.drain().collect();
+        entries.sort_by_key(|&(_, ref entry)| entry.seq);
+        self.hosts.clear();
+        self.best.clear();
+        self.worst.clear();
+        for (url, entry) in entries.into_iter() {
+            self.add(url.to_string(), entry.meta, entry.inlinks);
+        }
+    }
+
+    /// Keeps only the urls for which `f` returns true.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - Returns whether an url is to be kept.
+    pub fn retain<F: FnMut(&str) -> bool>(&mut self, mut f: F) {
+        let dropped:Vec<(String, Key)>=self.entries.iter()
+        .filter(|&(url, _)| !f(url))
+        .map(|(url, entry)| (host_of(url).to_string(), entry.key()))
+        .collect();
+        for (host, key) in dropped.into_iter() {
+            self.remove(host.as_str(), key);
+        }
+    }
+
+    /// Retrieves the highest scored of the contained urls along with its UrlMeta,
+    /// or None if the PriorityFrontier structure is empty.
+    pub fn get_url(&mut self) -> Option<(String, url_meta::UrlMeta)> {
+        let host=self.best.values().next_back().cloned()?;
+        let highest=self.hosts.get(host.as_str()).and_then(|urls| urls.keys().next_back().cloned())?;
+        self.remove(host.as_str(), highest).map(|(url, entry)| (url.to_string(), entry.meta))
+    }
+
+    /// Writes the contained urls, highest scored first, to `w` along with their
+    /// UrlMeta and number of in-links.
+    ///
+    /// # Arguments
+    ///
+    /// * `w` - Writer to write to.
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let mut ranked=Vec::with_capacity(self.entries.len());
+        for urls in self.hosts.values() {
+            let penalty=host_penalty(urls.len());
+            ranked.extend(urls.iter().map(|(key, shared)| ((key.0-penalty, key.1), shared)));
+        }
+        ranked.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+
+        checkpoint::write_u64(w, ranked.len() as u64)?;
+        for (_, shared) in ranked.into_iter() {
+            let entry=&self.entries[shared];
+            checkpoint::write_bytes(w, shared.as_bytes())?;
+            entry.meta.write_to(w)?;
+            checkpoint::write_u32(w, entry.inlinks)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a PriorityFrontier structure written by `write_to` from `r`. Urls
+    /// are added back highest scored first, so if there are more than `size`,
+    /// the lowest scored ones are dropped. Urls found in the same page share
+    /// their parent again.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - Reader to read from.
+    /// * `size` - amount of urls the structure can hold before it starts dropping them.
+    pub fn read_from<R: io::Read>(r: &mut R, size: usize) -> io::Result<PriorityFrontier> {
+        let mut frontier=PriorityFrontier::new(Vec::new(), size);
+        let len=checkpoint::read_u64(r)?;
+        let mut parents=collections::HashMap::new();
+        for _ in 0..len {
+            let url=String::from_utf8(checkpoint::read_bytes(r, url_meta::MAX_URL_LEN)?).map_err(|_| checkpoint::invalid_data("url is not utf8"))?;
+            let meta=url_meta::UrlMeta::read_from(r, &mut parents)?;
+            let inlinks=checkpoint::read_u32(r)?;
+            if frontier.len()<size {
+                frontier.add(url, meta, inlinks);
+            }
+        }
+        Ok(frontier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at_depth(depth: u16) -> url_meta::UrlMeta {
+        url_meta::UrlMeta{depth: depth, parent: None, discovered_secs: 0}
+    }
+
+    #[test]
+    fn test_score() {
+        assert_eq!(host_of("http://user@a.com:8080/x?y#z"), "a.com:8080");
+        assert_eq!(host_of("https://b.com"), "b.com");
+        assert_eq!(host_of("nothing"), "");
+        let binary=&["png", "pdf"];
+        assert_eq!(extension_hint("http://a.com", binary), PAGE_HINT);
+        assert_eq!(extension_hint("http://a.com/x/", binary), PAGE_HINT);
+        assert_eq!(extension_hint("http://a.com/x.HTML?y.png", binary), PAGE_HINT);
+        assert_eq!(extension_hint("http://a.com/x.png#y", binary), BINARY_HINT);
+        assert_eq!(extension_hint("http://a.com/x.png#y", &["pdf"]), 0);
+        assert_eq!(extension_hint("http://a.com/s.css", binary), 0);
+        assert_eq!(extension_hint("http://a.b.com/dir.v2/", binary), PAGE_HINT);
+
+        let base=score("http://a.com/", &at_depth(1), 1, binary);
+        assert!(score("http://a.com/", &at_depth(0), 1, binary)>base);
+        assert!(score("http://a.com/", &at_depth(1), 2, binary)>base);
+        assert!(score("http://a.com/x.pdf", &at_depth(1), 1, binary)<base);
+        assert_eq!((host_penalty(0), host_penalty(1)), (0, 0));
+        assert!(host_penalty(2)>0 && host_penalty(5)>host_penalty(2));
+    }
+
+    #[test]
+    fn test_priority_frontier() {
+        let mut frontier=PriorityFrontier::new(vec!["http://a.com/".into()], 4);
+        frontier.add_urls(vec![("http://b.com/x.zip".into(), at_depth(1)), ("http://b.com/y".into(), at_depth(2)), ("http://c.com/z".into(), at_depth(1))]);
+        assert_eq!(frontier.len(), 4);
+
+        // Links to a waiting url raise its score instead of adding it twice, and it keeps the shallowest UrlMeta.
+        let mut v=vec![("http://b.com/y".to_string(), at_depth(1)), ("http://b.com/y".to_string(), at_depth(3)), ("http://b.com/y".to_string(), at_depth(3))];
+        frontier.add_urls_popping(&mut v);
+        assert_eq!(v.len(), 0);
+        assert_eq!(frontier.len(), 4);
+
+        // When full, the lowest scored url makes room for a higher scored one, while lower scored ones are dropped.
+        frontier.add_urls(vec![("http://d.com/".into(), at_depth(1)), ("http://d.com/deep".into(), at_depth(9))]);
+        assert_eq!(frontier.len(), 4);
+
+        // Retrieved best first, with ties in the order urls were added.
+        assert_eq!(frontier.get_url().map(|(url, meta)| (url, meta.depth)), Some(("http://a.com/".into(), 0)));
+        assert_eq!(frontier.get_url(), Some(("http://b.com/y".into(), at_depth(1))));
+        assert_eq!(frontier.get_url().map(|(url, _)| url), Some("http://c.com/z".into()));
+        assert_eq!(frontier.get_url().map(|(url, _)| url), Some("http://d.com/".into()));
+        assert_eq!(frontier.get_url(), None);
+        assert!(frontier.hosts.is_empty() && frontier.best.is_empty() && frontier.worst.is_empty());
+
+        // Urls of hosts that have others waiting come after those of other hosts.
+        frontier.add_urls(vec![("http://e.com/1".into(), at_depth(1)), ("http://e.com/2".into(), at_depth(1)), ("http://f.com/1".into(), at_depth(1))]);
+        assert_eq!(frontier.get_url().map(|(url, _)| url), Some("http://f.com/1".into()));
+        assert_eq!(frontier.get_url().map(|(url, _)| url), Some("http://e.com/1".into()));
+        assert_eq!(frontier.get_url().map(|(url, _)| url), Some("http://e.com/2".into()));
+
+        // The penalty of a host follows the number of its urls waiting, so they move up as it drops.
+        let mut frontier=PriorityFrontier::new(Vec::new(), 8);
+        frontier.add_urls((0..5).map(|i| (format!("http://g.com/{}", i), at_depth(1))).collect());
+        frontier.add_urls(vec![("http://h.com/s.css".into(), at_depth(1))]);
+        assert_eq!(frontier.get_url().map(|(url, _)| url), Some("http://h.com/s.css".into()));
+        frontier.add_urls(vec![("http://h.com/t.css".into(), at_depth(1)), ("http://h.com/u.css".into(), at_depth(1))]);
+        let urls:Vec<String>=(0..7).filter_map(|_| frontier.get_url().map(|(url, _)| url)).collect();
+        assert_eq!(urls, vec!["http://g.com/0", "http://g.com/1", "http://g.com/2", "http://g.com/3", "http://g.com/4", "http://h.com/t.css", "http://h.com/u.css"]);
+
+        // Urls of a kind some processor reads are no longer scored down, waiting ones included.
+        let mut frontier=PriorityFrontier::new(Vec::new(), 8);
+        frontier.add_urls(vec![("http://i.com/x.png".into(), at_depth(1)), ("http://j.com/x.js".into(), at_depth(1)), ("http://k.com/x.css".into(), at_depth(1))]);
+        frontier.entries.get_mut("http://i.com/x.png").unwrap().inlinks=4;
+        frontier.lift_binary_hint(|mime| mime.starts_with("image/"));
+        assert_eq!(frontier.entries["http://i.com/x.png"].inlinks, 4);
+        frontier.add_urls(vec![("http://l.com/y.jpg".into(), at_depth(1))]);
+        let urls:Vec<String>=(0..4).filter_map(|_| frontier.get_url().map(|(url, _)| url)).collect();
+        assert_eq!(urls, vec!["http://i.com/x.png", "http://k.com/x.css", "http://l.com/y.jpg", "http://j.com/x.js"]);
+    }
+
+    #[test]
+    fn test_priority_frontier_roundtrip() {
+        let parent:sync::Arc<str>=sync::Arc::from("http://a.com/");
+        let mut frontier=PriorityFrontier::new(vec!["http://a.com/".into()], 8);
+        let child=url_meta::UrlMeta::seed().child(parent.clone(), 7);
+        frontier.add_urls(vec![("http://a.com/x".into(), child.clone()), ("http://b.com/x".into(), child.clone()), ("http://b.com/x".into(), child.clone())]);
+
+        let mut buf=Vec::new();
+        frontier.write_to(&mut buf).unwrap();
+        let mut restored=PriorityFrontier::read_from(&mut io::Cursor::new(buf.clone()), 8).unwrap();
+        assert_eq!(restored.entries["http://b.com/x"].inlinks, 2);
+        let mut parents=Vec::new();
+        while let Some((url, meta))=frontier.get_url() {
+            let (restored_url, restored_meta)=restored.get_url().unwrap();
+            assert_eq!((restored_url, restored_meta.clone()), (url, meta));
+            parents.extend(restored_meta.parent);
+        }
+        assert!(sync::Arc::ptr_eq(&parents[0], &parents[1]));
+
+        // Only the highest scored urls are restored into a smaller frontier.
+        let mut restored=PriorityFrontier::read_from(&mut io::Cursor::new(buf), 1).unwrap();
+        assert_eq!(restored.get_url().map(|(url, _)| url), Some("http://a.com/".into()));
+        assert_eq!(restored.get_url(), None);
+    }
+}
@@ -0,0 +1,441 @@
+use bloom_filter;
+use canonical;
+use crawl_error::CrawlError;
+use crawl_error;
+use fetch_log;
+use frontier;
+use scope;
+use url_meta;
+use bytes;
+use hyper;
+use url;
+use std::fs;
+use std::io::Write;
+use std::path;
+use std::sync;
+use std::thread;
+
+/// File a processor wants written, e.g. a stylesheet worth keeping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+    /// Path the file is written to (relative to the working directory, unless absolute).
+    pub path: path::PathBuf,
+    pub bytes: Vec<u8>,
+}
+
+/// What a processor got out of a response.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Output {
+    /// Urls to be crawled, which are added to the frontier unless they were sent before.
+    pub urls: Vec<String>,
+    /// Files to be written.
+    pub artifacts: Vec<Artifact>,
+    /// Number of links found in the response, for the fetch log.
+    pub links: Option<usize>,
+}
+
+/// Something to be done with the responses of some media types, on a thread of its own.
+pub trait ContentProcessor: Send {
+    /// Returns the name of the processor, used in logs and error messages.
+    fn name(&self) -> &'static str;
+
+    /// Returns the media types of the responses to be handed to this processor,
+    /// e.g. `text/css`, or `image/*` for every image.
+    fn mime_types(&self) -> Vec<String>;
+
+    /// Returns the size a (decoded) body may reach before its download is aborted.
+    fn max_bytes(&self) -> usize;
+
+    /// Processes a response, adding the urls found and the files to be written to `output`.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - Url the response was finally gotten from (after redirects).
+    /// * `headers` - Headers of the response.
+    /// * `body` - Decoded body of the response.
+    /// * `output` - Output the results are added to.
+    fn process(&mut self, url: &url::Url, headers: &hyper::Headers, body: &[u8], output: &mut Output) -> Result<(), CrawlError>;
+
+    /// Called once an artifact of the processor has been written, e.g. to count
+    /// it. Artifacts that could not be written are not handed to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `artifact` - The artifact written.
+    fn artifact_written(&mut self, _artifact: &Artifact) {}
+}
+
+/// Media types a processor is interested in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MimeMatcher {
+    patterns: Vec<String>,
+}
+
+impl MimeMatcher {
+    /// Creates and returns a new MimeMatcher structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `patterns` - Media types to match (case insensitively), `*` standing for any subtype (`image/*`) or any type at all.
+    pub fn new(patterns: Vec<String>) -> MimeMatcher {
+        MimeMatcher{
+            patterns: patterns.into_iter().map(|pattern| pattern.trim().to_lowercase()).collect(),
+        }
+    }
+
+    /// Returns whether `mime` (without parameters) is one of the media types.
+    pub fn matches(&self, mime: &str) -> bool {
+        let mime=mime.trim().to_lowercase();
+        let type_=mime.split('/').next().unwrap_or("");
+        self.patterns.iter().any(|pattern| {
+            pattern=="*" || *pattern==mime || (pattern.ends_with("/*") && pattern[..pattern.len()-2]==*type_)
+        })
+    }
+
+    /// Returns whether some media type matched by `pattern` (in the form of the
+    /// patterns of `new`) is one of the media types, e.g. `image/png` for `image/*`.
+    pub fn overlaps(&self, pattern: &str) -> bool {
+        let pattern=pattern.trim().to_lowercase();
+        if pattern=="*" {
+            return !self.patterns.is_empty();
+        }
+        let type_=pattern.split('/').next().unwrap_or("");
+        self.matches(pattern.as_str()) || (pattern.ends_with("/*") && self.patterns.iter().any(|own| own.split('/').next()==Some(type_)))
+    }
+}
+
+/// Returns the charset of the Content-Type header, if any.
+pub fn header_charset(headers: &hyper::Headers) -> Option<String> {
+    headers.get::<hyper::header::ContentType>()
+    .and_then(|content_type| content_type.get_param("charset").map(|charset| charset.as_str().to_string()))
+}
+
+/// Response handed to a processor, along with the UrlMeta of its url and its
+/// (incomplete) fetch log record.
+struct Page {
+    url: url::Url,
+    meta: url_meta::UrlMeta,
+    headers: hyper::Headers,
+    body: bytes::Bytes,
+    event: Option<fetch_log::FetchEvent>,
+}
+
+/// What the processor threads share with the rest of the crawl.
+#[derive(Clone)]
+pub struct Sinks {
+    /// ConcurrentBloomFilter that keeps track of already sent urls (by `url_enqueuer`).
+    pub bloom_filter: sync::Arc<bloom_filter::ConcurrentBloomFilter>,
+    /// Canonicalizer the urls found are rewritten with before they are checked against `bloom_filter`.
+    pub canonicalizer: sync::Arc<canonical::Canonicalizer>,
+    /// Scope of the crawl, which the urls found must be within to be added to the frontier.
+    pub scope: sync::Arc<scope::Scope>,
+    /// Frontier the urls found are added to.
+    pub frontier: sync::Arc<sync::Mutex<frontier::Frontier>>,
+    /// Depth beyond which the urls found are dropped (0 for no limit).
+    pub max_depth: usize,
+    /// Atomic counter that counts the urls dropped for being beyond `max_depth`.
+    pub urls_too_deep: sync::Arc<sync::atomic::AtomicUsize>,
+    /// ErrorCounters counting the responses the processors failed on.
+    pub errors: sync::Arc<crawl_error::ErrorCounters>,
+    /// FetchLog the records of the processed responses are logged to, once the number of their links is known.
+    pub fetch_log: Option<fetch_log::FetchLog>,
+    /// Whether the processors tell on stderr when they terminate.
+    pub verbose: bool,
+}
+
+/// Processors to be run, in the order they were registered.
+pub struct ProcessorRegistry {
+    processors: Vec<Box<ContentProcessor>>,
+}
+
+impl ProcessorRegistry {
+    /// Creates and returns a new, empty ProcessorRegistry structure.
+    pub fn new() -> ProcessorRegistry {
+        ProcessorRegistry{
+            processors: Vec::new(),
+        }
+    }
+
+    /// Adds `processor`, to be handed the responses of its media types.
+    pub fn register(&mut self, processor: Box<ContentProcessor>) {
+        self.processors.push(processor);
+    }
+
+    /// Returns the names of the registered processors.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.processors.iter().map(|processor| processor.name()).collect()
+    }
+
+    /// Starts a thread per processor and returns the Dispatcher handing the
+    /// responses to them.
+    pub fn start(self, sinks: Sinks) -> Dispatcher {
+        let mut routes=Vec::with_capacity(self.processors.len());
+        let mut threads=Vec::with_capacity(self.processors.len());
+        for processor in self.processors.into_iter() {
+            let (sender, receiver)=sync::mpsc::channel::<Page>();
+            routes.push(Route{
+                name: processor.name(),
+                matcher: MimeMatcher::new(processor.mime_types()),
+                max_bytes: processor.max_bytes(),
+                sender: sender,
+            });
+            let sinks=sinks.clone();
+            threads.push(thread::spawn(move || {
+                run_processor(processor, receiver, sinks);
+            }));
+        }
+
+        Dispatcher{
+            routes: routes,
+            threads: threads,
+        }
+    }
+}
+
+struct Route {
+    name: &'static str,
+    matcher: MimeMatcher,
+    max_bytes: usize,
+    sender: sync::mpsc::Sender<Page>,
+}
+
+/// Hands responses to the threads of the processors of their media types.
+pub struct Dispatcher {
+    routes: Vec<Route>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl Dispatcher {
+    /// Returns the size the body of a response of media type `mime` may reach,
+    /// the largest allowed by its processors, or None if no processor wants it.
+    pub fn max_bytes(&self, mime: &str) -> Option<usize> {
+        self.routes.iter().filter(|route| route.matcher.matches(mime)).map(|route| route.max_bytes).max()
+    }
+
+    /// Hands a response to every processor of its media type. The fetch log
+    /// record goes to the first of them, which logs it once the response is
+    /// processed; it is left in `event` if there is none.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - Url the response was finally gotten from (after redirects).
+    /// * `meta` - UrlMeta of the url originally requested.
+    /// * `mime` - Media type of the response, without its parameters.
+    /// * `headers` - Headers of the response.
+    /// * `body` - Decoded body of the response.
+    /// * `event` - Fetch log record of the response.
+    pub fn dispatch(&self, url: url::Url, meta: &url_meta::UrlMeta, mime: &str, headers: &hyper::Headers, body: bytes::Bytes, event: &mut Option<fetch_log::FetchEvent>) -> Result<(), CrawlError> {
+        for route in self.routes.iter().filter(|route| route.matcher.matches(mime)) {
+            let page=Page{
+                url: url.clone(),
+                meta: meta.clone(),
+                headers: headers.clone(),
+                body: body.clone(),
+                event: event.take(),
+            };
+            if let Err(e)=route.sender.send(page) {
+                if (e.0).event.is_some() {
+                    *event=(e.0).event;
+                }
+                return Err(CrawlError::ChannelClosed(route.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Lets the processors finish with the responses already handed to them, and waits until they did.
+    pub fn finish(self) {
+        let Dispatcher{routes, threads}=self;
+        let names:Vec<&'static str>=routes.iter().map(|route| route.name).collect();
+        drop(routes);
+        for (name, thread) in names.into_iter().zip(threads.into_iter()) {
+            match thread.join() {
+                Ok(_) => {},
+                Err(e) => eprintln!("Error ({}): {:?}", name, e),
+            }
+        }
+    }
+}
+
+/// Within an endless loop, it obtains responses through `receiver` and has
+/// `processor` process them. The urls found are added to the frontier, unless
+/// contained within the bloom filter, out of scope or too deep, and the artifacts
+/// are written.
+fn run_processor(mut processor: Box<ContentProcessor>, receiver: sync::mpsc::Receiver<Page>, sinks: Sinks) {
+    let name=processor.name();
+    let mut output=Output::default();
+    let mut found=Vec::new();
+    for page in receiver.iter() {
+        let Page{url, meta, headers, body, mut event}=page;
+        output.urls.clear();
+        output.artifacts.clear();
+        output.links=None;
+        match processor.process(&url, &headers, &body, &mut output) {
+            Ok(_) => {},
+            Err(e) => {
+                sinks.errors.record(name, &e);
+                if let Some(ref mut event)=event {
+                    event.outcome=e.name();
+                }
+            },
+        }
+        if let (Some(fetch_log), Some(mut event))=(sinks.fetch_log.as_ref(), event) {
+            event.links=output.links;
+            fetch_log.log(event);
+        }
+
+        // Canonicalize and deduplicate urls, and drop them all if they would be beyond the maximum depth.
+        sinks.canonicalizer.canonicalize_all(&mut output.urls);
+        output.urls.sort_unstable();
+        output.urls.dedup();
+        if !output.urls.is_empty() && sinks.max_depth>0 && meta.depth as usize>=sinks.max_depth {
+            sinks.urls_too_deep.fetch_add(output.urls.len(), sync::atomic::Ordering::Relaxed);
+            output.urls.clear();
+        }
+
+        // Filter out those that have already been crawled (and are thus contained within `bloom_filter`), then those out of scope.
+        output.urls.retain(|u| !sinks.bloom_filter.contains(u.as_bytes()) && sinks.scope.allows(u));
+
+        // Give the others the page as their parent.
+        if !output.urls.is_empty() {
+            let child=meta.child(sync::Arc::from(url.as_str()), url_meta::now_secs());
+            found.extend(output.urls.drain(..).map(|u| (u, child.clone())));
+        }
+
+        // Add obtained urls to the frontier.
+        if !found.is_empty() {
+            let mut mutex_guard=match sinks.frontier.lock() {
+                Ok(mutex_guard) => mutex_guard,
+                Err(e) => {eprintln!("Error ({}): {:?}", name, e);break;},
+            };
+
+            mutex_guard.add_urls_popping(&mut found);
+        }
+
+        // Write the artifacts.
+        for artifact in output.artifacts.iter() {
+            let written=fs::File::create(&artifact.path).and_then(|mut f| f.write_all(&artifact.bytes));
+            match written {
+                Ok(_) => processor.artifact_written(artifact),
+                Err(e) => sinks.errors.record(name, &CrawlError::from(e)),
+            }
+        }
+    }
+
+    if sinks.verbose {
+        eprintln!("Processor {} terminated.", name);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config;
+    use rand;
+    use test_util;
+
+    #[test]
+    fn test_mime_matcher() {
+        let matcher=MimeMatcher::new(vec!["text/html".to_string(), "Image/*".to_string()]);
+        assert!(matcher.matches("text/html"));
+        assert!(matcher.matches("TEXT/HTML"));
+        assert!(matcher.matches("image/png"));
+        assert!(!matcher.matches("text/css"));
+        assert!(!matcher.matches("imagex/png"));
+        assert!(MimeMatcher::new(vec!["*".to_string()]).matches("application/ld+json"));
+
+        assert!(matcher.overlaps("image/*") && matcher.overlaps("image/gif") && matcher.overlaps("text/*") && matcher.overlaps("*"));
+        assert!(!matcher.overlaps("font/*") && !matcher.overlaps("application/javascript"));
+        assert!(MimeMatcher::new(vec!["image/png".to_string()]).overlaps("image/*"));
+        assert!(MimeMatcher::new(vec!["*".to_string()]).overlaps("font/*"));
+        assert!(!MimeMatcher::new(Vec::new()).overlaps("*"));
+    }
+
+    /// Emits the urls listed in the body, one per line, and the body itself as an artifact.
+    struct ListProcessor {
+        artifact_path: path::PathBuf,
+        written: sync::Arc<sync::atomic::AtomicUsize>,
+    }
+
+    impl ContentProcessor for ListProcessor {
+        fn name(&self) -> &'static str {"list"}
+        fn mime_types(&self) -> Vec<String> {vec!["text/plain".to_string()]}
+        fn max_bytes(&self) -> usize {1024}
+        fn process(&mut self, _: &url::Url, _: &hyper::Headers, body: &[u8], output: &mut Output) -> Result<(), CrawlError> {
+            if body.is_empty() {
+                return Err(CrawlError::TooShort(1));
+            }
+            output.urls.extend(String::from_utf8_lossy(body).lines().map(|line| line.to_string()));
+            output.links=Some(output.urls.len());
+            output.artifacts.push(Artifact{path: self.artifact_path.clone(), bytes: body.to_vec()});
+            if body.starts_with(b"unwritable") {
+                output.artifacts.push(Artifact{path: self.artifact_path.join("not_a_directory"), bytes: body.to_vec()});
+            }
+            Ok(())
+        }
+        fn artifact_written(&mut self, _: &Artifact) {
+            self.written.fetch_add(1, sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_dispatcher() {
+        let dir=test_util::TempDir::new("processor");
+        let artifact_path=dir.join("artifact.txt");
+        let mut config=config::CrawlConfig::default();
+        config.scope_denied_hosts=vec!["b.com".to_string()];
+        let sinks=Sinks{
+            bloom_filter: sync::Arc::new(bloom_filter::ConcurrentBloomFilter::new(1000, 0.01, 1)),
+            canonicalizer: sync::Arc::new(canonical::Canonicalizer::new(&config, |_| 0)),
+            scope: sync::Arc::new(scope::Scope::new(&config, |_| 0).unwrap()),
+            frontier: sync::Arc::new(sync::Mutex::new(frontier::Frontier::new(frontier::FrontierKind::Reservoir, Vec::new(), 10, rand::StdRng::new().unwrap()))),
+            max_depth: 2,
+            urls_too_deep: sync::Arc::new(sync::atomic::AtomicUsize::new(0)),
+            errors: sync::Arc::new(crawl_error::ErrorCounters::new(|_: &str| 0)),
+            fetch_log: None,
+            verbose: false,
+        };
+        sinks.bloom_filter.contains_add(b"http://a.com/known");
+
+        let written=sync::Arc::new(sync::atomic::AtomicUsize::new(0));
+        let mut registry=ProcessorRegistry::new();
+        registry.register(Box::new(ListProcessor{artifact_path: artifact_path.clone(), written: written.clone()}));
+        assert_eq!(registry.names(), vec!["list"]);
+        let dispatcher=registry.start(sinks.clone());
+        assert_eq!(dispatcher.max_bytes("text/plain"), Some(1024));
+        assert_eq!(dispatcher.max_bytes("text/html"), None);
+
+        let url=url::Url::parse("http://a.com/").unwrap();
+        let meta=url_meta::UrlMeta::seed();
+        let mut event=Some(fetch_log::FetchEvent::new(url.to_string(), &meta));
+        dispatcher.dispatch(url.clone(), &meta, "text/plain", &hyper::Headers::new(), bytes::Bytes::from(&b"http://a.com/x\nhttp://a.com/known?utm_source=z\nhttp://b.com/\nhttp://A.com:80/x#top"[..]), &mut event).unwrap();
+        assert!(event.is_none());
+
+        // Responses of other media types are left alone, along with their records.
+        let mut event=Some(fetch_log::FetchEvent::new(url.to_string(), &meta));
+        dispatcher.dispatch(url.clone(), &meta, "text/html", &hyper::Headers::new(), bytes::Bytes::new(), &mut event).unwrap();
+        assert!(event.is_some());
+
+        dispatcher.dispatch(url.clone(), &meta, "text/plain", &hyper::Headers::new(), bytes::Bytes::new(), &mut None).unwrap();
+
+        // Only the artifacts actually written are reported back to the processor.
+        dispatcher.dispatch(url.clone(), &meta, "text/plain", &hyper::Headers::new(), bytes::Bytes::from(&b"unwritable"[..]), &mut None).unwrap();
+
+        // Pages at the maximum depth lead nowhere.
+        let deep=url_meta::UrlMeta{depth: 2, parent: None, discovered_secs: 0};
+        dispatcher.dispatch(url::Url::parse("http://a.com/deep").unwrap(), &deep, "text/plain", &hyper::Headers::new(), bytes::Bytes::from(&b"http://a.com/deeper/than/allowed"[..]), &mut None).unwrap();
+        dispatcher.finish();
+
+        let mut frontier=sinks.frontier.lock().unwrap();
+        let (found, found_meta)=frontier.get_url().unwrap();
+        assert_eq!((found.as_str(), found_meta.depth, found_meta.parent.as_ref().map(|parent| &parent[..])), ("http://a.com/x", 1, Some("http://a.com/")));
+        assert_eq!(frontier.get_url(), None);
+        assert_eq!(sinks.canonicalizer.named()[0].1.load(sync::atomic::Ordering::Relaxed), 2);
+        assert_eq!(sinks.urls_too_deep.load(sync::atomic::Ordering::Relaxed), 1);
+        assert_eq!(sinks.errors.get("filter_short"), 1);
+        assert_eq!((written.load(sync::atomic::Ordering::Relaxed), sinks.errors.get("storage_io")), (3, 1));
+        assert_eq!((sinks.scope.get("scope_accepted"), sinks.scope.get("scope_host_denied")), (1, 1));
+        assert_eq!(fs::read(&artifact_path).unwrap(), b"http://a.com/deeper/than/allowed".to_vec());
+    }
+}
@@ -0,0 +1,145 @@
+#![allow(dead_code)]
+
+use url;
+use std::collections;
+use std::fs;
+use std::io::Read;
+use std::net;
+
+/// Copy of https://publicsuffix.org/list/public_suffix_list.dat bundled with the
+/// crawler, used unless a fresher one is given through `scope_public_suffix_file`.
+const BUNDLED_LIST: &str = include_str!("public_suffix_list.dat");
+
+/// Rules of the public suffix list, which tell under which suffixes domains are
+/// registered (e.g. `co.uk`, `*.ck` or `github.io`), so that the registrable
+/// domain of a host can be told apart from its subdomains.
+pub struct PublicSuffixList {
+    /// Suffixes of the plain rules, e.g. `co.uk`.
+    rules: collections::HashSet<String>,
+    /// Suffixes of the wildcard rules without their `*.`, e.g. `ck` for `*.ck`.
+    wildcards: collections::HashSet<String>,
+    /// Suffixes of the exception rules without their `!`, e.g. `www.ck` for `!www.ck`.
+    exceptions: collections::HashSet<String>,
+}
+
+impl PublicSuffixList {
+    /// Parses a list in the format of the public suffix list: one rule per line,
+    /// up to the first whitespace, and `//` comments. Rules in unicode are
+    /// turned into punycode, as hosts of parsed urls are.
+    ///
+    /// # Arguments
+    ///
+    /// * `list` - Content of the list.
+    pub fn parse(list: &str) -> PublicSuffixList {
+        let mut suffixes=PublicSuffixList{
+            rules: collections::HashSet::new(),
+            wildcards: collections::HashSet::new(),
+            exceptions: collections::HashSet::new(),
+        };
+        for line in list.lines() {
+            let rule=line.split_whitespace().next().unwrap_or("");
+            if rule.is_empty() || rule.starts_with("//") {
+                continue;
+            }
+            let (set, rule)=if rule.starts_with('!') {
+                (&mut suffixes.exceptions, &rule[1..])
+            } else if rule.starts_with("*.") {
+                (&mut suffixes.wildcards, &rule[2..])
+            } else {
+                (&mut suffixes.rules, rule)
+            };
+            match url::idna::domain_to_ascii(rule) {
+                Ok(ascii) => {set.insert(ascii);},
+                Err(_) => {set.insert(rule.to_lowercase());},
+            }
+        }
+        suffixes
+    }
+
+    /// Returns the list bundled with the crawler.
+    pub fn bundled() -> PublicSuffixList {
+        PublicSuffixList::parse(BUNDLED_LIST)
+    }
+
+    /// Reads the list in the file at `path`.
+    pub fn from_file(path: &str) -> Result<PublicSuffixList, String> {
+        let mut list=String::new();
+        match fs::File::open(path).and_then(|mut f| f.read_to_string(&mut list)) {
+            Ok(_) => Ok(PublicSuffixList::parse(list.as_str())),
+            Err(e) => Err(format!("cannot read public suffix list {:?}: {}", path, e)),
+        }
+    }
+
+    /// Returns the number of labels of the public suffix of the host whose labels
+    /// start at `starts` (as byte offsets into `host`). Exception rules win over
+    /// the others, then the rule with the most labels, and hosts no rule matches
+    /// have a suffix of one label.
+    fn suffix_labels(&self, host: &str, starts: &[usize]) -> usize {
+        let mut labels=1;
+        for (i, &start) in starts.iter().enumerate() {
+            let suffix=&host[start..];
+            let suffix_labels=starts.len()-i;
+            if self.exceptions.contains(suffix) {
+                return suffix_labels-1;
+            }
+            if suffix_labels>labels && (self.rules.contains(suffix) || (i+1<starts.len() && self.wildcards.contains(&host[starts[i+1]..]))) {
+                labels=suffix_labels;
+            }
+        }
+        labels
+    }
+
+    /// Returns the domain `host` is registered under (e.g. `example.co.uk` for
+    /// `www.example.co.uk`), or the host itself for ip addresses, single labels
+    /// and public suffixes.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - Lowercase host name, without a trailing dot.
+    pub fn registrable_domain<'a>(&self, host: &'a str) -> &'a str {
+        if host.parse::<net::IpAddr>().is_ok() || host.starts_with('[') {
+            return host;
+        }
+
+        let starts:Vec<usize>=Some(0).into_iter().chain(host.match_indices('.').map(|(i, _)| i+1)).collect();
+        let labels=self.suffix_labels(host, &starts)+1;
+        if starts.len()<=labels {
+            host
+        } else {
+            &host[starts[starts.len()-labels]..]
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registrable_domain() {
+        let suffixes=PublicSuffixList::parse("// comment\ncom\nuk\nco.uk\n*.ck\n!www.ck\ngithub.io\n公司.cn\n");
+        assert_eq!(suffixes.registrable_domain("www.example.com"), "example.com");
+        assert_eq!(suffixes.registrable_domain("example.com"), "example.com");
+        assert_eq!(suffixes.registrable_domain("a.b.example.co.uk"), "example.co.uk");
+        assert_eq!(suffixes.registrable_domain("co.uk"), "co.uk");
+        assert_eq!(suffixes.registrable_domain("user.github.io"), "user.github.io");
+        assert_eq!(suffixes.registrable_domain("a.b.c.ck"), "b.c.ck");
+        assert_eq!(suffixes.registrable_domain("a.www.ck"), "www.ck");
+        assert_eq!(suffixes.registrable_domain("a.example.xn--55qx5d.cn"), "example.xn--55qx5d.cn");
+        assert_eq!(suffixes.registrable_domain("a.example.unlisted"), "example.unlisted");
+        assert_eq!(suffixes.registrable_domain("localhost"), "localhost");
+        assert_eq!(suffixes.registrable_domain("127.0.0.1"), "127.0.0.1");
+        assert_eq!(suffixes.registrable_domain("[::1]"), "[::1]");
+    }
+
+    #[test]
+    fn test_bundled() {
+        let suffixes=PublicSuffixList::bundled();
+        assert_eq!(suffixes.registrable_domain("www.example.co.uk"), "example.co.uk");
+        assert_eq!(suffixes.registrable_domain("shop.example.com.au"), "example.com.au");
+        assert_eq!(suffixes.registrable_domain("a.b.example.kawasaki.jp"), "b.example.kawasaki.jp");
+        assert_eq!(suffixes.registrable_domain("user.blogspot.com"), "user.blogspot.com");
+        assert!(PublicSuffixList::from_file("/no/such/list.dat").is_err());
+    }
+}
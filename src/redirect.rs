@@ -0,0 +1,315 @@
+#![allow(dead_code)]
+
+use bloom_filter;
+use canonical;
+use frontier;
+use robots;
+use scope;
+use url_meta;
+use hyper;
+use url;
+use std::error;
+use std::fmt;
+use std::io;
+use std::sync;
+
+/// Reason a redirect chain was not followed to its end.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RedirectError {
+    /// The redirect response had no Location header.
+    MissingLocation,
+    /// The Location header could not be resolved into an http(s) url.
+    InvalidLocation(String),
+    /// The redirect leads back to a url already in the chain.
+    Loop(String),
+    /// The chain got longer than allowed.
+    TooManyHops(usize),
+    /// The redirect leads to a url that was crawled (or enqueued) before.
+    AlreadySeen(String),
+    /// The redirect leads to a url disallowed by robots.txt.
+    Disallowed(String),
+    /// The redirect leads to a url outside the scope of the crawl.
+    OutOfScope(String),
+    /// The redirect leads to another origin, whose url was given back to the frontier instead.
+    Deferred(String),
+}
+
+impl fmt::Display for RedirectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RedirectError::MissingLocation => write!(f, "redirect without location"),
+            RedirectError::InvalidLocation(ref location) => write!(f, "invalid redirect location {:?}", location),
+            RedirectError::Loop(ref url) => write!(f, "redirect loop at {}", url),
+            RedirectError::TooManyHops(hops) => write!(f, "more than {} redirects", hops),
+            RedirectError::AlreadySeen(ref url) => write!(f, "redirect to already seen url {}", url),
+            RedirectError::Disallowed(ref url) => write!(f, "redirect to url disallowed by robots.txt {}", url),
+            RedirectError::OutOfScope(ref url) => write!(f, "redirect to url out of scope {}", url),
+            RedirectError::Deferred(ref url) => write!(f, "redirect to other origin deferred {}", url),
+        }
+    }
+}
+
+impl error::Error for RedirectError {
+    fn description(&self) -> &str {
+        "redirect not followed"
+    }
+}
+
+impl RedirectError {
+    /// Returns whether the chain was cut short because of the redirects themselves
+    /// (rather than because the crawl already knows or cannot crawl their target).
+    pub fn is_broken_chain(&self) -> bool {
+        match *self {
+            RedirectError::MissingLocation | RedirectError::InvalidLocation(_) | RedirectError::Loop(_) | RedirectError::TooManyHops(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the url (or location) redirected to, if known.
+    pub fn target(&self) -> Option<&str> {
+        match *self {
+            RedirectError::InvalidLocation(ref url) | RedirectError::Loop(ref url) | RedirectError::AlreadySeen(ref url) |
+            RedirectError::Disallowed(ref url) | RedirectError::OutOfScope(ref url) | RedirectError::Deferred(ref url) => Some(url.as_str()),
+            RedirectError::MissingLocation | RedirectError::TooManyHops(_) => None,
+        }
+    }
+}
+
+/// Returns the RedirectError that caused `e`, if any.
+pub fn redirect_error(e: &io::Error) -> Option<&RedirectError> {
+    e.get_ref().and_then(|inner| inner.downcast_ref::<RedirectError>())
+}
+
+/// Returns whether a response with `status` redirects to its Location header.
+pub fn is_redirect(status: hyper::StatusCode) -> bool {
+    match status {
+        hyper::StatusCode::MovedPermanently | hyper::StatusCode::Found | hyper::StatusCode::SeeOther |
+        hyper::StatusCode::TemporaryRedirect | hyper::StatusCode::PermanentRedirect => true,
+        _ => false,
+    }
+}
+
+/// Urls a request went through, from the one originally requested to the one
+/// (so far) last redirected to.
+#[derive(Debug, Clone)]
+pub struct RedirectChain {
+    urls: Vec<url::Url>,
+    max_hops: usize,
+}
+
+impl RedirectChain {
+    /// Creates and returns a new RedirectChain structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - Url originally requested.
+    /// * `max_hops` - Maximum amount of redirects to be followed.
+    pub fn new(start: url::Url, max_hops: usize) -> RedirectChain {
+        RedirectChain{urls: vec![start], max_hops: max_hops}
+    }
+
+    /// Returns the amount of redirects followed.
+    pub fn hops(&self) -> usize {
+        self.urls.len()-1
+    }
+
+    /// Returns the url last redirected to (the originally requested one if there were no redirects).
+    pub fn current(&self) -> &url::Url {
+        &self.urls[self.urls.len()-1]
+    }
+
+    /// Returns all urls of the chain, in the order they were requested.
+    pub fn urls(&self) -> &[url::Url] {
+        &self.urls
+    }
+
+    /// Resolves the Location header of a redirect response to the current url
+    /// against it, and returns the resulting url after adding it to the chain.
+    /// Fails (without changing the chain) if the location is missing or invalid,
+    /// leads back into the chain, or would make the chain too long.
+    ///
+    /// # Arguments
+    ///
+    /// * `location` - Value of the Location header, which may be a relative url.
+    /// * `canonicalizer` - Canonicalizer the resulting url is rewritten with.
+    pub fn follow(&mut self, location: Option<&str>, canonicalizer: &canonical::Canonicalizer) -> Result<url::Url, RedirectError> {
+        let location=match location {
+            Some(location) if !location.trim().is_empty() => location.trim(),
+            _ => return Err(RedirectError::MissingLocation),
+        };
+
+        let mut next=match self.current().join(location) {
+            Ok(next) => next,
+            Err(_) => return Err(RedirectError::InvalidLocation(location.to_string())),
+        };
+        if next.scheme()!="http" && next.scheme()!="https" {
+            return Err(RedirectError::InvalidLocation(location.to_string()));
+        }
+        // Fragments are never sent, and the other spellings of an url lead to the same page, so neither can tell urls apart.
+        canonicalizer.canonicalize_url(&mut next);
+
+        if self.urls.iter().any(|url| url.as_str().split('#').next()==Some(next.as_str())) {
+            return Err(RedirectError::Loop(next.into_string()));
+        }
+        if self.hops()>=self.max_hops {
+            return Err(RedirectError::TooManyHops(self.max_hops));
+        }
+
+        self.urls.push(next.clone());
+        Ok(next)
+    }
+}
+
+/// Decides where the redirect response `res` to the current url of `chain` leads,
+/// adding its target to the chain. Targets outside the scope of the crawl are
+/// dropped. Redirects within the same origin are followed if robots.txt allows
+/// it, so the uri to be requested next is returned, to be requested once the
+/// host scheduler allows it (see `HostScheduler::hop`).
+/// Those to other origins (or whose robots.txt rules are no longer known) are given
+/// back to the frontier instead, so that robots.txt and the host scheduler
+/// apply to them as to any other url.
+///
+/// # Arguments
+///
+/// * `res` - The redirect response.
+/// * `chain` - Urls requested so far.
+/// * `meta` - UrlMeta of the first url of the chain, which the urls given back to the frontier keep.
+/// * `canonicalizer` - Canonicalizer the target is rewritten with before it is checked against the scope and `bloom_filter`.
+/// * `scope` - Scope of the crawl.
+/// * `bloom_filter` - ConcurrentBloomFilter that keeps track of already sent urls.
+/// * `robots_cache` - RobotsCache holding the robots.txt rules of the hosts being crawled.
+/// * `frontier` - Frontier containing the urls to be crawled.
+pub fn next_hop(res: &hyper::Response, chain: &mut RedirectChain, meta: &url_meta::UrlMeta, canonicalizer: &canonical::Canonicalizer, scope: &scope::Scope, bloom_filter: &bloom_filter::ConcurrentBloomFilter, robots_cache: &sync::Mutex<robots::RobotsCache>, frontier: &sync::Mutex<frontier::Frontier>) -> Result<hyper::Uri, RedirectError> {
+    let location=res.headers().get_raw("Location").and_then(|raw| raw.one()).map(|line| String::from_utf8_lossy(line).into_owned());
+    let from=chain.current().origin();
+    let next=chain.follow(location.as_ref().map(|location| location.as_str()), canonicalizer)?;
+    if !scope.check(&next).is_accepted() {
+        return Err(RedirectError::OutOfScope(next.into_string()));
+    }
+    if next.origin()!=from {
+        return Err(defer(next, meta, bloom_filter, frontier));
+    }
+
+    let uri=match next.as_str().parse::<hyper::Uri>() {
+        Ok(uri) => uri,
+        Err(_) => return Err(RedirectError::InvalidLocation(next.into_string())),
+    };
+    let path=match uri.query() {
+        Some(query) => format!("{}?{}", uri.path(), query),
+        None => uri.path().to_string(),
+    };
+    let allowed=match robots_cache.lock() {
+        Ok(mutex_guard) => robots::origin(&uri).and_then(|origin| mutex_guard.peek(origin.as_str(), path.as_str())),
+        Err(e) => {eprintln!("Error (redirect): {:?}", e);None},
+    };
+    match allowed {
+        Some(true) => {},
+        Some(false) => return Err(RedirectError::Disallowed(next.into_string())),
+        None => return Err(defer(next, meta, bloom_filter, frontier)),
+    }
+
+    if bloom_filter.contains_add(next.as_str().as_bytes()) {
+        return Err(RedirectError::AlreadySeen(next.into_string()));
+    }
+    Ok(uri)
+}
+
+/// Gives `url` (with `meta`) back to `frontier` unless it was sent before, and returns the error ending the chain.
+fn defer(url: url::Url, meta: &url_meta::UrlMeta, bloom_filter: &bloom_filter::ConcurrentBloomFilter, frontier: &sync::Mutex<frontier::Frontier>) -> RedirectError {
+    if bloom_filter.contains(url.as_str().as_bytes()) {
+        return RedirectError::AlreadySeen(url.into_string());
+    }
+
+    match frontier.lock() {
+        Ok(mut mutex_guard) => mutex_guard.add_urls(vec![(url.as_str().to_string(), meta.clone())]),
+        Err(e) => eprintln!("Error (redirect): {:?}", e),
+    }
+    RedirectError::Deferred(url.into_string())
+}
+
+impl fmt::Display for RedirectChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, url) in self.urls.iter().enumerate() {
+            if i>0 {
+                write!(f, " -> ")?;
+            }
+            write!(f, "{}", url)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config;
+    use rand;
+
+    #[test]
+    fn test_follow() {
+        let canonicalizer=canonical::Canonicalizer::new(&config::CrawlConfig::default(), |_| 0);
+        let mut chain=RedirectChain::new(url::Url::parse("http://a.com/x/y?q=1").unwrap(), 3);
+        assert_eq!(chain.follow(Some("z"), &canonicalizer).unwrap().as_str(), "http://a.com/x/z");
+        assert_eq!(chain.follow(Some("/w#top"), &canonicalizer).unwrap().as_str(), "http://a.com/w");
+        assert_eq!(chain.follow(Some("//b.com/"), &canonicalizer).unwrap().as_str(), "http://b.com/");
+        assert_eq!(chain.hops(), 3);
+        assert_eq!(chain.current().as_str(), "http://b.com/");
+        assert_eq!(chain.follow(Some("/c"), &canonicalizer), Err(RedirectError::TooManyHops(3)));
+        assert_eq!(chain.to_string(), "http://a.com/x/y?q=1 -> http://a.com/x/z -> http://a.com/w -> http://b.com/");
+
+        let mut chain=RedirectChain::new(url::Url::parse("http://a.com/#frag").unwrap(), 5);
+        assert_eq!(chain.follow(None, &canonicalizer), Err(RedirectError::MissingLocation));
+        assert_eq!(chain.follow(Some("  "), &canonicalizer), Err(RedirectError::MissingLocation));
+        assert_eq!(chain.follow(Some("mailto:a@a.com"), &canonicalizer), Err(RedirectError::InvalidLocation("mailto:a@a.com".to_string())));
+        assert_eq!(chain.follow(Some("http://[::1"), &canonicalizer), Err(RedirectError::InvalidLocation("http://[::1".to_string())));
+        assert_eq!(chain.follow(Some("/./b?utm_medium=m"), &canonicalizer).unwrap().as_str(), "http://a.com/b");
+        assert_eq!(chain.follow(Some("http://A.com/"), &canonicalizer), Err(RedirectError::Loop("http://a.com/".to_string())));
+        assert_eq!(chain.hops(), 1);
+    }
+
+    #[test]
+    fn test_next_hop() {
+        let bloom_filter=bloom_filter::ConcurrentBloomFilter::new(1000, 0.001, 0);
+        let robots_cache=sync::Mutex::new(robots::RobotsCache::new(10, ::std::time::Duration::from_secs(60)));
+        robots_cache.lock().unwrap().insert("http://a.com".to_string(), robots::Robots::parse("User-agent: *\nDisallow: /private", "rustcrawl"), ::std::time::Duration::from_secs(60));
+        let frontier=sync::Mutex::new(frontier::Frontier::new(frontier::FrontierKind::Reservoir, vec![], 10, rand::StdRng::new().unwrap()));
+        let mut config=config::CrawlConfig::default();
+        config.scope_denied_hosts=vec!["c.com".to_string()];
+        let canonicalizer=canonical::Canonicalizer::new(&config, |_| 0);
+        let scope=scope::Scope::new(&config, |_| 0).unwrap();
+        let meta=url_meta::UrlMeta{depth: 2, parent: Some(sync::Arc::from("http://a.com/")), discovered_secs: 5};
+        let redirect_to=|location: &str| hyper::Response::new().with_status(hyper::StatusCode::Found).with_header(hyper::header::Location::new(location.to_string()));
+
+        let mut chain=RedirectChain::new(url::Url::parse("http://a.com/x").unwrap(), 5);
+        assert_eq!(next_hop(&redirect_to("/y?q"), &mut chain, &meta, &canonicalizer, &scope, &bloom_filter, &robots_cache, &frontier).unwrap().to_string(), "http://a.com/y?q");
+        assert!(bloom_filter.contains(b"http://a.com/y?q"));
+        assert_eq!(next_hop(&redirect_to("/private"), &mut chain, &meta, &canonicalizer, &scope, &bloom_filter, &robots_cache, &frontier), Err(RedirectError::Disallowed("http://a.com/private".to_string())));
+
+        // Urls of other origins are left to `url_enqueuer`, unless they were sent before.
+        let mut chain=RedirectChain::new(url::Url::parse("http://a.com/z").unwrap(), 5);
+        assert_eq!(next_hop(&redirect_to("http://b.com/"), &mut chain, &meta, &canonicalizer, &scope, &bloom_filter, &robots_cache, &frontier), Err(RedirectError::Deferred("http://b.com/".to_string())));
+        assert_eq!(frontier.lock().unwrap().get_url(), Some(("http://b.com/".to_string(), meta.clone())));
+        let mut chain=RedirectChain::new(url::Url::parse("http://a.com/z").unwrap(), 5);
+        assert_eq!(next_hop(&redirect_to("/./y?q&fbclid=f#top"), &mut chain, &meta, &canonicalizer, &scope, &bloom_filter, &robots_cache, &frontier), Err(RedirectError::AlreadySeen("http://a.com/y?q".to_string())));
+        assert_eq!(next_hop(&hyper::Response::new().with_status(hyper::StatusCode::Found), &mut chain, &meta, &canonicalizer, &scope, &bloom_filter, &robots_cache, &frontier), Err(RedirectError::MissingLocation));
+
+        // Urls out of scope are dropped, wherever they are.
+        let mut chain=RedirectChain::new(url::Url::parse("http://a.com/z").unwrap(), 5);
+        assert_eq!(next_hop(&redirect_to("http://c.com/"), &mut chain, &meta, &canonicalizer, &scope, &bloom_filter, &robots_cache, &frontier), Err(RedirectError::OutOfScope("http://c.com/".to_string())));
+        assert_eq!(frontier.lock().unwrap().len(), 0);
+        assert_eq!(scope.get("scope_host_denied"), 1);
+    }
+
+    #[test]
+    fn test_redirect_error() {
+        let e=io::Error::new(io::ErrorKind::Other, RedirectError::Loop("http://a.com/".to_string()));
+        assert_eq!(redirect_error(&e), Some(&RedirectError::Loop("http://a.com/".to_string())));
+        assert!(redirect_error(&e).unwrap().is_broken_chain());
+        assert!(!RedirectError::Deferred("http://b.com/".to_string()).is_broken_chain());
+        assert_eq!(redirect_error(&io::Error::new(io::ErrorKind::Other, "other")), None);
+
+        assert!(is_redirect(hyper::StatusCode::PermanentRedirect));
+        assert!(!is_redirect(hyper::StatusCode::NotModified));
+    }
+}
@@ -0,0 +1,294 @@
+#![allow(dead_code)]
+
+use url_meta;
+use serde_json;
+use std::collections;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::time;
+
+/// Sizes of the structures shared by the threads of the crawl, at the time of a report.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct Gauges {
+    pub reservoir_urls: usize,
+    pub scheduled_urls: usize,
+    pub scheduled_hosts: usize,
+    pub retries_waiting: usize,
+    pub robots_cached: usize,
+    pub bloom_urls: usize,
+    pub bloom_fill_ratio: f64,
+    pub bloom_false_positive_probability: f64,
+    pub fetch_log_dropped: usize,
+}
+
+/// Per second rates of some counters over the interval since the previous report.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct Rates {
+    pub urls_gotten: f64,
+    pub urls_processed: f64,
+    pub urls_timed_out: f64,
+    pub css_written: f64,
+}
+
+/// Snapshot of the state of a crawl, written to the text report and (as a line
+/// of json) to the json report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CrawlReport {
+    /// Number of reports made before this one.
+    pub index: usize,
+    /// Whether this is the report made when the crawl stopped.
+    #[serde(rename="final")]
+    pub is_final: bool,
+    /// Time of the report, in milliseconds since the unix epoch.
+    pub timestamp: u64,
+    /// Time since the crawl (or the process resuming it) started.
+    pub elapsed_secs: f64,
+    /// Time since the previous report.
+    pub interval_secs: f64,
+    pub urls_enqueued: usize,
+    pub urls_gotten: usize,
+    pub urls_processed: usize,
+    pub urls_timed_out: usize,
+    pub htmls_crawled: usize,
+    pub css_written: usize,
+    /// Urls enqueued but not gotten by the IO loop yet.
+    pub urls_waiting: usize,
+    /// Urls gotten that were neither processed nor timed out.
+    pub urls_failed: usize,
+    /// Fractions of the urls gotten that were processed, timed out and failed (0 while none were gotten).
+    pub processed_ratio: f64,
+    pub timed_out_ratio: f64,
+    pub failed_ratio: f64,
+    pub rates: Rates,
+    pub gauges: Gauges,
+    /// Every counter of the crawl, by name (those above and the CrawlError ones included).
+    pub counters: collections::BTreeMap<String, usize>,
+    /// One line summary of the errors by stage, for the text report.
+    #[serde(skip)]
+    pub error_summary: String,
+}
+
+impl CrawlReport {
+    /// Returns the value of the counter named `name`, or 0 if there is none.
+    pub fn counter(&self, name: &str) -> usize {
+        self.counters.get(name).cloned().unwrap_or(0)
+    }
+
+    /// Returns a short summary of the report, e.g. to be told when the crawl stops.
+    pub fn summary(&self) -> String {
+        format!("crawled for {:.0}s: {} urls gotten ({} processed, {} timed out, {} failed), {} htmls crawled, {} css written, {} urls left in the reservoir",
+            self.elapsed_secs, self.urls_gotten, self.urls_processed, self.urls_timed_out, self.urls_failed, self.htmls_crawled, self.css_written, self.gauges.reservoir_urls)
+    }
+
+    /// Returns the number of urls found out of scope, detailing the rules that
+    /// left some out, e.g. `5 (off_site: 3, excluded: 2)`.
+    fn scope_summary(&self) -> String {
+        let rejected:Vec<(&str, usize)>=self.counters.iter()
+        .filter(|&(name, &value)| name.starts_with("scope_") && name!="scope_accepted" && value>0)
+        .map(|(name, &value)| (&name["scope_".len()..], value))
+        .collect();
+        let total:usize=rejected.iter().map(|&(_, value)| value).sum();
+        if rejected.is_empty() {
+            total.to_string()
+        } else {
+            format!("{} ({})", total, rejected.iter().map(|&(name, value)| format!("{}: {}", name, value)).collect::<Vec<_>>().join(", "))
+        }
+    }
+
+    /// Returns the number of urls gotten at each depth any were gotten at,
+    /// shallowest first, e.g. `0: 1, 1: 12, 16+: 3`.
+    fn depth_summary(&self) -> String {
+        let depths:Vec<String>=url_meta::DEPTH_NAMES.iter()
+        .map(|name| (name, self.counter(name)))
+        .filter(|&(_, value)| value>0)
+        .map(|(name, value)| format!("{}: {}", name["depth_".len()..].replace("_plus", "+"), value))
+        .collect();
+        if depths.is_empty() {
+            "none".to_string()
+        } else {
+            depths.join(", ")
+        }
+    }
+}
+
+impl fmt::Display for CrawlReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{} ({})] elapsed: {:.0}s\n", if self.is_final {"final report"} else {"report"}, self.index, self.elapsed_secs)?;
+        write!(f, "urls enqueued: {}, urls gotten: {} ({:.2} per second), difference: {}\n", self.urls_enqueued, self.urls_gotten, self.rates.urls_gotten, self.urls_waiting)?;
+        write!(f, "urls processed: {} ({:.2}%, {:.2} per second)\n", self.urls_processed, 100.0*self.processed_ratio, self.rates.urls_processed)?;
+        write!(f, "timeouts: {} ({:.2}%, {:.2} per second)\n", self.urls_timed_out, 100.0*self.timed_out_ratio, self.rates.urls_timed_out)?;
+        write!(f, "oversized bodies aborted: {}\n", self.counter("urls_oversized"))?;
+        write!(f, "redirects followed: {}, deferred to other origins: {}, to known urls: {}, out of scope: {}, broken chains: {}\n",
+            self.counter("redirects_followed"), self.counter("redirects_deferred"), self.counter("redirects_known"), self.counter("redirects_out_of_scope"), self.counter("redirects_broken"))?;
+        write!(f, "http: {} succeeded, {} failed; https: {} succeeded, {} failed\n",
+            self.counter("http_succeeded"), self.counter("http_failed"), self.counter("https_succeeded"), self.counter("https_failed"))?;
+        write!(f, "retries scheduled: {} ({} waiting), enqueued: {}, gotten: {}, given up: {}, permanent failures: {}\n",
+            self.counter("fetches_retried"), self.gauges.retries_waiting, self.counter("retries_enqueued"), self.counter("retries_gotten"), self.counter("fetches_given_up"), self.counter("fetches_failed_permanently"))?;
+        write!(f, "other errors: {} ({:.2}%)\n", self.urls_failed, 100.0*self.failed_ratio)?;
+        write!(f, "errors by stage: {}\n", self.error_summary)?;
+        write!(f, "fetch log records dropped: {}\n", self.gauges.fetch_log_dropped)?;
+        write!(f, "htmls crawled: {}, css written: {} ({:.2} per second)\n", self.htmls_crawled, self.css_written, self.rates.css_written)?;
+        write!(f, "reservoir contains: {}, scheduled: {} (over {} hosts)\n", self.gauges.reservoir_urls, self.gauges.scheduled_urls, self.gauges.scheduled_hosts)?;
+        write!(f, "urls in scope: {}, out of scope: {}\n", self.counter("scope_accepted"), self.scope_summary())?;
+        write!(f, "urls rewritten by canonicalization: {}\n", self.counter("urls_canonicalized"))?;
+        write!(f, "urls gotten by depth: {}; beyond max depth: {}\n", self.depth_summary(), self.counter("urls_too_deep"))?;
+        write!(f, "disallowed by robots.txt: {}, robots.txt cached: {}\n", self.counter("urls_disallowed"), self.gauges.robots_cached)?;
+        write!(f, "noindex pages: {}, nofollow pages: {}, nofollow links skipped: {}\n", self.counter("pages_noindex"), self.counter("pages_nofollow"), self.counter("links_nofollow"))?;
+        write!(f, "pages rescued by charset decoding: {}\n", self.counter("pages_rescued"))?;
+        write!(f, "bloom filter contains: ~{} urls (false positive probability {:.4}%)\n\n", self.gauges.bloom_urls, 100.0*self.gauges.bloom_false_positive_probability)
+    }
+}
+
+/// Returns `part/whole`, or 0 if `whole` is 0.
+fn ratio(part: usize, whole: usize) -> f64 {
+    if whole==0 {0.0} else {part as f64/whole as f64}
+}
+
+fn seconds(duration: time::Duration) -> f64 {
+    duration.as_secs() as f64+duration.subsec_nanos() as f64/1e9
+}
+
+/// Makes the reports of a crawl, keeping track of the counters at the
+/// previous one to compute rates with.
+pub struct Reporter {
+    started: time::Instant,
+    last_at: time::Instant,
+    last: collections::BTreeMap<String, usize>,
+    index: usize,
+}
+
+impl Reporter {
+    /// Creates and returns a new Reporter structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `counters` - Values of the counters the crawl starts from (e.g. those of a resumed crawl).
+    pub fn new(counters: &[(&str, usize)]) -> Reporter {
+        let now=time::Instant::now();
+        Reporter{
+            started: now,
+            last_at: now,
+            last: counters.iter().map(|&(name, value)| (name.to_string(), value)).collect(),
+            index: 0,
+        }
+    }
+
+    /// Returns a report of the current state of the crawl.
+    ///
+    /// # Arguments
+    ///
+    /// * `counters` - Current values of the counters.
+    /// * `gauges` - Current sizes of the shared structures.
+    /// * `error_summary` - One line summary of the errors by stage.
+    /// * `is_final` - Whether the crawl is stopping.
+    pub fn report(&mut self, counters: &[(&str, usize)], gauges: Gauges, error_summary: String, is_final: bool) -> CrawlReport {
+        self.report_at(time::Instant::now(), time::SystemTime::now(), counters, gauges, error_summary, is_final)
+    }
+
+    fn report_at(&mut self, now: time::Instant, now_system: time::SystemTime, counters: &[(&str, usize)], gauges: Gauges, error_summary: String, is_final: bool) -> CrawlReport {
+        let counters:collections::BTreeMap<String, usize>=counters.iter().map(|&(name, value)| (name.to_string(), value)).collect();
+        let interval_secs=seconds(now.duration_since(self.last_at));
+        let get=|name: &str| counters.get(name).cloned().unwrap_or(0);
+        // Counters only grow, but a missing or reset one must not make a rate underflow.
+        let rate=|name: &str| {
+            let delta=get(name).saturating_sub(self.last.get(name).cloned().unwrap_or(0));
+            if interval_secs>0.0 {delta as f64/interval_secs} else {0.0}
+        };
+
+        let rates=Rates{
+            urls_gotten: rate("urls_gotten"),
+            urls_processed: rate("urls_processed"),
+            urls_timed_out: rate("urls_timed_out"),
+            css_written: rate("css_written"),
+        };
+
+        let (gotten, processed, timed_out)=(get("urls_gotten"), get("urls_processed"), get("urls_timed_out"));
+        let failed=gotten.saturating_sub(processed).saturating_sub(timed_out);
+        let report=CrawlReport{
+            index: self.index,
+            is_final: is_final,
+            timestamp: now_system.duration_since(time::UNIX_EPOCH).map(|d| d.as_secs()*1000+(d.subsec_nanos()/1_000_000) as u64).unwrap_or(0),
+            elapsed_secs: seconds(now.duration_since(self.started)),
+            interval_secs: interval_secs,
+            urls_enqueued: get("urls_enqueued"),
+            urls_gotten: gotten,
+            urls_processed: processed,
+            urls_timed_out: timed_out,
+            htmls_crawled: get("htmls_crawled"),
+            css_written: get("css_written"),
+            urls_waiting: get("urls_enqueued").saturating_sub(gotten),
+            urls_failed: failed,
+            processed_ratio: ratio(processed, gotten),
+            timed_out_ratio: ratio(timed_out, gotten),
+            failed_ratio: ratio(failed, gotten),
+            rates: rates,
+            gauges: gauges,
+            counters: counters.clone(),
+            error_summary: error_summary,
+        };
+
+        self.index+=1;
+        self.last_at=now;
+        self.last=counters;
+        report
+    }
+}
+
+/// Appends `report` to the text report at `text_filename` and, as a line of
+/// json, to the json report at `json_filename` (unless it is empty).
+pub fn write_report(report: &CrawlReport, text_filename: &str, json_filename: &str) -> io::Result<()> {
+    let mut f=fs::OpenOptions::new().append(true).create(true).open(text_filename)?;
+    f.write_all(report.to_string().as_bytes())?;
+
+    if !json_filename.is_empty() {
+        let mut line=serde_json::to_vec(report).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        line.push(b'\n');
+        let mut f=fs::OpenOptions::new().append(true).create(true).open(json_filename)?;
+        f.write_all(&line)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reporter() {
+        let start=time::Instant::now();
+        let mut reporter=Reporter::new(&[("urls_gotten", 10), ("css_written", 5)]);
+        reporter.started=start;
+        reporter.last_at=start;
+
+        // Nothing gotten yet (and counters smaller than expected) must neither divide by zero nor underflow.
+        let report=reporter.report_at(start, time::UNIX_EPOCH, &[("urls_enqueued", 0), ("urls_processed", 3), ("css_written", 5)], Gauges::default(), String::new(), false);
+        assert_eq!(report.index, 0);
+        assert_eq!((report.urls_waiting, report.urls_failed), (0, 0));
+        assert_eq!((report.processed_ratio, report.rates.urls_gotten, report.rates.css_written), (0.0, 0.0, 0.0));
+
+        let counters=[("urls_enqueued", 130), ("urls_gotten", 110), ("urls_processed", 80), ("urls_timed_out", 10), ("css_written", 25), ("scope_accepted", 40), ("scope_off_site", 3), ("scope_excluded", 2), ("scope_port", 0), ("depth_0", 2), ("depth_1", 100), ("depth_10", 4), ("depth_16_plus", 4), ("urls_too_deep", 7)];
+        let report=reporter.report_at(start+time::Duration::from_secs(10), time::UNIX_EPOCH+time::Duration::from_millis(1500), &counters, Gauges::default(), "fetch: 0".to_string(), true);
+        assert_eq!(report.index, 1);
+        assert_eq!(report.timestamp, 1500);
+        assert_eq!((report.elapsed_secs, report.interval_secs), (10.0, 10.0));
+        assert_eq!((report.urls_waiting, report.urls_failed), (20, 20));
+        assert_eq!((report.processed_ratio, report.failed_ratio), (80.0/110.0, 20.0/110.0));
+        assert_eq!(report.rates, Rates{urls_gotten: 11.0, urls_processed: 7.7, urls_timed_out: 1.0, css_written: 2.0});
+        assert_eq!(report.counter("css_written"), 25);
+
+        let text=report.to_string();
+        assert!(text.starts_with("[final report (1)] elapsed: 10s\nurls enqueued: 130, urls gotten: 110 (11.00 per second), difference: 20\n"));
+        assert!(text.contains("\nother errors: 20 (18.18%)\nerrors by stage: fetch: 0\n"));
+        assert!(text.contains("\nurls in scope: 40, out of scope: 5 (excluded: 2, off_site: 3)\n"));
+        assert!(text.contains("\nurls gotten by depth: 0: 2, 1: 100, 10: 4, 16+: 4; beyond max depth: 7\n"));
+        assert_eq!(report.summary(), "crawled for 10s: 110 urls gotten (80 processed, 10 timed out, 20 failed), 0 htmls crawled, 25 css written, 0 urls left in the reservoir");
+
+        let json=serde_json::to_value(&report).unwrap();
+        assert_eq!(json["final"], serde_json::Value::Bool(true));
+        assert_eq!(json["counters"]["urls_gotten"], serde_json::Value::from(110));
+        assert!(json.get("error_summary").is_none());
+    }
+}
@@ -0,0 +1,393 @@
+#![allow(dead_code)]
+
+use url_meta;
+use hyper;
+use rand;
+use rand::Rng;
+use std::cmp;
+use std::collections;
+use std::error;
+use std::fmt;
+use std::io;
+use std::time;
+
+/// Reason a fetch failed, as far as retrying it is concerned.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Failure {
+    /// The request (including its body) took too long.
+    Timeout,
+    /// The connection was refused, reset or closed before the response was complete.
+    Connection,
+    /// The host name could not be resolved.
+    Dns,
+    /// Response with a 5xx status code.
+    ServerError(u16),
+    /// 429 response, along with the delay asked for by its Retry-After header.
+    TooManyRequests(Option<time::Duration>),
+    /// Response with a 4xx status code other than 429.
+    ClientError(u16),
+    /// Anything else, e.g. invalid responses or tls errors.
+    Other,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Failure::Timeout => write!(f, "timeout"),
+            Failure::Connection => write!(f, "connection failed"),
+            Failure::Dns => write!(f, "dns lookup failed"),
+            Failure::ServerError(status) => write!(f, "server error {}", status),
+            Failure::TooManyRequests(_) => write!(f, "too many requests"),
+            Failure::ClientError(status) => write!(f, "client error {}", status),
+            Failure::Other => write!(f, "fetch failed"),
+        }
+    }
+}
+
+impl error::Error for Failure {
+    fn description(&self) -> &str {
+        "fetch failed"
+    }
+}
+
+impl Failure {
+    /// Returns whether the failure may go away if the fetch is tried again later.
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            Failure::Timeout | Failure::Connection | Failure::Dns | Failure::ServerError(_) | Failure::TooManyRequests(_) => true,
+            Failure::ClientError(_) | Failure::Other => false,
+        }
+    }
+
+    /// Returns the failure a response with `status` stands for, if any (redirects
+    /// and other non-error responses are not failures).
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - Status code of the response.
+    /// * `retry_after` - Retry-After header of the response, if any.
+    pub fn from_status(status: hyper::StatusCode, retry_after: Option<&hyper::header::RetryAfter>) -> Option<Failure> {
+        let code=u16::from(status);
+        if status==hyper::StatusCode::TooManyRequests {
+            let delay=retry_after.map(|retry_after| match *retry_after {
+                hyper::header::RetryAfter::Delay(delay) => delay,
+                hyper::header::RetryAfter::DateTime(date) => time::SystemTime::from(date).duration_since(time::SystemTime::now()).unwrap_or(time::Duration::from_secs(0)),
+            });
+            Some(Failure::TooManyRequests(delay))
+        } else if status.is_server_error() {
+            Some(Failure::ServerError(code))
+        } else if status.is_client_error() {
+            Some(Failure::ClientError(code))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the failure behind an error of the hyper client.
+    ///
+    /// # Arguments
+    ///
+    /// * `e` - The error.
+    pub fn from_error(e: &hyper::Error) -> Failure {
+        match *e {
+            hyper::Error::Io(ref e) => Failure::from_io_error(e),
+            hyper::Error::Incomplete | hyper::Error::Closed | hyper::Error::Cancel(_) => Failure::Connection,
+            hyper::Error::Timeout => Failure::Timeout,
+            _ => Failure::Other,
+        }
+    }
+
+    fn from_io_error(e: &io::Error) -> Failure {
+        if let Some(failure)=e.get_ref().and_then(|inner| inner.downcast_ref::<Failure>()) {
+            return *failure;
+        }
+
+        match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Failure::Timeout,
+            io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted |
+            io::ErrorKind::NotConnected | io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof => Failure::Connection,
+            // Failed lookups of the resolver (getaddrinfo) only come with a message.
+            _ if e.to_string().starts_with("failed to lookup address") => Failure::Dns,
+            _ => Failure::Other,
+        }
+    }
+}
+
+/// Settings of how often and how late failed fetches are tried again.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Number of times a fetch is tried at most, the first one included.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each following one.
+    pub base_delay: time::Duration,
+    /// Upper bound for the delay before a retry. Servers asking (through Retry-After) for longer are given up on.
+    pub max_delay: time::Duration,
+}
+
+impl RetryPolicy {
+    /// Returns the delay before retrying a fetch that failed for the `attempt`th
+    /// time, or None if it is not to be retried. The exponential backoff is
+    /// jittered (between half and all of it), so that fetches that failed together
+    /// are not retried together.
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - Number of times the fetch failed, this one included.
+    /// * `failure` - Why it failed this time.
+    /// * `rng` - Random number generator used for the jitter.
+    pub fn delay<R: Rng>(&self, attempt: u32, failure: Failure, rng: &mut R) -> Option<time::Duration> {
+        if !failure.is_retryable() || attempt>=self.max_attempts {
+            return None;
+        }
+
+        let base_millis=duration_millis(self.base_delay);
+        let backoff_millis=cmp::min(base_millis.saturating_mul(1u64 << cmp::min(attempt-1, 32)), duration_millis(self.max_delay));
+        let jittered_millis=backoff_millis/2+rng.gen_range(0, backoff_millis-backoff_millis/2+1);
+        let delay=time::Duration::from_millis(jittered_millis);
+
+        match failure {
+            Failure::TooManyRequests(Some(retry_after)) if retry_after>self.max_delay => None,
+            Failure::TooManyRequests(Some(retry_after)) => Some(cmp::max(delay, retry_after)),
+            _ => Some(delay),
+        }
+    }
+}
+
+fn duration_millis(duration: time::Duration) -> u64 {
+    duration.as_secs().saturating_mul(1000).saturating_add((duration.subsec_nanos()/1_000_000) as u64)
+}
+
+/// What became of a failed fetch.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum RetryDecision {
+    /// It is tried again after the delay.
+    Retry(time::Duration),
+    /// It failed too often (or the server asked to wait too long, or too many fetches wait already).
+    GiveUp,
+    /// It failed in a way that retrying does not fix.
+    Permanent,
+}
+
+/// Data structure that holds the urls of failed fetches until they are due to
+/// be tried again, and counts the attempts made for each of them. It also holds
+/// urls that cannot be fetched yet, e.g. as the robots.txt of their host is unreachable.
+pub struct RetryQueue {
+    /// Urls along with the time they are due at and whether they were sent before.
+    heap: collections::BinaryHeap<cmp::Reverse<(time::Instant, String, url_meta::UrlMeta, bool)>>,
+    attempts: collections::HashMap<String, u32>,
+    policy: RetryPolicy,
+    max_len: usize,
+    rng: rand::XorShiftRng,
+}
+
+impl RetryQueue {
+    /// Creates and returns a new RetryQueue structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - How often and how late fetches are retried.
+    /// * `max_len` - Maximum amount of urls waiting to be retried.
+    pub fn new(policy: RetryPolicy, max_len: usize) -> RetryQueue {
+        RetryQueue{
+            heap: collections::BinaryHeap::new(),
+            attempts: collections::HashMap::new(),
+            policy: policy,
+            max_len: max_len,
+            rng: rand::weak_rng(),
+        }
+    }
+
+    /// Returns the amount of urls waiting to be retried.
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Records a failed fetch of `url` and decides whether it is tried again.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - Url last requested, the end of its redirect chain so far.
+    /// * `meta` - UrlMeta of the url, handed out again along with it.
+    /// * `failure` - Why the fetch failed.
+    /// * `now` - Current time.
+    pub fn failed(&mut self, url: String, meta: url_meta::UrlMeta, failure: Failure, now: time::Instant) -> RetryDecision {
+        if !failure.is_retryable() {
+            self.attempts.remove(&url);
+            return RetryDecision::Permanent;
+        }
+
+        let attempt=self.attempts.get(&url).cloned().unwrap_or(0)+1;
+        match self.policy.delay(attempt, failure, &mut self.rng) {
+            Some(delay) if self.heap.len()<self.max_len => {
+                self.attempts.insert(url.clone(), attempt);
+                self.heap.push(cmp::Reverse((now+delay, url, meta, true)));
+                RetryDecision::Retry(delay)
+            },
+            _ => {
+                self.attempts.remove(&url);
+                RetryDecision::GiveUp
+            },
+        }
+    }
+
+    /// Records a successful fetch of `url`, forgetting its failed attempts.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - Url last requested, the end of its redirect chain.
+    pub fn succeeded(&mut self, url: &str) {
+        if !self.attempts.is_empty() {
+            self.attempts.remove(url);
+        }
+    }
+
+    /// Returns the amount of failed fetches of `url` recorded, which is being
+    /// retried if there are any.
+    pub fn attempts(&self, url: &str) -> u32 {
+        self.attempts.get(url).cloned().unwrap_or(0)
+    }
+
+    /// Holds `url` (with `meta`) until `due`, without counting an attempt.
+    /// Returns false if too many urls wait already, in which case it is not held.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - Url to be held.
+    /// * `meta` - UrlMeta of the url, handed out again along with it.
+    /// * `due` - Time the url is handed out again.
+    /// * `sent` - Whether the url was sent before, and thus must skip the bloom filter.
+    pub fn postpone(&mut self, url: String, meta: url_meta::UrlMeta, due: time::Instant, sent: bool) -> bool {
+        if self.heap.len()>=self.max_len {
+            return false;
+        }
+        self.heap.push(cmp::Reverse((due, url, meta, sent)));
+        true
+    }
+
+    /// Hands out `urls` (with their UrlMeta and whether they were sent before)
+    /// from `now` on, however many urls wait already, e.g. those restored from a checkpoint.
+    pub fn restore(&mut self, urls: Vec<(String, url_meta::UrlMeta, bool)>, now: time::Instant) {
+        for (url, meta, sent) in urls.into_iter() {
+            self.heap.push(cmp::Reverse((now, url, meta, sent)));
+        }
+    }
+
+    /// Returns the urls waiting, along with their UrlMeta and whether they were sent before, to be saved in checkpoints.
+    pub fn urls(&self) -> Vec<(String, url_meta::UrlMeta, bool)> {
+        self.heap.iter().map(|&cmp::Reverse((_, ref url, ref meta, sent))| (url.clone(), meta.clone(), sent)).collect()
+    }
+
+    /// Returns a url (with its UrlMeta and whether it was sent before) due to be
+    /// tried again at `now`, if there is one. Retries of failed fetches were sent before.
+    pub fn pop_due(&mut self, now: time::Instant) -> Option<(String, url_meta::UrlMeta, bool)> {
+        match self.heap.peek() {
+            Some(&cmp::Reverse((due, _, _, _))) if due<=now => {},
+            _ => return None,
+        }
+        self.heap.pop().map(|cmp::Reverse((_, url, meta, sent))| (url, meta, sent))
+    }
+
+    /// Returns the time the next url is due to be tried again.
+    pub fn next_due(&self) -> Option<time::Instant> {
+        self.heap.peek().map(|&cmp::Reverse((due, _, _, _))| due)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy{max_attempts: 3, base_delay: time::Duration::from_millis(1000), max_delay: time::Duration::from_millis(3000)}
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(Failure::from_status(hyper::StatusCode::Ok, None), None);
+        assert_eq!(Failure::from_status(hyper::StatusCode::MovedPermanently, None), None);
+        assert_eq!(Failure::from_status(hyper::StatusCode::NotFound, None), Some(Failure::ClientError(404)));
+        assert_eq!(Failure::from_status(hyper::StatusCode::ServiceUnavailable, None), Some(Failure::ServerError(503)));
+        let retry_after=hyper::header::RetryAfter::Delay(time::Duration::from_secs(7));
+        assert_eq!(Failure::from_status(hyper::StatusCode::TooManyRequests, Some(&retry_after)), Some(Failure::TooManyRequests(Some(time::Duration::from_secs(7)))));
+
+        assert_eq!(Failure::from_error(&hyper::Error::Io(io::Error::new(io::ErrorKind::ConnectionReset, "reset"))), Failure::Connection);
+        assert_eq!(Failure::from_error(&hyper::Error::Io(io::Error::new(io::ErrorKind::Other, "failed to lookup address information: Name or service not known"))), Failure::Dns);
+        assert_eq!(Failure::from_error(&hyper::Error::Io(io::Error::new(io::ErrorKind::Other, Failure::ServerError(502)))), Failure::ServerError(502));
+        assert_eq!(Failure::from_error(&hyper::Error::Incomplete), Failure::Connection);
+        assert_eq!(Failure::from_error(&hyper::Error::Header), Failure::Other);
+
+        assert!(Failure::Dns.is_retryable());
+        assert!(!Failure::ClientError(404).is_retryable());
+        assert!(!Failure::Other.is_retryable());
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy=policy();
+        let mut rng=rand::weak_rng();
+        for _ in 0..100 {
+            let delay=policy.delay(1, Failure::Timeout, &mut rng).unwrap();
+            assert!(delay>=time::Duration::from_millis(500) && delay<=time::Duration::from_millis(1000));
+            let delay=policy.delay(2, Failure::Timeout, &mut rng).unwrap();
+            assert!(delay>=time::Duration::from_millis(1000) && delay<=time::Duration::from_millis(2000));
+        }
+        assert_eq!(policy.delay(3, Failure::Timeout, &mut rng), None);
+        assert_eq!(policy.delay(1, Failure::ClientError(403), &mut rng), None);
+
+        // Retry-After is waited for, unless it is longer than the maximum delay.
+        assert_eq!(policy.delay(1, Failure::TooManyRequests(Some(time::Duration::from_millis(2500))), &mut rng), Some(time::Duration::from_millis(2500)));
+        assert_eq!(policy.delay(1, Failure::TooManyRequests(Some(time::Duration::from_secs(3600))), &mut rng), None);
+    }
+
+    #[test]
+    fn test_retry_queue() {
+        let mut retry_queue=RetryQueue::new(policy(), 3);
+        let now=time::Instant::now();
+        assert_eq!(retry_queue.failed("http://a.com/".to_string(), url_meta::UrlMeta::default(), Failure::ClientError(404), now), RetryDecision::Permanent);
+
+        let meta=url_meta::UrlMeta{depth: 3, parent: None, discovered_secs: 0};
+        let delay=match retry_queue.failed("http://a.com/".to_string(), meta.clone(), Failure::Timeout, now) {
+            RetryDecision::Retry(delay) => delay,
+            decision => panic!("{:?}", decision),
+        };
+        assert_eq!(retry_queue.pop_due(now), None);
+        assert_eq!(retry_queue.next_due(), Some(now+delay));
+        assert_eq!(retry_queue.pop_due(now+delay), Some(("http://a.com/".to_string(), meta.clone(), true)));
+        assert_eq!((retry_queue.attempts("http://a.com/"), retry_queue.attempts("http://b.com/")), (1, 0));
+
+        // The attempts are counted until the fetch succeeds or is given up.
+        assert!(match retry_queue.failed("http://a.com/".to_string(), url_meta::UrlMeta::default(), Failure::Dns, now) {RetryDecision::Retry(_) => true, _ => false});
+        assert_eq!(retry_queue.failed("http://a.com/".to_string(), url_meta::UrlMeta::default(), Failure::Dns, now), RetryDecision::GiveUp);
+        assert!(match retry_queue.failed("http://a.com/".to_string(), url_meta::UrlMeta::default(), Failure::Dns, now) {RetryDecision::Retry(_) => true, _ => false});
+        retry_queue.succeeded("http://a.com/");
+        assert_eq!(retry_queue.attempts("http://a.com/"), 0);
+        assert!(match retry_queue.failed("http://a.com/".to_string(), url_meta::UrlMeta::default(), Failure::Dns, now) {RetryDecision::Retry(_) => true, _ => false});
+
+        // Fetches that do not fit into the queue are given up.
+        assert_eq!(retry_queue.len(), 3);
+        assert_eq!(retry_queue.failed("http://b.com/".to_string(), url_meta::UrlMeta::default(), Failure::Timeout, now), RetryDecision::GiveUp);
+        assert!(!retry_queue.postpone("http://b.com/".to_string(), meta.clone(), now, false));
+    }
+
+    #[test]
+    fn test_postpone() {
+        let mut retry_queue=RetryQueue::new(policy(), 3);
+        let now=time::Instant::now();
+        let meta=url_meta::UrlMeta{depth: 1, parent: None, discovered_secs: 0};
+        assert!(retry_queue.postpone("http://a.com/".to_string(), meta.clone(), now+time::Duration::from_secs(5), false));
+        assert!(retry_queue.postpone("http://b.com/".to_string(), meta.clone(), now, true));
+
+        // Postponed urls come out when due, without attempts counted for them.
+        assert_eq!(retry_queue.pop_due(now), Some(("http://b.com/".to_string(), meta.clone(), true)));
+        assert_eq!(retry_queue.pop_due(now), None);
+        assert_eq!(retry_queue.pop_due(now+time::Duration::from_secs(5)), Some(("http://a.com/".to_string(), meta.clone(), false)));
+        assert_eq!((retry_queue.attempts("http://a.com/"), retry_queue.len()), (0, 0));
+
+        // Restored urls are all handed out, however many there are.
+        retry_queue.restore((0..5).map(|i| (format!("http://a.com/{}", i), meta.clone(), i%2==0)).collect(), now);
+        assert_eq!((retry_queue.len(), retry_queue.urls().iter().filter(|&&(_, _, sent)| sent).count()), (5, 3));
+        assert!(!retry_queue.postpone("http://b.com/".to_string(), meta.clone(), now, false));
+        assert_eq!((0..5).filter_map(|_| retry_queue.pop_due(now)).count(), 5);
+    }
+}
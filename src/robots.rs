@@ -0,0 +1,700 @@
+#![allow(dead_code)]
+
+use body_decoder;
+use redirect;
+use futures;
+use futures::Future;
+use futures::stream::Stream;
+use hyper;
+use tokio_core;
+use url;
+use std::collections;
+use std::time;
+
+const MAX_ROBOTS_SIZE: usize = 512*1024;
+/// Number of redirects followed to get a robots.txt file, beyond which there is taken to be none.
+const MAX_ROBOTS_REDIRECTS: usize = 5;
+
+/// Rules of a robots.txt file that apply to one user agent, plus the sitemaps
+/// it lists (which apply to everyone).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Robots {
+    rules: Vec<(bool, String)>,
+    crawl_delay: Option<time::Duration>,
+    sitemaps: Vec<String>,
+}
+
+/// Checks whether the robots.txt path `pattern` matches `path`. A `*` in the
+/// pattern matches any sequence of characters and a trailing `$` anchors the
+/// pattern to the end of the path; otherwise matching a prefix is enough.
+///
+/// # Arguments
+///
+/// * `pattern` - Pattern of an Allow or Disallow directive.
+/// * `path` - Path (and query) of the url to be checked.
+fn pattern_matches(pattern: &[u8], path: &[u8]) -> bool {
+    let (pattern, anchored)=match pattern.last() {
+        Some(&b'$') => (&pattern[..pattern.len()-1], true),
+        _ => (pattern, false),
+    };
+
+    let mut pi=0;
+    let mut ti=0;
+    // Position in the pattern after the last `*`, and position in the path it currently covers up to.
+    let mut star: Option<(usize, usize)>=None;
+    while ti<path.len() {
+        if pi<pattern.len() && pattern[pi]==b'*' {
+            star=Some((pi+1, ti));
+            pi+=1;
+        } else if pi<pattern.len() && pattern[pi]==path[ti] {
+            pi+=1;
+            ti+=1;
+        } else if pi==pattern.len() && !anchored {
+            return true;
+        } else if let Some((star_pi, star_ti))=star {
+            pi=star_pi;
+            ti=star_ti+1;
+            star=Some((star_pi, star_ti+1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[pi..].iter().all(|&c| c==b'*')
+}
+
+impl Robots {
+    /// Returns a Robots structure that allows everything (e.g. for hosts without robots.txt).
+    pub fn allow_all() -> Robots {
+        Robots{rules: Vec::new(), crawl_delay: None, sitemaps: Vec::new()}
+    }
+
+    /// Returns a Robots structure that disallows everything (e.g. for hosts whose robots.txt is unreachable).
+    pub fn disallow_all() -> Robots {
+        Robots{rules: vec![(false, "/".to_string())], crawl_delay: None, sitemaps: Vec::new()}
+    }
+
+    /// Parses the content of a robots.txt file, keeping the rules of the groups
+    /// that name `user_agent` or, if there are none, those of the `*` groups.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - Content of the robots.txt file.
+    /// * `user_agent` - User agent of the crawler. Only its product token (up to the first `/`) is matched.
+    pub fn parse(content: &str, user_agent: &str) -> Robots {
+        let token=user_agent.split(|c: char| c=='/' || c.is_whitespace()).next().unwrap_or("").to_lowercase();
+
+        let mut specific=Robots::allow_all();
+        let mut generic=Robots::allow_all();
+        let mut found_specific=false;
+        let mut sitemaps=Vec::new();
+
+        // Agents of the current group, and whether rules were seen since its user-agent lines.
+        let mut group_is_specific=false;
+        let mut group_is_generic=false;
+        let mut group_has_rules=false;
+        for line in content.lines() {
+            // Remove comments and split the line into key and value.
+            let line=match line.find('#') {
+                Some(i) => &line[..i],
+                None => line,
+            };
+            let (key, value)=match line.find(':') {
+                Some(i) => (line[..i].trim().to_lowercase(), line[i+1..].trim()),
+                None => continue,
+            };
+
+            match key.as_str() {
+                "user-agent" => {
+                    // A user-agent line after rules starts a new group.
+                    if group_has_rules {
+                        group_is_specific=false;
+                        group_is_generic=false;
+                        group_has_rules=false;
+                    }
+                    let agent=value.to_lowercase();
+                    if agent=="*" {
+                        group_is_generic=true;
+                    } else if !token.is_empty() && agent==token {
+                        group_is_specific=true;
+                        found_specific=true;
+                    }
+                },
+                "allow" | "disallow" | "crawl-delay" => {
+                    group_has_rules=true;
+                    if group_is_specific {
+                        specific.add_directive(key.as_str(), value);
+                    }
+                    if group_is_generic {
+                        generic.add_directive(key.as_str(), value);
+                    }
+                },
+                "sitemap" => {
+                    if !value.is_empty() {
+                        sitemaps.push(value.to_string());
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        let mut robots=if found_specific {specific} else {generic};
+        robots.sitemaps=sitemaps;
+        robots
+    }
+
+    /// Adds an Allow, Disallow or Crawl-delay directive. Invalid ones are ignored.
+    fn add_directive(&mut self, key: &str, value: &str) {
+        if key=="crawl-delay" {
+            match value.parse::<f64>() {
+                Ok(seconds) if seconds>=0.0 && seconds.is_finite() => self.crawl_delay=Some(time::Duration::from_millis((seconds*1000.0) as u64)),
+                _ => {},
+            }
+        } else if !value.is_empty() {
+            self.rules.push((key=="allow", value.to_string()));
+        }
+    }
+
+    /// Checks whether the url with path (and query) `path` may be crawled. The
+    /// longest matching rule decides; on a tie Allow wins. Paths no rule matches
+    /// are allowed, and so is /robots.txt itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path (and query) of the url to be checked.
+    pub fn is_allowed(&self, path: &str) -> bool {
+        if path=="/robots.txt" {
+            return true;
+        }
+
+        let mut decision: Option<(usize, bool)>=None;
+        for &(allow, ref pattern) in self.rules.iter() {
+            if !pattern_matches(pattern.as_bytes(), path.as_bytes()) {
+                continue;
+            }
+            decision=match decision {
+                Some((len, decided)) if len>pattern.len() || (len==pattern.len() && decided) => Some((len, decided)),
+                _ => Some((pattern.len(), allow)),
+            };
+        }
+
+        decision.map_or(true, |(_, allow)| allow)
+    }
+
+    /// Returns the Crawl-delay given for the user agent, if any.
+    pub fn crawl_delay(&self) -> Option<time::Duration> {
+        self.crawl_delay
+    }
+
+    /// Returns the sitemap urls listed in the robots.txt file.
+    pub fn sitemaps(&self) -> &[String] {
+        &self.sitemaps
+    }
+}
+
+/// Returns the origin (scheme and authority, e.g. `http://a.com:8080`) of `uri`,
+/// which is what robots.txt files apply to.
+pub fn origin(uri: &hyper::Uri) -> Option<String> {
+    match (uri.scheme(), uri.authority()) {
+        (Some(scheme), Some(authority)) => Some(format!("{}://{}", scheme.to_lowercase(), authority.to_lowercase())),
+        _ => None,
+    }
+}
+
+/// Returns the uri of the robots.txt file for `origin`.
+pub fn robots_uri(origin: &str) -> Option<hyper::Uri> {
+    format!("{}/robots.txt", origin).parse::<hyper::Uri>().ok()
+}
+
+/// Indexing and link following directives of a single page, given by its
+/// `<meta name="robots">` tags or its `X-Robots-Tag` response headers.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub struct PageDirectives {
+    /// The page is not to be kept (its links may still be followed).
+    pub noindex: bool,
+    /// The links of the page are not to be followed.
+    pub nofollow: bool,
+}
+
+impl PageDirectives {
+    /// Parses a comma separated list of directives (e.g. `noindex, nofollow`),
+    /// as found in the content of a robots meta tag. Unknown directives are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - List of directives.
+    pub fn parse(content: &str) -> PageDirectives {
+        let mut directives=PageDirectives::default();
+        for directive in content.split(',') {
+            match directive.trim().to_lowercase().as_str() {
+                "noindex" => directives.noindex=true,
+                "nofollow" => directives.nofollow=true,
+                "none" => {
+                    directives.noindex=true;
+                    directives.nofollow=true;
+                },
+                _ => {},
+            }
+        }
+        directives
+    }
+
+    /// Parses the value of an `X-Robots-Tag` header. A value may be prefixed
+    /// with the user agent it applies to (e.g. `otherbot: noindex`), in which
+    /// case it is ignored unless that is `user_agent`.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - Value of the header.
+    /// * `user_agent` - User agent of the crawler. Only its product token (up to the first `/`) is matched.
+    pub fn parse_header(value: &str, user_agent: &str) -> PageDirectives {
+        if let Some(i)=value.find(':') {
+            let prefix=value[..i].trim().to_lowercase();
+            // Directives with values (e.g. `unavailable_after: ...`) also contain a colon.
+            let is_agent=!prefix.is_empty() && !prefix.contains(|c: char| c==',' || c.is_whitespace())
+                && !["unavailable_after", "max-snippet", "max-image-preview", "max-video-preview"].contains(&prefix.as_str());
+            if is_agent {
+                let token=user_agent.split(|c: char| c=='/' || c.is_whitespace()).next().unwrap_or("").to_lowercase();
+                if prefix!=token {
+                    return PageDirectives::default();
+                }
+                return PageDirectives::parse(&value[i+1..]);
+            }
+        }
+        PageDirectives::parse(value)
+    }
+
+    /// Gathers the directives of all `X-Robots-Tag` headers meant for `user_agent`.
+    pub fn from_headers(headers: &hyper::Headers, user_agent: &str) -> PageDirectives {
+        let mut directives=PageDirectives::default();
+        if let Some(raw)=headers.get_raw("X-Robots-Tag") {
+            for line in raw.iter() {
+                directives.merge(PageDirectives::parse_header(String::from_utf8_lossy(line).as_ref(), user_agent));
+            }
+        }
+        directives
+    }
+
+    /// Adds the directives of `other` to these.
+    pub fn merge(&mut self, other: PageDirectives) {
+        self.noindex|=other.noindex;
+        self.nofollow|=other.nofollow;
+    }
+}
+
+/// Result of checking an url against the RobotsCache.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum RobotsStatus {
+    Allowed,
+    Disallowed,
+    /// The robots.txt of the host is not known yet and is being fetched.
+    Pending,
+    /// The robots.txt of the host is not known yet and the caller should fetch it.
+    Fetch,
+    /// The robots.txt of the host could not be fetched, and is fetched again once
+    /// the time given has come. Until then, the host is not to be crawled.
+    Unreachable(time::Instant),
+}
+
+enum CacheEntry {
+    Pending(time::Instant),
+    Fetched(Robots, time::Instant),
+    Unreachable(time::Instant),
+}
+
+/// Data structure that keeps the robots.txt rules of the hosts being crawled,
+/// each for a limited time, and keeps track of which ones are being fetched.
+pub struct RobotsCache {
+    entries: collections::HashMap<String, CacheEntry>,
+    max_entries: usize,
+    pending_timeout: time::Duration,
+}
+
+impl RobotsCache {
+    /// Creates and returns a new RobotsCache structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_entries` - amount of hosts to keep rules for before expired (and then arbitrary) ones are removed.
+    /// * `pending_timeout` - time after which a fetch that has not finished is requested again.
+    pub fn new(max_entries: usize, pending_timeout: time::Duration) -> RobotsCache {
+        RobotsCache{
+            entries: collections::HashMap::new(),
+            max_entries: max_entries,
+            pending_timeout: pending_timeout,
+        }
+    }
+
+    /// Returns the amount of hosts the RobotsCache structure has entries for.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Checks whether the url with path (and query) `path` on `origin` may be
+    /// crawled. If the rules for `origin` are unknown or expired, the first
+    /// caller gets `RobotsStatus::Fetch` (and should fetch them), and the next
+    /// ones `RobotsStatus::Pending` until they are inserted.
+    ///
+    /// # Arguments
+    ///
+    /// * `origin` - Origin of the url, see `origin`.
+    /// * `path` - Path (and query) of the url.
+    pub fn check(&mut self, origin: &str, path: &str) -> RobotsStatus {
+        let now=time::Instant::now();
+        let status=match self.entries.get(origin) {
+            Some(&CacheEntry::Fetched(ref robots, expiry)) if expiry>now => {
+                if robots.is_allowed(path) {RobotsStatus::Allowed} else {RobotsStatus::Disallowed}
+            },
+            Some(&CacheEntry::Pending(since)) if now.duration_since(since)<self.pending_timeout => RobotsStatus::Pending,
+            Some(&CacheEntry::Unreachable(expiry)) if expiry>now => RobotsStatus::Unreachable(expiry),
+            _ => RobotsStatus::Fetch,
+        };
+
+        if status==RobotsStatus::Fetch {
+            self.insert_entry(origin.to_string(), CacheEntry::Pending(now));
+        }
+        status
+    }
+
+    /// Returns whether the url with path (and query) `path` on `origin` may be
+    /// crawled, or `None` if the rules for `origin` are unknown or expired. Unlike
+    /// `check`, it never marks the rules as being fetched.
+    ///
+    /// # Arguments
+    ///
+    /// * `origin` - Origin of the url, see `origin`.
+    /// * `path` - Path (and query) of the url.
+    pub fn peek(&self, origin: &str, path: &str) -> Option<bool> {
+        match self.entries.get(origin) {
+            Some(&CacheEntry::Fetched(ref robots, expiry)) if expiry>time::Instant::now() => Some(robots.is_allowed(path)),
+            _ => None,
+        }
+    }
+
+    /// Returns the Crawl-delay of `origin`, if its rules are known and contain one.
+    pub fn crawl_delay(&self, origin: &str) -> Option<time::Duration> {
+        match self.entries.get(origin) {
+            Some(&CacheEntry::Fetched(ref robots, _)) => robots.crawl_delay(),
+            _ => None,
+        }
+    }
+
+    /// Stores the rules for `origin`, to be used for the duration `ttl`.
+    ///
+    /// # Arguments
+    ///
+    /// * `origin` - Origin the rules apply to.
+    /// * `robots` - The rules.
+    /// * `ttl` - Time after which the rules have to be fetched again.
+    pub fn insert(&mut self, origin: String, robots: Robots, ttl: time::Duration) {
+        let expiry=time::Instant::now()+ttl;
+        self.insert_entry(origin, CacheEntry::Fetched(robots, expiry));
+    }
+
+    /// Records that the rules for `origin` could not be fetched, so that its urls
+    /// wait for the duration `ttl`, after which they are fetched again.
+    ///
+    /// # Arguments
+    ///
+    /// * `origin` - Origin the rules apply to.
+    /// * `ttl` - Time after which the rules have to be fetched again.
+    pub fn insert_unreachable(&mut self, origin: String, ttl: time::Duration) {
+        let expiry=time::Instant::now()+ttl;
+        self.insert_entry(origin, CacheEntry::Unreachable(expiry));
+    }
+
+    fn insert_entry(&mut self, origin: String, entry: CacheEntry) {
+        if self.entries.len()>=self.max_entries && !self.entries.contains_key(&origin) {
+            // Make room, removing expired entries first.
+            let now=time::Instant::now();
+            let pending_timeout=self.pending_timeout;
+            self.entries.retain(|_, entry| match *entry {
+                CacheEntry::Fetched(_, expiry) | CacheEntry::Unreachable(expiry) => expiry>now,
+                CacheEntry::Pending(since) => now.duration_since(since)<pending_timeout,
+            });
+            if self.entries.len()>=self.max_entries {
+                let key=self.entries.keys().next().cloned();
+                if let Some(key)=key {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+        self.entries.insert(origin, entry);
+    }
+}
+
+/// Returns the url the redirect response `res` to a request for `uri` leads to,
+/// if it has a Location header resolving to an http(s) url.
+fn redirect_target(uri: &hyper::Uri, res: &hyper::Response) -> Option<hyper::Uri> {
+    let location=res.headers().get_raw("Location").and_then(|raw| raw.one()).map(|line| String::from_utf8_lossy(line).into_owned())?;
+    let target=url::Url::parse(uri.to_string().as_str()).and_then(|base| base.join(location.trim())).ok()?;
+    if target.scheme()!="http" && target.scheme()!="https" {
+        return None;
+    }
+    target.as_str().parse::<hyper::Uri>().ok()
+}
+
+/// Fetches and parses a robots.txt file, following up to MAX_ROBOTS_REDIRECTS
+/// redirects (e.g. from http to https). Resolves to `Some` with the parsed rules
+/// on success, to `Some(Robots::allow_all())` if there is no robots.txt (4xx, or
+/// redirects that lead nowhere), and to `None` if it is unreachable (5xx, 429,
+/// error or timeout), in which case the host should not be crawled for a while.
+///
+/// # Arguments
+///
+/// * `client` - Client to get the robots.txt file with.
+/// * `request` - Get request for the robots.txt file.
+/// * `user_agent` - User agent whose rules are kept.
+/// * `timeout` - Time the request may take.
+/// * `handle` - Handle of the core the request runs on.
+pub fn fetch_robots<C: hyper::client::Connect>(client: &hyper::Client<C>, request: hyper::Request, user_agent: String, timeout: time::Duration, handle: &tokio_core::reactor::Handle) -> Box<Future<Item=Option<Robots>, Error=()>> {
+    let timeout=match tokio_core::reactor::Timeout::new(timeout, handle) {
+        Ok(timeout) => timeout,
+        Err(e) => {
+            eprintln!("Error (fetch_robots): {:?}", e);
+            return Box::new(futures::future::ok(None));
+        },
+    };
+
+    let client=client.clone();
+    let headers=request.headers().clone();
+    let fetch=futures::future::loop_fn((request, 0), move |(request, redirects)| {
+        let uri=request.uri().clone();
+        let headers=headers.clone();
+        client.request(request)
+        .map(move |res| {
+            if redirects<MAX_ROBOTS_REDIRECTS && redirect::is_redirect(res.status()) {
+                if let Some(target)=redirect_target(&uri, &res) {
+                    let mut request=hyper::Request::new(hyper::Method::Get, target);
+                    *request.headers_mut()=headers;
+                    return futures::future::Loop::Continue((request, redirects+1));
+                }
+            }
+            futures::future::Loop::Break(res)
+        })
+    })
+    .and_then(move |res| {
+        let status=res.status();
+        // Content beyond MAX_ROBOTS_SIZE (once decoded) is ignored.
+        let content_encoding=res.headers().get_raw("Content-Encoding").and_then(|raw| raw.one()).map(|value| String::from_utf8_lossy(value).into_owned());
+        let content_encoding=body_decoder::ContentEncoding::from_header(content_encoding.as_ref().map(|e| e.as_str()));
+        let body: Box<Future<Item=Option<Robots>, Error=hyper::Error>>=if status.is_success() {
+            match content_encoding {
+                Ok(content_encoding) => {
+                    let decoder=body_decoder::BodyDecoder::truncating(content_encoding, MAX_ROBOTS_SIZE);
+                    Box::new(res.body()
+                    .fold(decoder, |mut decoder, chunk| decoder.write(&chunk).map(|_| decoder))
+                    .and_then(|decoder| decoder.finish().map_err(hyper::Error::from))
+                    .map(move |content| Some(Robots::parse(&String::from_utf8_lossy(&content), user_agent.as_str()))))
+                },
+                Err(e) => {
+                    eprintln!("Error (fetch_robots): {:?}", e);
+                    Box::new(futures::future::ok(None))
+                },
+            }
+        } else if status.is_server_error() || status==hyper::StatusCode::TooManyRequests {
+            Box::new(futures::future::ok(None))
+        } else {
+            Box::new(futures::future::ok(Some(Robots::allow_all())))
+        };
+        body
+    });
+
+    Box::new(fetch
+    .select2(timeout)
+    .then(|t| {
+        match t {
+            Ok(futures::future::Either::A((robots, _))) => Ok(robots),
+            Ok(futures::future::Either::B(_)) => Ok(None),
+            Err(futures::future::Either::A((e, _))) => {eprintln!("Error (fetch_robots): {:?}", e);Ok(None)},
+            Err(futures::future::Either::B((e, _))) => {eprintln!("Error (fetch_robots): {:?}", e);Ok(None)},
+        }
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net;
+    use std::thread;
+
+    const ROBOTS: &str = "
+# Comments are ignored.
+User-agent: *
+Disallow: /private/
+Allow: /private/public
+Disallow: /*.gif$
+Crawl-delay: 3
+
+User-agent: otherbot
+User-agent: rustcrawl
+Disallow: /no-rustcrawl
+Allow: /no-rustcrawl/but-this
+Disallow: /search*q=
+Crawl-delay: 0.5
+
+Sitemap: http://a.com/sitemap.xml
+";
+
+    #[test]
+    fn test_pattern_matches() {
+        assert!(pattern_matches(b"/", b"/anything"));
+        assert!(pattern_matches(b"/fish", b"/fish.html"));
+        assert!(!pattern_matches(b"/fish", b"/Fish"));
+        assert!(pattern_matches(b"/*.php", b"/folder/index.php?a=1"));
+        assert!(pattern_matches(b"/*.php$", b"/index.php"));
+        assert!(!pattern_matches(b"/*.php$", b"/index.php?a=1"));
+        assert!(pattern_matches(b"/fish*", b"/fish"));
+        assert!(pattern_matches(b"/a*b*c", b"/aXbYbZc"));
+        assert!(!pattern_matches(b"/a*b*c$", b"/aXbYcZ"));
+        assert!(pattern_matches(b"*", b"/"));
+        assert!(!pattern_matches(b"/fish/", b"/fish"));
+    }
+
+    #[test]
+    fn test_parse_generic() {
+        let robots=Robots::parse(ROBOTS, "somebot/1.0");
+        assert!(robots.is_allowed("/"));
+        assert!(!robots.is_allowed("/private/file"));
+        assert!(robots.is_allowed("/private/public/file"));
+        assert!(!robots.is_allowed("/images/a.gif"));
+        assert!(robots.is_allowed("/images/a.gif?size=2"));
+        assert!(robots.is_allowed("/no-rustcrawl"));
+        assert!(robots.is_allowed("/robots.txt"));
+        assert_eq!(robots.crawl_delay(), Some(time::Duration::from_secs(3)));
+        assert_eq!(robots.sitemaps(), &["http://a.com/sitemap.xml".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_specific() {
+        let robots=Robots::parse(ROBOTS, "RustCrawl/0.1 (+http://example.com)");
+        assert!(robots.is_allowed("/private/file"));
+        assert!(!robots.is_allowed("/no-rustcrawl/page"));
+        assert!(robots.is_allowed("/no-rustcrawl/but-this/page"));
+        assert!(!robots.is_allowed("/search?lang=en&q=rust"));
+        assert!(robots.is_allowed("/search?lang=en"));
+        assert_eq!(robots.crawl_delay(), Some(time::Duration::from_millis(500)));
+        assert_eq!(robots.sitemaps().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_edge_cases() {
+        assert!(Robots::parse("", "rustcrawl").is_allowed("/a"));
+        assert!(Robots::parse("User-agent: *\nDisallow:\n", "rustcrawl").is_allowed("/a"));
+        assert!(!Robots::parse("user-agent: *\ndisallow: /\n", "rustcrawl").is_allowed("/a"));
+        // Equally long allow and disallow rules: allow wins.
+        assert!(Robots::parse("User-agent: *\nDisallow: /a\nAllow: /a\n", "rustcrawl").is_allowed("/a"));
+        // A group naming the crawler overrides the `*` group, and blank lines do not end groups.
+        assert!(Robots::parse("User-agent: rustcrawl\nAllow: /\n\nUser-agent: *\nDisallow: /\n", "rustcrawl").is_allowed("/a"));
+        assert!(!Robots::parse("User-agent: rustcrawl\n\nUser-agent: *\nDisallow: /\n", "rustcrawl").is_allowed("/a"));
+        assert!(!Robots::disallow_all().is_allowed("/a"));
+        assert!(Robots::allow_all().is_allowed("/a"));
+    }
+
+    #[test]
+    fn test_robots_cache() {
+        let mut robots_cache=RobotsCache::new(2, time::Duration::from_secs(60));
+        assert_eq!(robots_cache.peek("http://a.com", "/x"), None);
+        assert_eq!(robots_cache.check("http://a.com", "/x"), RobotsStatus::Fetch);
+        assert_eq!(robots_cache.check("http://a.com", "/x"), RobotsStatus::Pending);
+
+        robots_cache.insert("http://a.com".to_string(), Robots::parse("User-agent: *\nDisallow: /x\nCrawl-delay: 2", "rustcrawl"), time::Duration::from_secs(60));
+        assert_eq!(robots_cache.check("http://a.com", "/x"), RobotsStatus::Disallowed);
+        assert_eq!(robots_cache.check("http://a.com", "/y"), RobotsStatus::Allowed);
+        assert_eq!(robots_cache.peek("http://a.com", "/x"), Some(false));
+        assert_eq!(robots_cache.peek("http://a.com", "/y"), Some(true));
+        assert_eq!(robots_cache.crawl_delay("http://a.com"), Some(time::Duration::from_secs(2)));
+
+        // Expired rules have to be fetched again.
+        robots_cache.insert("http://b.com".to_string(), Robots::allow_all(), time::Duration::from_secs(0));
+        assert_eq!(robots_cache.check("http://b.com", "/y"), RobotsStatus::Fetch);
+
+        // Unreachable rules keep the urls of their host waiting until they are fetched again.
+        robots_cache.insert_unreachable("http://b.com".to_string(), time::Duration::from_secs(60));
+        assert!(match robots_cache.check("http://b.com", "/y") {RobotsStatus::Unreachable(expiry) => expiry>time::Instant::now(), _ => false});
+        assert_eq!(robots_cache.peek("http://b.com", "/y"), None);
+        robots_cache.insert_unreachable("http://b.com".to_string(), time::Duration::from_secs(0));
+        assert_eq!(robots_cache.check("http://b.com", "/y"), RobotsStatus::Fetch);
+
+        // The cache does not grow beyond its maximum.
+        robots_cache.insert("http://c.com".to_string(), Robots::allow_all(), time::Duration::from_secs(60));
+        assert!(robots_cache.len()<=2);
+    }
+
+    #[test]
+    fn test_origin() {
+        let uri="HTTP://A.com:8080/path?q".parse::<hyper::Uri>().unwrap();
+        assert_eq!(origin(&uri), Some("http://a.com:8080".to_string()));
+        assert_eq!(robots_uri("http://a.com:8080").map(|uri| uri.to_string()), Some("http://a.com:8080/robots.txt".to_string()));
+        assert_eq!(origin(&"/relative".parse::<hyper::Uri>().unwrap()), None);
+    }
+
+    /// Serves a single http response with status line `status` and body `body` on a local port.
+    fn serve_once(status: &'static str, body: &'static str) -> net::SocketAddr {
+        let listener=net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr=listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _)=listener.accept().unwrap();
+            let mut buf=[0u8;4096];
+            let _=stream.read(&mut buf);
+            let _=stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body).as_bytes());
+        });
+        addr
+    }
+
+    /// Serves a single redirect to `location` on a local port.
+    fn serve_redirect(location: String) -> net::SocketAddr {
+        let listener=net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr=listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _)=listener.accept().unwrap();
+            let mut buf=[0u8;4096];
+            let _=stream.read(&mut buf);
+            let _=stream.write_all(format!("HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", location).as_bytes());
+        });
+        addr
+    }
+
+    fn fetch_from(addr: net::SocketAddr) -> Option<Robots> {
+        let mut core=tokio_core::reactor::Core::new().unwrap();
+        let handle=core.handle();
+        let client=hyper::Client::new(&handle);
+        let uri=robots_uri(format!("http://{}", addr).as_str()).unwrap();
+        let request=hyper::Request::new(hyper::Method::Get, uri);
+        core.run(fetch_robots(&client, request, "rustcrawl".to_string(), time::Duration::from_secs(10), &handle)).unwrap()
+    }
+
+    #[test]
+    fn test_fetch_robots_local_server() {
+        let robots=fetch_from(serve_once("200 OK", "User-agent: *\nDisallow: /secret\n")).unwrap();
+        assert!(!robots.is_allowed("/secret/x"));
+        assert!(robots.is_allowed("/public"));
+
+        assert_eq!(fetch_from(serve_once("404 Not Found", "")), Some(Robots::allow_all()));
+        assert_eq!(fetch_from(serve_once("503 Service Unavailable", "")), None);
+
+        // Redirects are followed, to a limit.
+        let target=serve_once("200 OK", "User-agent: *\nDisallow: /\n");
+        let robots=fetch_from(serve_redirect(format!("http://{}/robots.txt", target))).unwrap();
+        assert!(!robots.is_allowed("/x"));
+        let mut addr=serve_once("200 OK", "User-agent: *\nDisallow: /\n");
+        for _ in 0..MAX_ROBOTS_REDIRECTS+1 {
+            addr=serve_redirect(format!("http://{}/robots.txt", addr));
+        }
+        assert_eq!(fetch_from(addr), Some(Robots::allow_all()));
+    }
+
+    #[test]
+    fn test_page_directives() {
+        assert_eq!(PageDirectives::parse("noindex, NoFollow"), PageDirectives{noindex: true, nofollow: true});
+        assert_eq!(PageDirectives::parse("none"), PageDirectives{noindex: true, nofollow: true});
+        assert_eq!(PageDirectives::parse("index,follow,noarchive"), PageDirectives::default());
+        assert_eq!(PageDirectives::parse_header("nofollow", "rustcrawl/0.1"), PageDirectives{noindex: false, nofollow: true});
+        assert_eq!(PageDirectives::parse_header("RustCrawl: noindex", "rustcrawl/0.1"), PageDirectives{noindex: true, nofollow: false});
+        assert_eq!(PageDirectives::parse_header("otherbot: noindex, nofollow", "rustcrawl/0.1"), PageDirectives::default());
+        assert_eq!(PageDirectives::parse_header("unavailable_after: 25 Jun 2010 15:00:00 PST", "rustcrawl/0.1"), PageDirectives::default());
+    }
+}
@@ -0,0 +1,289 @@
+#![allow(dead_code)]
+
+use config;
+use public_suffix;
+use regex;
+use url;
+use std::sync;
+
+/// Decision taken about an url by the scope of the crawl. Every url is checked
+/// against the rules in the order of the variants, and the first rule it breaks
+/// decides.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Decision {
+    /// The url is within the scope, and may be crawled.
+    Accepted,
+    /// The url could not be parsed, or has no host.
+    InvalidUrl,
+    /// The scheme of the url is not among the allowed ones.
+    Scheme,
+    /// The port of the url (explicit or implied by its scheme) is not among the allowed ones.
+    Port,
+    /// The host of the url is denied.
+    HostDenied,
+    /// The host of the url is not among the allowed ones.
+    HostNotAllowed,
+    /// The url is not on the registrable domain of any seed.
+    OffSite,
+    /// The path of the url starts with a denied prefix.
+    PathDenied,
+    /// The path of the url starts with none of the allowed prefixes.
+    PathNotAllowed,
+    /// The url matches an exclude pattern.
+    Excluded,
+    /// The url matches none of the include patterns.
+    NotIncluded,
+}
+
+/// Names of the counters of each decision, in the order of `Decision`.
+pub const DECISION_NAMES: [&str;11] = [
+    "scope_accepted",
+    "scope_invalid_url",
+    "scope_scheme",
+    "scope_port",
+    "scope_host_denied",
+    "scope_host_not_allowed",
+    "scope_off_site",
+    "scope_path_denied",
+    "scope_path_not_allowed",
+    "scope_excluded",
+    "scope_not_included",
+];
+
+impl Decision {
+    /// Returns the name of the counter of the decision.
+    pub fn name(&self) -> &'static str {
+        DECISION_NAMES[*self as usize]
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        *self==Decision::Accepted
+    }
+}
+
+/// Pattern matching hosts: `example.com` matches that host only, while
+/// `*.example.com` matches it along with all of its subdomains.
+#[derive(Debug, PartialEq, Clone)]
+struct HostPattern {
+    domain: String,
+    subdomains: bool,
+}
+
+impl HostPattern {
+    fn new(pattern: &str) -> HostPattern {
+        let pattern=pattern.trim().trim_right_matches('.').to_lowercase();
+        if pattern.starts_with("*.") {
+            HostPattern{domain: pattern[2..].to_string(), subdomains: true}
+        } else {
+            HostPattern{domain: pattern, subdomains: false}
+        }
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        host==self.domain || (self.subdomains && host.len()>self.domain.len() && host.ends_with(self.domain.as_str()) && host.as_bytes()[host.len()-self.domain.len()-1]==b'.')
+    }
+}
+
+/// Rules deciding which urls the crawl may go to, checked before urls enter the
+/// url reservoir, along with counters of the decisions taken.
+pub struct Scope {
+    schemes: Vec<String>,
+    ports: Vec<u16>,
+    denied_hosts: Vec<HostPattern>,
+    allowed_hosts: Vec<HostPattern>,
+    /// Registrable domains of the seeds, if the crawl is to stay on them, along with the list telling them apart.
+    sites: Option<(Vec<String>, public_suffix::PublicSuffixList)>,
+    denied_path_prefixes: Vec<String>,
+    allowed_path_prefixes: Vec<String>,
+    exclude: Option<regex::RegexSet>,
+    include: Option<regex::RegexSet>,
+    counters: Vec<sync::Arc<sync::atomic::AtomicUsize>>,
+}
+
+/// Compiles `patterns` into a RegexSet, or returns None if there are none.
+fn regex_set(patterns: &[String]) -> Result<Option<regex::RegexSet>, String> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    regex::RegexSet::new(patterns).map(Some).map_err(|e| format!("invalid scope pattern: {}", e))
+}
+
+impl Scope {
+    /// Creates and returns a new Scope structure, or an error if a pattern is not
+    /// a valid regex or the public suffix list cannot be read.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Settings of the crawl (the scope rules and, for `scope_same_site`, the seeds and public suffix list).
+    /// * `start` - Returns the value a counter (given its name) starts from, e.g. as saved by a checkpoint.
+    pub fn new<F: Fn(&str) -> usize>(config: &config::CrawlConfig, start: F) -> Result<Scope, String> {
+        let sites=if config.scope_same_site {
+            let suffixes=if config.scope_public_suffix_file.is_empty() {
+                public_suffix::PublicSuffixList::bundled()
+            } else {
+                public_suffix::PublicSuffixList::from_file(config.scope_public_suffix_file.as_str())?
+            };
+            let mut sites:Vec<String>=config.seeds.iter()
+            .filter_map(|seed| url::Url::parse(seed).ok())
+            .filter_map(|seed| seed.host_str().map(|host| suffixes.registrable_domain(host.trim_right_matches('.')).to_string()))
+            .collect();
+            sites.sort();
+            sites.dedup();
+            Some((sites, suffixes))
+        } else {
+            None
+        };
+
+        Ok(Scope{
+            schemes: config.scope_allowed_schemes.iter().map(|scheme| scheme.to_lowercase()).collect(),
+            ports: config.scope_allowed_ports.clone(),
+            denied_hosts: config.scope_denied_hosts.iter().map(|pattern| HostPattern::new(pattern)).collect(),
+            allowed_hosts: config.scope_allowed_hosts.iter().map(|pattern| HostPattern::new(pattern)).collect(),
+            sites: sites,
+            denied_path_prefixes: config.scope_denied_path_prefixes.clone(),
+            allowed_path_prefixes: config.scope_allowed_path_prefixes.clone(),
+            exclude: regex_set(&config.scope_exclude_patterns)?,
+            include: regex_set(&config.scope_include_patterns)?,
+            counters: DECISION_NAMES.iter().map(|name| sync::Arc::new(sync::atomic::AtomicUsize::new(start(name)))).collect(),
+        })
+    }
+
+    /// Returns the decision about `url`, without counting it.
+    pub fn decide(&self, url: &url::Url) -> Decision {
+        let host=match url.host_str() {
+            Some(host) => host.trim_right_matches('.'),
+            None => return Decision::InvalidUrl,
+        };
+        if !self.schemes.is_empty() && !self.schemes.iter().any(|scheme| scheme==url.scheme()) {
+            return Decision::Scheme;
+        }
+        if !self.ports.is_empty() && !url.port_or_known_default().map_or(false, |port| self.ports.contains(&port)) {
+            return Decision::Port;
+        }
+
+        if self.denied_hosts.iter().any(|pattern| pattern.matches(host)) {
+            return Decision::HostDenied;
+        }
+        if !self.allowed_hosts.is_empty() && !self.allowed_hosts.iter().any(|pattern| pattern.matches(host)) {
+            return Decision::HostNotAllowed;
+        }
+        if let Some((ref sites, ref suffixes))=self.sites {
+            let site=suffixes.registrable_domain(host);
+            if !sites.iter().any(|s| s==site) {
+                return Decision::OffSite;
+            }
+        }
+
+        let path=url.path();
+        if self.denied_path_prefixes.iter().any(|prefix| path.starts_with(prefix.as_str())) {
+            return Decision::PathDenied;
+        }
+        if !self.allowed_path_prefixes.is_empty() && !self.allowed_path_prefixes.iter().any(|prefix| path.starts_with(prefix.as_str())) {
+            return Decision::PathNotAllowed;
+        }
+
+        if self.exclude.as_ref().map_or(false, |exclude| exclude.is_match(url.as_str())) {
+            return Decision::Excluded;
+        }
+        if self.include.as_ref().map_or(false, |include| !include.is_match(url.as_str())) {
+            return Decision::NotIncluded;
+        }
+        Decision::Accepted
+    }
+
+    /// Returns the decision about `url`, and counts it.
+    pub fn check(&self, url: &url::Url) -> Decision {
+        let decision=self.decide(url);
+        self.counters[decision as usize].fetch_add(1, sync::atomic::Ordering::Relaxed);
+        decision
+    }
+
+    /// Returns whether the url `url` is within the scope, counting the decision.
+    /// Urls that cannot be parsed are not.
+    pub fn allows(&self, url: &str) -> bool {
+        match url::Url::parse(url) {
+            Ok(url) => self.check(&url).is_accepted(),
+            Err(_) => {
+                self.counters[Decision::InvalidUrl as usize].fetch_add(1, sync::atomic::Ordering::Relaxed);
+                false
+            },
+        }
+    }
+
+    /// Returns the value of the counter named `name`, or 0 if there is none.
+    pub fn get(&self, name: &str) -> usize {
+        DECISION_NAMES.iter().position(|&n| n==name).map_or(0, |i| self.counters[i].load(sync::atomic::Ordering::Relaxed))
+    }
+
+    /// Returns the counters along with their names, to be saved in checkpoints.
+    pub fn named(&self) -> Vec<(&'static str, sync::Arc<sync::atomic::AtomicUsize>)> {
+        DECISION_NAMES.iter().cloned().zip(self.counters.iter().cloned()).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decide(scope: &Scope, url: &str) -> Decision {
+        scope.decide(&url::Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn test_host_pattern() {
+        let pattern=HostPattern::new("*.Example.com");
+        assert!(pattern.matches("example.com"));
+        assert!(pattern.matches("a.b.example.com"));
+        assert!(!pattern.matches("badexample.com"));
+        assert!(!pattern.matches("example.com.evil.org"));
+        let pattern=HostPattern::new("example.com");
+        assert!(pattern.matches("example.com"));
+        assert!(!pattern.matches("www.example.com"));
+    }
+
+    #[test]
+    fn test_decide() {
+        let mut config=config::CrawlConfig::default();
+        assert_eq!(decide(&Scope::new(&config, |_| 0).unwrap(), "ftp://a.com/"), Decision::Scheme);
+        assert_eq!(decide(&Scope::new(&config, |_| 0).unwrap(), "https://a.com:8443/"), Decision::Accepted);
+
+        config.seeds=vec!["http://www.example.co.uk/".to_string()];
+        config.scope_same_site=true;
+        config.scope_allowed_ports=vec![80, 443];
+        config.scope_denied_hosts=vec!["*.ads.example.co.uk".to_string()];
+        config.scope_denied_path_prefixes=vec!["/private".to_string()];
+        config.scope_exclude_patterns=vec![r"\.(jpg|png)$".to_string()];
+        config.scope_include_patterns=vec!["/docs/".to_string(), "/$".to_string()];
+        let scope=Scope::new(&config, |_| 0).unwrap();
+        assert_eq!(decide(&scope, "https://shop.example.co.uk/docs/a"), Decision::Accepted);
+        assert_eq!(decide(&scope, "http://example.co.uk/"), Decision::Accepted);
+        assert_eq!(decide(&scope, "http://example.co.uk:8080/"), Decision::Port);
+        assert_eq!(decide(&scope, "http://x.ads.example.co.uk/"), Decision::HostDenied);
+        assert_eq!(decide(&scope, "http://other.co.uk/"), Decision::OffSite);
+        assert_eq!(decide(&scope, "http://example.co.uk/private/docs/"), Decision::PathDenied);
+        assert_eq!(decide(&scope, "http://example.co.uk/docs/a.png"), Decision::Excluded);
+        assert_eq!(decide(&scope, "http://example.co.uk/blog/a"), Decision::NotIncluded);
+
+        config.scope_same_site=false;
+        config.scope_allowed_hosts=vec!["*.a.com".to_string(), "b.com".to_string()];
+        config.scope_allowed_path_prefixes=vec!["/docs/".to_string()];
+        let scope=Scope::new(&config, |name| if name=="scope_accepted" {5} else {0}).unwrap();
+        assert_eq!(decide(&scope, "http://www.b.com/docs/"), Decision::HostNotAllowed);
+        assert_eq!(decide(&scope, "http://a.com/"), Decision::PathNotAllowed);
+        assert_eq!(decide(&scope, "data:text/plain,docs/"), Decision::InvalidUrl);
+
+        // Only checks are counted.
+        assert!(scope.allows("http://www.a.com/docs/"));
+        assert!(!scope.allows("http://b.com/docs/a.jpg"));
+        assert!(!scope.allows("not an url"));
+        assert_eq!((scope.get("scope_accepted"), scope.get("scope_excluded"), scope.get("scope_invalid_url"), scope.get("scope_path_not_allowed")), (6, 1, 1, 0));
+
+        config.scope_exclude_patterns=vec!["(".to_string()];
+        assert!(Scope::new(&config, |_| 0).is_err());
+        config.scope_exclude_patterns=Vec::new();
+        config.scope_same_site=true;
+        config.scope_public_suffix_file="/no/such/list.dat".to_string();
+        assert!(Scope::new(&config, |_| 0).is_err());
+    }
+}
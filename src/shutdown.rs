@@ -0,0 +1,100 @@
+#![allow(dead_code)]
+
+use signal_hook;
+use std::cmp;
+use std::io;
+use std::sync;
+use std::thread;
+use std::time;
+
+/// Flag shared by the threads of the crawl, set once the crawl is to stop
+/// (on SIGINT or SIGTERM, or when `request` is called).
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: sync::Arc<sync::atomic::AtomicBool>,
+}
+
+impl Shutdown {
+    /// Creates and returns a new Shutdown structure, not requested yet.
+    pub fn new() -> Shutdown {
+        Shutdown{
+            requested: sync::Arc::new(sync::atomic::AtomicBool::new(false)),
+        }
+    }
+
+    /// Makes SIGINT and SIGTERM request the shutdown instead of killing the
+    /// process, unless the shutdown was requested already: a second signal
+    /// terminates the process at once.
+    pub fn register_signals(&self) -> io::Result<()> {
+        for &signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM].iter() {
+            // Registered first, so that it only sees the flag set by an earlier signal.
+            signal_hook::flag::register_conditional_shutdown(signal, 1, self.requested.clone())?;
+            signal_hook::flag::register(signal, self.requested.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Requests the shutdown.
+    pub fn request(&self) {
+        self.requested.store(true, sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Returns whether the shutdown was requested.
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Blocks until the shutdown is requested.
+    ///
+    /// # Arguments
+    ///
+    /// * `poll_interval` - Time waited between looks at the flag.
+    pub fn wait(&self, poll_interval: time::Duration) {
+        while !self.is_requested() {
+            thread::sleep(poll_interval);
+        }
+    }
+
+    /// Blocks until the shutdown is requested or `timeout` passed, looking at
+    /// the flag every 100 milliseconds. Returns whether the shutdown was requested.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - Time waited at most.
+    pub fn wait_timeout(&self, timeout: time::Duration) -> bool {
+        let deadline=time::Instant::now()+timeout;
+        while !self.is_requested() {
+            let now=time::Instant::now();
+            if now>=deadline {
+                return false;
+            }
+            thread::sleep(cmp::min(deadline-now, time::Duration::from_millis(100)));
+        }
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shutdown() {
+        let shutdown=Shutdown::new();
+        assert!(!shutdown.is_requested());
+
+        let waiter={
+            let shutdown=shutdown.clone();
+            thread::spawn(move || shutdown.wait(time::Duration::from_millis(5)))
+        };
+        shutdown.clone().request();
+        waiter.join().unwrap();
+        assert!(shutdown.is_requested());
+        assert!(shutdown.wait_timeout(time::Duration::from_secs(60)));
+
+        let start=time::Instant::now();
+        assert!(!Shutdown::new().wait_timeout(time::Duration::from_millis(150)));
+        assert!(start.elapsed()>=time::Duration::from_millis(150));
+    }
+}
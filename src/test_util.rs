@@ -0,0 +1,45 @@
+use std::env;
+use std::fs;
+use std::path;
+use std::process;
+use std::sync;
+
+static TEMP_DIRS: sync::atomic::AtomicUsize = sync::atomic::AtomicUsize::new(0);
+
+/// Directory of a test's own, removed (along with its files) when dropped, so
+/// that tests running at the same time, in this process or another, never
+/// share files.
+pub struct TempDir {
+    path: path::PathBuf,
+}
+
+impl TempDir {
+    /// Creates and returns a new, empty TempDir structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the test, which the name of the directory starts with.
+    pub fn new(name: &str) -> TempDir {
+        let n=TEMP_DIRS.fetch_add(1, sync::atomic::Ordering::SeqCst);
+        let path=env::temp_dir().join(format!("rustcrawl_test_{}_{}_{}", name, process::id(), n));
+        let _=fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir{path: path}
+    }
+
+    /// Returns the path of the file named `name` within the directory.
+    pub fn join(&self, name: &str) -> path::PathBuf {
+        self.path.join(name)
+    }
+
+    /// Returns the path of the file named `name` within the directory, as a String.
+    pub fn file(&self, name: &str) -> String {
+        self.join(name).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _=fs::remove_dir_all(&self.path);
+    }
+}
@@ -1,44 +1,110 @@
 use bloom_filter;
-use url_reservoir;
+use canonical;
+use config;
+use crawl_error::CrawlError;
+use crawl_error;
+use frontier;
+use host_scheduler;
+use outstanding;
+use retry;
+use robots;
+use shutdown;
+use url_meta;
 use futures;
 use hyper;
+use std::cmp;
+use std::collections;
+use std::mem;
 use std::thread;
 use std::sync;
 use std::time;
 
-const SLEEP_MILLIS_AFTER_SEND: u64 = 12;
-const SLEEP_MILLIS_ON_EMPTY_RESERVOIR: u64 = 2000;
-const SLEEP_MILLIS_ON_FULL_CHANNEL: u64 = 8000;
-const MAX_URLS_PER_ITER: usize = 100;
+/// Url on its way from the frontier (or the retry queue) to the host scheduler.
+struct Pending {
+    url: String,
+    origin: String,
+    uri: hyper::Uri,
+    meta: url_meta::UrlMeta,
+    /// Whether the url was sent before, as retries were, and thus is in the bloom filter already.
+    sent: bool,
+}
 
-
-
-/// Within an endless loop, it obtains urls from the `url_reservoir` and sends them
-/// via `uri_sink` to be processed. It makes use of `bloom_filter` to not send the
-/// same url twice.
+/// Within an endless loop, it obtains urls from the `frontier` and sends them
+/// (along with their UrlMeta) via `uri_sink` to be processed. It makes use of `bloom_filter` to not send the
+/// same url twice, checking urls once rewritten by `canonicalizer`, and of `robots_cache` to not send urls disallowed by the
+/// robots.txt of their host. Urls whose host's robots.txt is not known yet are
+/// held back until it has been fetched, after requesting it through `robots_sink`
+/// (those beyond `max_urls_awaiting_robots` going back to the frontier), and those
+/// whose host's robots.txt is unreachable wait in `retry_queue` until it is fetched again.
+/// Allowed urls go through `host_scheduler`, which decides when each host may
+/// be requested again. Failed fetches come back through `retry_queue` once they
+/// are due to be tried again. While `paused` is set it sends nothing. Once
+/// `shutdown` is requested, it stops and drops its sinks, so that the IO loop runs
+/// out of urls.
 ///
 /// # Arguments
 ///
 /// * `uri_sink` - Channel sink where suitable urls are sent through.
-/// * `urls_enqueued` - Atomic counter that counts the urls sent through `uri_sink`
-/// * `bloom_filter` - BloomFilter that keeps track of already sent urls.
-/// * `url_reservoir` - Large structure containing urls that could be sent.
-pub fn url_enqueuer(mut uri_sink: futures::sync::mpsc::Sender<hyper::Uri>, urls_enqueued: sync::Arc<sync::atomic::AtomicUsize>, bloom_filter: sync::Arc<sync::Mutex<bloom_filter::LargeBloomFilter>>, url_reservoir: sync::Arc<sync::Mutex<url_reservoir::UrlReservoir>>){
-    let sleep_duration_after_send=time::Duration::from_millis(SLEEP_MILLIS_AFTER_SEND);
-    let sleep_duration_on_empty_reservoir=time::Duration::from_millis(SLEEP_MILLIS_ON_EMPTY_RESERVOIR);
-    let sleep_duration_on_full_channel=time::Duration::from_millis(SLEEP_MILLIS_ON_FULL_CHANNEL);
-
-    let mut urls=Vec::with_capacity(MAX_URLS_PER_ITER);
+/// * `robots_sink` - Channel sink where the urls of robots.txt files to be fetched are sent through.
+/// * `urls_enqueued` - Atomic counter that counts the urls sent through `uri_sink`, retries aside.
+/// * `retries_enqueued` - Atomic counter that counts the urls of failed fetches sent through `uri_sink` again.
+/// * `urls_disallowed` - Atomic counter that counts the urls dropped because of robots.txt rules.
+/// * `bloom_filter` - ConcurrentBloomFilter that keeps track of already sent urls.
+/// * `canonicalizer` - Canonicalizer the urls are rewritten with before they are checked against `bloom_filter`.
+/// * `frontier` - Frontier containing urls that could be sent.
+/// * `outstanding` - Outstanding urls, which those taken from `frontier` and `retry_queue` are until they are done with.
+/// * `retry_queue` - RetryQueue holding the urls of failed fetches until they are retried.
+/// * `robots_cache` - RobotsCache holding the robots.txt rules of the hosts being crawled.
+/// * `host_scheduler` - HostScheduler that spaces out the requests to each host.
+/// * `errors` - ErrorCounters counting the urls that could not be sent.
+/// * `paused` - Atomic flag telling it to hold back urls for now.
+/// * `shutdown` - Shutdown flag telling it to stop sending urls.
+/// * `config` - Settings of the crawl (sleep durations and urls taken from the frontier at once).
+pub fn url_enqueuer(mut uri_sink: futures::sync::mpsc::Sender<(hyper::Uri, url_meta::UrlMeta)>, mut robots_sink: futures::sync::mpsc::Sender<hyper::Uri>, urls_enqueued: sync::Arc<sync::atomic::AtomicUsize>, retries_enqueued: sync::Arc<sync::atomic::AtomicUsize>, urls_disallowed: sync::Arc<sync::atomic::AtomicUsize>, bloom_filter: sync::Arc<bloom_filter::ConcurrentBloomFilter>, canonicalizer: sync::Arc<canonical::Canonicalizer>, frontier: sync::Arc<sync::Mutex<frontier::Frontier>>, outstanding: sync::Arc<outstanding::Outstanding>, retry_queue: sync::Arc<sync::Mutex<retry::RetryQueue>>, robots_cache: sync::Arc<sync::Mutex<robots::RobotsCache>>, host_scheduler: sync::Arc<sync::Mutex<host_scheduler::HostScheduler<(hyper::Uri, url_meta::UrlMeta)>>>, errors: sync::Arc<crawl_error::ErrorCounters>, paused: sync::Arc<sync::atomic::AtomicBool>, shutdown: shutdown::Shutdown, config: sync::Arc<config::CrawlConfig>){
+    let sleep_duration_on_empty_reservoir=time::Duration::from_millis(config.sleep_millis_on_empty_reservoir);
+    let sleep_duration_on_full_channel=time::Duration::from_millis(config.sleep_millis_on_full_channel);
+    let scheduler_tick=time::Duration::from_millis(config.scheduler_tick_millis);
+
+    let mut urls=Vec::with_capacity(config.max_urls_per_iter);
+    let mut retry_urls=Vec::new();
+    let mut uris=Vec::with_capacity(config.max_urls_per_iter);
+    let mut robots_uris=Vec::new();
+    let mut ready_uris=Vec::new();
+    // Looks up the ip addresses of new hosts, without blocking, once requests turn out to be limited per ip.
+    let mut ip_resolver:Option<host_scheduler::IpResolver>=None;
+    // Urls waiting for the robots.txt of their host to be fetched.
+    let mut awaiting_robots:collections::VecDeque<Pending>=collections::VecDeque::new();
     loop {
-        // Grab up to MAX_URLS_PER_ITER urls from the reservoir.
-        {
-            let mut mutex_guard=match url_reservoir.lock() {
+        if shutdown.is_requested(){
+            break;
+        }
+        if paused.load(sync::atomic::Ordering::SeqCst){
+            thread::sleep(scheduler_tick);
+            continue;
+        }
+
+        // Check whether the scheduler can take more urls.
+        let (scheduled, limits_ips)={
+            let mutex_guard=match host_scheduler.lock() {
                 Ok(mutex_guard) => mutex_guard,
                 Err(e) => {eprintln!("Error (url_enqueuer): {:?}", e);break;},
             };
 
-            urls.clear();
-            for _ in 0..MAX_URLS_PER_ITER{
+            (mutex_guard.queued(), mutex_guard.limits_ips())
+        };
+        if limits_ips && ip_resolver.is_none(){
+            ip_resolver=Some(host_scheduler::IpResolver::new());
+        }
+
+        // Grab up to `max_urls_per_iter` urls from the frontier, unless too many are waiting already.
+        urls.clear();
+        if scheduled<config.max_scheduled_urls && awaiting_robots.len()+config.max_urls_per_iter<=config.max_urls_awaiting_robots {
+            let mut mutex_guard=match frontier.lock() {
+                Ok(mutex_guard) => mutex_guard,
+                Err(e) => {eprintln!("Error (url_enqueuer): {:?}", e);break;},
+            };
+
+            for _ in 0..config.max_urls_per_iter{
                 match mutex_guard.get_url(){
                     Some(url) => urls.push(url),
                     None => break,
@@ -46,49 +112,250 @@ pub fn url_enqueuer(mut uri_sink: futures::sync::mpsc::Sender<hyper::Uri>, urls_
             }
         };
 
-        // If no url was grabbed, continue.
-        if urls.is_empty(){
-            eprintln!("Error (url_enqueuer): {:?}", "reservoir is empty");
+        // Take the urls of failed fetches due to be tried again, which were sent before and thus skip the bloom filter,
+        // along with those held back until the robots.txt of their host can be fetched again.
+        retry_urls.clear();
+        let next_retry={
+            let mut mutex_guard=match retry_queue.lock() {
+                Ok(mutex_guard) => mutex_guard,
+                Err(e) => {eprintln!("Error (url_enqueuer): {:?}", e);break;},
+            };
+
+            let now=time::Instant::now();
+            while let Some(url_meta_sent)=mutex_guard.pop_due(now){
+                retry_urls.push(url_meta_sent);
+            }
+            mutex_guard.next_due()
+        };
+
+        // If no url was grabbed and none is waiting for its robots.txt or its host, continue.
+        if urls.is_empty() && retry_urls.is_empty() && awaiting_robots.is_empty() && scheduled==0{
+            eprintln!("Error (url_enqueuer): {:?}", "frontier is empty");
             thread::sleep(sleep_duration_on_empty_reservoir);
             continue;
         }
 
-        // Discard urls that have already been sent.
-        {
-            let mut mutex_guard=match bloom_filter.lock() {
+        // Canonicalize the urls, which those of older checkpoints (and retries of them) may not be, then discard those that have already been sent.
+        // Urls are only added to the bloom filter once robots.txt allows them, so that those held back until then can be given back to the frontier.
+        for url in urls.iter_mut().map(|&mut (ref mut url, _)| url).chain(retry_urls.iter_mut().map(|&mut (ref mut url, _, _)| url)) {
+            let original=mem::replace(url, String::new());
+            *url=canonicalizer.canonicalize(original);
+        }
+        urls.retain(|&(ref u, _)| !bloom_filter.contains(u.as_bytes()));
+
+        // Parse the urls, keeping those waiting for their robots.txt first in line.
+        uris.clear();
+        uris.extend(awaiting_robots.drain(..));
+        for (url, meta, sent) in urls.drain(..).map(|(url, meta)| (url, meta, false)).chain(retry_urls.drain(..)){
+            let uri=match url.parse::<hyper::Uri>() {
+                Ok(uri) => uri,
+                Err(e) => {
+                    errors.record("url_enqueuer", &CrawlError::InvalidUrl(e.to_string()));
+                    continue;
+                },
+            };
+
+            match robots::origin(&uri) {
+                Some(origin) => {
+                    outstanding.taken(uri.to_string(), &meta, sent);
+                    uris.push(Pending{url: url, origin: origin, uri: uri, meta: meta, sent: sent});
+                },
+                None => errors.record("url_enqueuer", &CrawlError::MissingOrigin),
+            }
+        }
+
+        // Check the urls against the robots.txt rules of their hosts, and find out their Crawl-delay.
+        let mut allowed_uris=Vec::with_capacity(uris.len());
+        let mut unreachable=Vec::new();
+        if !uris.is_empty(){
+            let mut mutex_guard=match robots_cache.lock() {
                 Ok(mutex_guard) => mutex_guard,
                 Err(e) => {eprintln!("Error (url_enqueuer): {:?}", e);break;},
             };
 
-            urls.retain(|u| !mutex_guard.contains_add(u.as_bytes()));
+            robots_uris.clear();
+            for pending in uris.drain(..){
+                let path=match pending.uri.query() {
+                    Some(query) => format!("{}?{}", pending.uri.path(), query),
+                    None => pending.uri.path().to_string(),
+                };
+
+                match mutex_guard.check(pending.origin.as_str(), path.as_str()) {
+                    robots::RobotsStatus::Allowed => {
+                        // Urls held back more than once (e.g. found again meanwhile) are sent once only.
+                        if pending.sent || !bloom_filter.contains_add(pending.url.as_bytes()) {
+                            outstanding.sent(pending.uri.to_string().as_str());
+                            let crawl_delay=mutex_guard.crawl_delay(pending.origin.as_str());
+                            allowed_uris.push((pending.uri, pending.meta, crawl_delay));
+                        }
+                    },
+                    robots::RobotsStatus::Disallowed => {
+                        outstanding.done(pending.uri.to_string().as_str());
+                        if pending.sent || !bloom_filter.contains_add(pending.url.as_bytes()) {
+                            urls_disallowed.fetch_add(1, sync::atomic::Ordering::Relaxed);
+                        }
+                    },
+                    robots::RobotsStatus::Fetch => {
+                        robots_uris.extend(robots::robots_uri(pending.origin.as_str()));
+                        awaiting_robots.push_back(pending);
+                    },
+                    robots::RobotsStatus::Pending => {
+                        awaiting_robots.push_back(pending);
+                    },
+                    robots::RobotsStatus::Unreachable(until) => {
+                        unreachable.push((pending, until));
+                    },
+                }
+            }
         }
 
-        // Send urls through the sink.
-        for url in urls.iter(){
-            let uri=match (*url).parse::<hyper::Uri>() {
-                Ok(uri) => uri,
-                Err(e) => {
-                    eprintln!("Error (url_enqueuer): {:?}", e);
+        // Hold back the urls whose robots.txt is unreachable until it is fetched again.
+        // Urls given back to the frontier or held in the retry queue are no longer outstanding once they are there.
+        let mut given_back=Vec::new();
+        let mut handed_over=Vec::new();
+        if !unreachable.is_empty(){
+            let mut mutex_guard=match retry_queue.lock() {
+                Ok(mutex_guard) => mutex_guard,
+                Err(e) => {eprintln!("Error (url_enqueuer): {:?}", e);break;},
+            };
+
+            for (pending, until) in unreachable.into_iter(){
+                handed_over.push(pending.uri.to_string());
+                let Pending{url, meta, sent, ..}=pending;
+                if mutex_guard.postpone(url.clone(), meta.clone(), until, sent) {
                     continue;
-                },
+                }
+                // Urls sent before cannot go back to the frontier, as the bloom filter would keep them from leaving it.
+                if sent {
+                    errors.record("url_enqueuer", &CrawlError::RobotsBacklog);
+                } else {
+                    given_back.push((url, meta));
+                }
+            }
+        }
+
+        // Do not hold back more urls than allowed, giving the oldest ones back to the frontier. Urls sent before stay, for the above reason.
+        if awaiting_robots.len()>config.max_urls_awaiting_robots{
+            let mut excess=awaiting_robots.len()-config.max_urls_awaiting_robots;
+            awaiting_robots.retain(|pending| {
+                if excess==0 || pending.sent {
+                    return true;
+                }
+                excess-=1;
+                handed_over.push(pending.uri.to_string());
+                given_back.push((pending.url.clone(), pending.meta.clone()));
+                false
+            });
+        }
+        if !given_back.is_empty(){
+            match frontier.lock() {
+                Ok(mut mutex_guard) => mutex_guard.add_urls(given_back),
+                Err(e) => {eprintln!("Error (url_enqueuer): {:?}", e);break;},
+            }
+        }
+        for uri in handed_over.drain(..){
+            outstanding.done(uri.as_str());
+        }
+
+        // Request the missing robots.txt files. If the channel is full, they are requested again once the fetch is considered timed out.
+        for robots_uri in robots_uris.drain(..){
+            match robots_sink.try_send(robots_uri) {
+                Ok(_) => {},
+                Err(e) => eprintln!("Error (url_enqueuer): {:?}", e),
+            }
+        }
+
+        // Hand the allowed urls to the scheduler and take those whose host allows a request now.
+        let next_wakeup={
+            let mut mutex_guard=match host_scheduler.lock() {
+                Ok(mutex_guard) => mutex_guard,
+                Err(e) => {eprintln!("Error (url_enqueuer): {:?}", e);break;},
             };
 
+            // If requests are limited per ip, the urls of new hosts are held back until their ip address has been looked up.
+            if let Some(ref mut ip_resolver)=ip_resolver {
+                for (host, ip) in ip_resolver.answers().into_iter(){
+                    mutex_guard.resolved(host.as_str(), ip);
+                }
+            }
+            for (uri, meta, crawl_delay) in allowed_uris.into_iter(){
+                match host_scheduler::host_key(&uri) {
+                    Some(host) => {
+                        let mut ip=None;
+                        if let Some(ref mut ip_resolver)=ip_resolver {
+                            if !mutex_guard.knows_host(host.as_str()) {
+                                match ip_resolver.lookup(host.as_str(), &uri) {
+                                    Some(answer) => ip=answer,
+                                    None => mutex_guard.hold(host.as_str()),
+                                }
+                            }
+                        }
+                        mutex_guard.schedule(host, ip, (uri, meta), crawl_delay);
+                    },
+                    None => {
+                        outstanding.done(uri.to_string().as_str());
+                        errors.record("url_enqueuer", &CrawlError::MissingHost);
+                    },
+                }
+            }
+
+            let now=time::Instant::now();
+            ready_uris.clear();
+            while let Some(host_uri)=mutex_guard.next_ready(now){
+                ready_uris.push(host_uri);
+            }
+            // Wake up for the next retry as well.
+            mutex_guard.next_wakeup().into_iter().chain(next_retry).min()
+        };
+
+        // Tell the retries of failed fetches apart, so that they are not counted as urls enqueued twice.
+        let retrying:Vec<bool>=if ready_uris.is_empty() {
+            Vec::new()
+        } else {
+            match retry_queue.lock() {
+                Ok(mutex_guard) => ready_uris.iter().map(|&(_, (ref uri, _))| mutex_guard.attempts(uri.to_string().as_str())>0).collect(),
+                Err(e) => {eprintln!("Error (url_enqueuer): {:?}", e);break;},
+            }
+        };
+
+        // Send urls through the sink, giving those that do not fit back to the scheduler.
+        let mut channel_full=false;
+        for ((host, uri), retrying) in ready_uris.drain(..).zip(retrying.into_iter()){
+            if channel_full{
+                match host_scheduler.lock() {
+                    Ok(mut mutex_guard) => mutex_guard.give_back(host, uri),
+                    Err(e) => eprintln!("Error (url_enqueuer): {:?}", e),
+                }
+                continue;
+            }
+
             match uri_sink.try_send(uri) {
                 Ok(_) => {
-                    urls_enqueued.fetch_add(1, sync::atomic::Ordering::Relaxed);
-
-                    if SLEEP_MILLIS_AFTER_SEND!=0{
-                        thread::sleep(sleep_duration_after_send);
-                    }
+                    let counter=if retrying {&retries_enqueued} else {&urls_enqueued};
+                    counter.fetch_add(1, sync::atomic::Ordering::Relaxed);
                 },
                 Err(e) => {
                     eprintln!("Error (url_enqueuer): {:?}", e);
-                    thread::sleep(sleep_duration_on_full_channel);
-                    continue;
+                    channel_full=true;
+                    match host_scheduler.lock() {
+                        Ok(mut mutex_guard) => mutex_guard.give_back(host, e.into_inner()),
+                        Err(e) => eprintln!("Error (url_enqueuer): {:?}", e),
+                    }
                 },
             }
         }
+
+        // Wait until the next host allows a request, but not longer than a tick, so that new urls keep flowing in.
+        if channel_full{
+            thread::sleep(sleep_duration_on_full_channel);
+        } else {
+            let now=time::Instant::now();
+            let until_wakeup=next_wakeup.map_or(scheduler_tick, |at| if at>now {at-now} else {time::Duration::from_millis(0)});
+            thread::sleep(cmp::min(until_wakeup, scheduler_tick));
+        }
     }
 
-    eprintln!("Url enqueuer terminated.");
-}
\ No newline at end of file
+    if config.verbose {
+        eprintln!("Url enqueuer terminated.");
+    }
+}
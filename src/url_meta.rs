@@ -0,0 +1,138 @@
+#![allow(dead_code)]
+
+use checkpoint;
+use std::cmp;
+use std::collections;
+use std::io;
+use std::sync;
+use std::time;
+
+/// Length beyond which urls read from a checkpoint are taken for corrupted data.
+pub const MAX_URL_LEN: usize = 64*1024;
+
+/// Names of the counters of the urls gotten at each depth, the last one
+/// counting those at that depth or deeper.
+pub const DEPTH_NAMES: [&str;17] = [
+    "depth_0", "depth_1", "depth_2", "depth_3", "depth_4", "depth_5", "depth_6", "depth_7",
+    "depth_8", "depth_9", "depth_10", "depth_11", "depth_12", "depth_13", "depth_14", "depth_15",
+    "depth_16_plus",
+];
+
+/// Returns the current time, in seconds since the unix epoch.
+pub fn now_secs() -> u32 {
+    time::SystemTime::now().duration_since(time::UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or(0)
+}
+
+/// What is known about an url on its way through the crawl, from the reservoir
+/// to the processors of its page. Kept small, as the reservoir holds one per url:
+/// the discovering page is shared among all the urls found in it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct UrlMeta {
+    /// Number of links followed from a seed to the url (redirects not counted).
+    pub depth: u16,
+    /// Url of the page the url was found in, None for seeds.
+    pub parent: Option<sync::Arc<str>>,
+    /// Time the url was found, in seconds since the unix epoch.
+    pub discovered_secs: u32,
+}
+
+impl UrlMeta {
+    /// Returns the UrlMeta of a seed, found now.
+    pub fn seed() -> UrlMeta {
+        UrlMeta{depth: 0, parent: None, discovered_secs: now_secs()}
+    }
+
+    /// Returns the UrlMeta of an url found in the page this UrlMeta belongs to.
+    ///
+    /// # Arguments
+    ///
+    /// * `parent` - Url of the page.
+    /// * `discovered_secs` - Time the url was found, in seconds since the unix epoch.
+    pub fn child(&self, parent: sync::Arc<str>, discovered_secs: u32) -> UrlMeta {
+        UrlMeta{depth: self.depth.saturating_add(1), parent: Some(parent), discovered_secs: discovered_secs}
+    }
+
+    /// Writes the UrlMeta to `w`, for checkpoints. A missing parent is written as an empty url.
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        checkpoint::write_u32(w, self.depth as u32)?;
+        checkpoint::write_u32(w, self.discovered_secs)?;
+        checkpoint::write_bytes(w, self.parent.as_ref().map_or(&b""[..], |parent| parent.as_bytes()))
+    }
+
+    /// Reads a UrlMeta written by `write_to` from `r`.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - Reader to read from.
+    /// * `parents` - Parents read so far, which urls found in the same page share again.
+    pub fn read_from<R: io::Read>(r: &mut R, parents: &mut collections::HashMap<String, sync::Arc<str>>) -> io::Result<UrlMeta> {
+        let depth=checkpoint::read_u32(r)?;
+        let discovered_secs=checkpoint::read_u32(r)?;
+        let parent=String::from_utf8(checkpoint::read_bytes(r, MAX_URL_LEN)?).map_err(|_| checkpoint::invalid_data("parent url is not utf8"))?;
+        let parent=if parent.is_empty() {
+            None
+        } else {
+            let shared=parents.get(&parent).cloned();
+            Some(shared.unwrap_or_else(|| {
+                let shared:sync::Arc<str>=sync::Arc::from(parent.as_str());
+                parents.insert(parent, shared.clone());
+                shared
+            }))
+        };
+        Ok(UrlMeta{depth: cmp::min(depth, u16::max_value() as u32) as u16, parent: parent, discovered_secs: discovered_secs})
+    }
+}
+
+/// Counters of the urls gotten at each depth, shared among the threads of the crawl.
+pub struct DepthCounters {
+    counters: Vec<sync::Arc<sync::atomic::AtomicUsize>>,
+}
+
+impl DepthCounters {
+    /// Creates and returns a new DepthCounters structure.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - Returns the value a counter (given its name) starts from, e.g. as saved by a checkpoint.
+    pub fn new<F: Fn(&str) -> usize>(start: F) -> DepthCounters {
+        DepthCounters{
+            counters: DEPTH_NAMES.iter().map(|name| sync::Arc::new(sync::atomic::AtomicUsize::new(start(name)))).collect(),
+        }
+    }
+
+    /// Counts an url gotten at `depth`.
+    pub fn count(&self, depth: u16) {
+        let i=::std::cmp::min(depth as usize, DEPTH_NAMES.len()-1);
+        self.counters[i].fetch_add(1, sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Returns the counters along with their names, to be saved in checkpoints.
+    pub fn named(&self) -> Vec<(&'static str, sync::Arc<sync::atomic::AtomicUsize>)> {
+        DEPTH_NAMES.iter().cloned().zip(self.counters.iter().cloned()).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_meta() {
+        let seed=UrlMeta::seed();
+        assert_eq!((seed.depth, seed.parent.clone()), (0, None));
+        let parent:sync::Arc<str>=sync::Arc::from("http://a.com/");
+        let child=seed.child(parent.clone(), 7).child(parent.clone(), 8);
+        assert_eq!(child, UrlMeta{depth: 2, parent: Some(parent), discovered_secs: 8});
+        assert_eq!(UrlMeta{depth: u16::max_value(), parent: None, discovered_secs: 0}.child(sync::Arc::from("x"), 0).depth, u16::max_value());
+
+        let counters=DepthCounters::new(|name| if name=="depth_1" {4} else {0});
+        counters.count(1);
+        counters.count(16);
+        counters.count(300);
+        let named=counters.named();
+        assert_eq!(named[1].1.load(sync::atomic::Ordering::Relaxed), 5);
+        assert_eq!(named[16].0, "depth_16_plus");
+        assert_eq!(named[16].1.load(sync::atomic::Ordering::Relaxed), 2);
+    }
+}
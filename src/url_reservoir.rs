@@ -1,15 +1,22 @@
 #![allow(dead_code)]
+use checkpoint;
+use url_meta;
 use rand;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use std::collections;
+use std::io;
+
+const RNG_SEED_LEN: usize = 8;
 
 /// Data structure designed to hold a large but finite amount of strings. Adding
 /// strings beyond capacity replaces random strings. The strings are intended to
-/// represent urls, hence the name.
+/// represent urls, hence the name, and carry a UrlMeta each.
 ///
-/// Retrieving data happens in random order.
-const RESERVOIR_SIZE: usize = 1024*1024;
+/// Retrieving data happens in random order. The strings are kept as boxed str,
+/// which makes up for part of the space their UrlMeta takes.
 pub struct UrlReservoir {
-    urls: Vec<String>,
+    urls: Vec<(Box<str>, url_meta::UrlMeta)>,
+    size: usize,
     rng: rand::StdRng,
 }
 
@@ -18,11 +25,13 @@ impl UrlReservoir {
     ///
     /// # Arguments
     ///
-    /// * `starting_urls` - strings the structure should contain right after creation.
-    pub fn new(starting_urls: Vec<String>, rng: rand::StdRng) -> UrlReservoir{
-        let mut urls=Vec::with_capacity(RESERVOIR_SIZE);
-        urls.extend(starting_urls.into_iter());
-        UrlReservoir{urls: urls, rng: rng}
+    /// * `starting_urls` - strings the structure should contain right after creation, as seeds.
+    /// * `size` - amount of strings the structure can hold before it starts replacing them.
+    pub fn new(starting_urls: Vec<String>, size: usize, rng: rand::StdRng) -> UrlReservoir{
+        let mut urls=Vec::with_capacity(size);
+        let meta=url_meta::UrlMeta::seed();
+        urls.extend(starting_urls.into_iter().map(|url| (url.into_boxed_str(), meta.clone())));
+        UrlReservoir{urls: urls, size: size, rng: rng}
     }
 
     /// Returns the ammount of strings contained within the UrlReservoir structure
@@ -35,7 +44,7 @@ impl UrlReservoir {
     /// structure before the strings it already contains start having to be removed.
     #[inline]
     fn available_space(&self) -> usize{
-        self.urls.capacity()-self.urls.len()
+        self.size.saturating_sub(self.urls.len())
     }
 
     /// Adds strings to the UrlReservoir structure, removing already contained
@@ -43,13 +52,14 @@ impl UrlReservoir {
     ///
     /// # Arguments
     ///
-    /// * `urls` - vector of strings to add to the UrlReservoir structure.
-    pub fn add_urls(&mut self, urls: Vec<String>){
+    /// * `urls` - vector of strings (with their UrlMeta) to add to the UrlReservoir structure.
+    pub fn add_urls(&mut self, urls: Vec<(String, url_meta::UrlMeta)>){
         let mut available_space=self.available_space();
         if available_space>=urls.len(){
-            self.urls.extend(urls.into_iter());
+            self.urls.extend(urls.into_iter().map(|(url, meta)| (url.into_boxed_str(), meta)));
         } else {
-            for url in urls.into_iter(){
+            for (url, meta) in urls.into_iter(){
+                let url=(url.into_boxed_str(), meta);
                 if available_space>0{
                     self.urls.push(url);
                     available_space-=1;
@@ -59,7 +69,7 @@ impl UrlReservoir {
                 }
             }
         }
-        assert!(self.urls.capacity()==RESERVOIR_SIZE, "UrlReservoir needs fixing: capacity has changed in add_urls");
+        assert!(self.urls.len()<=self.size, "UrlReservoir needs fixing: size was exceeded in add_urls");
     }
 
     /// Adds strings to the UrlReservoir structure, removing already contained
@@ -68,12 +78,13 @@ impl UrlReservoir {
     ///
     /// # Arguments
     ///
-    /// * `urls` - vector of strings to add to the UrlReservoir structure.
-    pub fn add_urls_popping(&mut self, urls: &mut Vec<String>){
+    /// * `urls` - vector of strings (with their UrlMeta) to add to the UrlReservoir structure.
+    pub fn add_urls_popping(&mut self, urls: &mut Vec<(String, url_meta::UrlMeta)>){
         loop {
             match urls.pop() {
-                Some(url) => {
-                    if self.urls.len()<self.urls.capacity(){
+                Some((url, meta)) => {
+                    let url=(url.into_boxed_str(), meta);
+                    if self.urls.len()<self.size{
                         self.urls.push(url);
                     } else{
                         let len=self.urls.len();
@@ -83,19 +94,79 @@ impl UrlReservoir {
                 None => break,
             }
         }
-        assert!(self.urls.capacity()==RESERVOIR_SIZE, "UrlReservoir needs fixing: capacity has changed in add_urls_popping");
+        assert!(self.urls.len()<=self.size, "UrlReservoir needs fixing: size was exceeded in add_urls_popping");
     }
 
-    /// Retrieves a random one of the contained strings, or None if the UrlReservoir
-    /// structure is empty.
-    pub fn get_url(&mut self) -> Option<String>{
+    /// Keeps only the strings for which `f` returns true.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - Returns whether a string is to be kept.
+    pub fn retain<F: FnMut(&str) -> bool>(&mut self, mut f: F){
+        self.urls.retain(|&(ref url, _)| f(url));
+    }
+
+    /// Retrieves a random one of the contained strings along with its UrlMeta,
+    /// or None if the UrlReservoir structure is empty.
+    pub fn get_url(&mut self) -> Option<(String, url_meta::UrlMeta)>{
         let len=self.urls.len();
         if len==0{
             None
         } else{
-            Some(self.urls.swap_remove((self.rng.next_u64()%(len as u64)) as usize))
+            let (url, meta)=self.urls.swap_remove((self.rng.next_u64()%(len as u64)) as usize);
+            Some((url.into_string(), meta))
         }
     }
+
+    /// Writes the contained strings and the state of the random number generator
+    /// to `w`. To be able to save the state, the generator is reseeded with a seed
+    /// drawn from itself, which is what gets written.
+    ///
+    /// # Arguments
+    ///
+    /// * `w` - Writer to write to.
+    pub fn write_to<W: io::Write>(&mut self, w: &mut W) -> io::Result<()>{
+        let seed:Vec<usize>=(0..RNG_SEED_LEN).map(|_| self.rng.next_u64() as usize).collect();
+        self.rng.reseed(&seed[..]);
+        for &n in seed.iter(){
+            checkpoint::write_u64(w, n as u64)?;
+        }
+
+        checkpoint::write_u64(w, self.urls.len() as u64)?;
+        for &(ref url, ref meta) in self.urls.iter(){
+            checkpoint::write_bytes(w, url.as_bytes())?;
+            meta.write_to(w)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a UrlReservoir structure written by `write_to` from `r`. If it
+    /// contains more strings than `size`, the extra ones are dropped. Urls found
+    /// in the same page share their parent again.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - Reader to read from.
+    /// * `size` - amount of strings the structure can hold before it starts replacing them.
+    pub fn read_from<R: io::Read>(r: &mut R, size: usize) -> io::Result<UrlReservoir>{
+        let mut seed=Vec::with_capacity(RNG_SEED_LEN);
+        for _ in 0..RNG_SEED_LEN{
+            seed.push(checkpoint::read_u64(r)? as usize);
+        }
+
+        let len=checkpoint::read_u64(r)?;
+        let mut urls=Vec::with_capacity(size);
+        let mut parents=collections::HashMap::new();
+        for _ in 0..len{
+            let url=String::from_utf8(checkpoint::read_bytes(r, url_meta::MAX_URL_LEN)?).map_err(|_| checkpoint::invalid_data("url is not utf8"))?;
+            let meta=url_meta::UrlMeta::read_from(r, &mut parents)?;
+            if urls.len()<size{
+                urls.push((url.into_boxed_str(), meta));
+            }
+        }
+
+        Ok(UrlReservoir{urls: urls, size: size, rng: rand::StdRng::from_seed(&seed[..])})
+    }
 }
 
 
@@ -103,36 +174,41 @@ impl UrlReservoir {
 mod tests {
     use super::*;
     use rand;
+    use std::sync;
+
+    const RESERVOIR_SIZE: usize = 1024*1024;
 
     #[test]
     fn test_url_reservoir() {
-        let mut url_reservoir=UrlReservoir::new(vec!["hello".into()], rand::StdRng::new().unwrap());
+        let mut url_reservoir=UrlReservoir::new(vec!["hello".into()], RESERVOIR_SIZE, rand::StdRng::new().unwrap());
         assert_eq!(url_reservoir.available_space(), RESERVOIR_SIZE-1);
-        assert_eq!(url_reservoir.get_url(), Some("hello".into()));
+        assert_eq!(url_reservoir.get_url().map(|(url, meta)| (url, meta.depth, meta.parent)), Some(("hello".into(), 0, None)));
         assert_eq!(url_reservoir.available_space(), RESERVOIR_SIZE);
 
-        url_reservoir.add_urls(vec!["1".into(), "1".into()]);
+        let parent:sync::Arc<str>=sync::Arc::from("hello");
+        let meta=url_meta::UrlMeta::seed().child(parent.clone(), 5);
+        url_reservoir.add_urls(vec![("1".into(), meta.clone()), ("1".into(), meta.clone())]);
         assert_eq!(url_reservoir.available_space(), RESERVOIR_SIZE-2);
-        assert_eq!(url_reservoir.get_url(), Some("1".into()));
+        assert_eq!(url_reservoir.get_url(), Some(("1".into(), meta.clone())));
         assert_eq!(url_reservoir.available_space(), RESERVOIR_SIZE-1);
-        assert_eq!(url_reservoir.get_url(), Some("1".into()));
+        assert_eq!(url_reservoir.get_url(), Some(("1".into(), meta.clone())));
         assert_eq!(url_reservoir.available_space(), RESERVOIR_SIZE);
         assert_eq!(url_reservoir.get_url(), None);
         assert_eq!(url_reservoir.available_space(), RESERVOIR_SIZE);
 
-        let mut v:Vec<String>=vec!["2".into(), "2".into(), "2".into()];
+        let mut v:Vec<(String, url_meta::UrlMeta)>=vec![("2".into(), meta.clone()), ("2".into(), meta.clone()), ("2".into(), meta.clone())];
         url_reservoir.add_urls_popping(&mut v);
         assert_eq!(v.len(), 0);
         assert_eq!(url_reservoir.available_space(), RESERVOIR_SIZE-3);
-        assert_eq!(url_reservoir.get_url(), Some("2".into()));
-        assert_eq!(url_reservoir.get_url(), Some("2".into()));
-        assert_eq!(url_reservoir.get_url(), Some("2".into()));
+        assert_eq!(url_reservoir.get_url().map(|(url, _)| url), Some("2".into()));
+        assert_eq!(url_reservoir.get_url().map(|(url, _)| url), Some("2".into()));
+        assert_eq!(url_reservoir.get_url().map(|(url, _)| url), Some("2".into()));
         assert_eq!(url_reservoir.get_url(), None);
         assert_eq!(url_reservoir.get_url(), None);
         assert_eq!(url_reservoir.get_url(), None);
         assert_eq!(url_reservoir.get_url(), None);
 
-        let v:Vec<String>=(0..(RESERVOIR_SIZE+5)).map(|_| "hello".into()).collect();
+        let v:Vec<(String, url_meta::UrlMeta)>=(0..(RESERVOIR_SIZE+5)).map(|_| ("hello".into(), meta.clone())).collect();
         url_reservoir.add_urls(v);
         assert_eq!(url_reservoir.available_space(), 0);
     }